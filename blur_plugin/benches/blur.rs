@@ -0,0 +1,80 @@
+//! Comparison of naive reference blur implementations with optimized filters
+//!
+//! Run with `cargo bench -p blur_plugin --features bench`
+use std::{hint::black_box, thread};
+
+use blur::{
+    filters::{self, FilterOptions, Window},
+    reference,
+    task::Task,
+};
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use plugin_errors::PluginError;
+
+const WIDTH: usize = 320;
+const HEIGHT: usize = 240;
+
+type Reference = fn(usize, usize, &[u8], &mut [u8], usize, &Task) -> Result<(), PluginError>;
+type Filter =
+    fn(usize, usize, &[u8], &mut [u8], Window, &FilterOptions, &Task) -> Result<(), PluginError>;
+
+fn test_image() -> Vec<u8> {
+    (0..WIDTH * HEIGHT * 4)
+        .map(|i| (i * 31 % 251) as u8)
+        .collect()
+}
+
+fn bench_filters(c: &mut Criterion, group_name: &str, reference: Reference, optimized: Filter) {
+    let src = test_image();
+    let mut dst = vec![0u8; src.len()];
+    let task = Task::new(None, HEIGHT as u64);
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+
+    let mut group = c.benchmark_group(group_name);
+    group.sample_size(10);
+    for radius in [2, 8, 32] {
+        group.bench_with_input(
+            BenchmarkId::new("reference", radius),
+            &radius,
+            |b, &radius| {
+                b.iter(|| reference(WIDTH, HEIGHT, black_box(&src), &mut dst, radius, &task))
+            },
+        );
+        for (name, threads) in [("optimized", 1), ("optimized_parallel", threads)] {
+            let options = FilterOptions {
+                threads,
+                ..FilterOptions::default()
+            };
+            group.bench_with_input(BenchmarkId::new(name, radius), &radius, |b, &radius| {
+                b.iter(|| {
+                    optimized(
+                        WIDTH,
+                        HEIGHT,
+                        black_box(&src),
+                        &mut dst,
+                        Window::square(radius),
+                        &options,
+                        &task,
+                    )
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+fn box_blur(c: &mut Criterion) {
+    bench_filters(c, "box_blur", reference::apply_box_blur, filters::box_blur);
+}
+
+fn gaussian_blur(c: &mut Criterion) {
+    bench_filters(
+        c,
+        "gaussian_blur",
+        reference::apply_weighted_blur,
+        filters::gaussian_blur,
+    );
+}
+
+criterion_group!(benches, box_blur, gaussian_blur);
+criterion_main!(benches);
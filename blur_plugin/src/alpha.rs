@@ -0,0 +1,59 @@
+//! Conversion of RGBA images between straight and premultiplied alpha
+use crate::sample::Sample;
+
+/// Multiply color channels of every RGBA pixel by its alpha
+pub(crate) fn premultiply<T: Sample>(pixels: &mut [T]) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        let alpha = pixel[3];
+        for c in &mut pixel[..3] {
+            *c = c.premultiply(alpha);
+        }
+    }
+}
+
+/// Divide color channels of every RGBA pixel by its alpha
+///
+/// Fully transparent pixels get black color
+pub(crate) fn unpremultiply<T: Sample>(pixels: &mut [T]) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        let alpha = pixel[3];
+        for c in &mut pixel[..3] {
+            *c = c.unpremultiply(alpha);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_premultiply() {
+        let mut pixels: [u8; 12] = [200, 100, 0, 255, 200, 100, 0, 128, 200, 100, 0, 0];
+        premultiply(&mut pixels);
+        assert_eq!(pixels, [200, 100, 0, 255, 100, 50, 0, 128, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_unpremultiply() {
+        let mut pixels: [u8; 16] = [
+            200, 100, 0, 255, 100, 50, 0, 128, 30, 20, 10, 0, 90, 0, 0, 45,
+        ];
+        unpremultiply(&mut pixels);
+        assert_eq!(
+            pixels,
+            [
+                200, 100, 0, 255, 199, 100, 0, 128, 0, 0, 0, 0, 255, 0, 0, 45
+            ]
+        );
+    }
+
+    #[test]
+    fn test_round_trip_opaque() {
+        let original: Vec<u8> = (0..=255).flat_map(|v| [v, 255 - v, v / 2, 255]).collect();
+        let mut pixels = original.clone();
+        premultiply(&mut pixels);
+        unpremultiply(&mut pixels);
+        assert_eq!(pixels, original);
+    }
+}
@@ -0,0 +1,374 @@
+//! Blur filters averaging samples along line segments
+//!
+//! Unlike separable filters of `filters` module direction of averaging may differ for every pixel,
+//! so pixels are sampled with bilinear interpolation and every pixel costs O(number of samples)
+use plugin_errors::PluginError;
+
+use crate::filters::{Edge, FilterOptions, process_bands};
+use crate::sample::Sample;
+use crate::task::Task;
+
+/// Maximum number of samples per pixel of radial blur, limits cost of pixels far from center
+const MAX_RADIAL_SAMPLES: usize = 64;
+
+/// Motion blur averaging pixels of segment of `length` pixels centered at every pixel
+///
+/// Segment direction is `angle` degrees counterclockwise from horizontal.
+/// Coordinates outside of image are clamped unless `options.edge` is set.
+/// Length is limited by image diagonal, longer segments would only add samples outside of image
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn motion_blur<T: Sample>(
+    width: usize,
+    height: usize,
+    src: &[T],
+    dst: &mut [T],
+    angle: f32,
+    length: f32,
+    options: &FilterOptions,
+    task: &Task,
+) -> Result<(), PluginError> {
+    let length = length.min((width as f32).hypot(height as f32));
+    let samples = length.ceil() as usize + 1;
+    let (sin, cos) = angle.to_radians().sin_cos();
+    // Image y axis points down, so counterclockwise angle decreases y
+    let offsets: Vec<(f32, f32)> = (0..samples)
+        .map(|i| {
+            let t = match samples {
+                1 => 0.0,
+                _ => length * (i as f32 / (samples - 1) as f32 - 0.5),
+            };
+            (t * cos, -t * sin)
+        })
+        .collect();
+
+    let sampler = Sampler::new(src, width, height, options);
+    process_bands(width, height, dst, options.threads, |rows, band| {
+        for y in rows.clone() {
+            task.check()?;
+            for x in 0..width {
+                let points = offsets
+                    .iter()
+                    .map(|(dx, dy)| (x as f32 + dx, y as f32 + dy));
+                sampler.average(
+                    x,
+                    y,
+                    points,
+                    &mut band[((y - rows.start) * width + x) * 4..],
+                );
+            }
+            task.row_done();
+        }
+        Ok(())
+    })
+}
+
+/// Radial (zoom) blur averaging pixels of segment from every pixel towards `center`
+///
+/// Segment covers `strength` part of distance to `center`, so pixels far from center are blurred more.
+/// `center` is given in pixels, coordinates outside of image are clamped unless `options.edge` is set
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn radial_blur<T: Sample>(
+    width: usize,
+    height: usize,
+    src: &[T],
+    dst: &mut [T],
+    center: (f32, f32),
+    strength: f32,
+    options: &FilterOptions,
+    task: &Task,
+) -> Result<(), PluginError> {
+    let sampler = Sampler::new(src, width, height, options);
+    process_bands(width, height, dst, options.threads, |rows, band| {
+        for y in rows.clone() {
+            task.check()?;
+            for x in 0..width {
+                let (dx, dy) = (x as f32 - center.0, y as f32 - center.1);
+                let length = strength * dx.hypot(dy);
+                let samples = length.ceil().min(MAX_RADIAL_SAMPLES as f32) as usize + 1;
+                let samples = samples.min(MAX_RADIAL_SAMPLES);
+                let points = (0..samples).map(|i| {
+                    let scale = match samples {
+                        1 => 1.0,
+                        _ => 1.0 - strength * i as f32 / (samples - 1) as f32,
+                    };
+                    (center.0 + dx * scale, center.1 + dy * scale)
+                });
+                sampler.average(
+                    x,
+                    y,
+                    points,
+                    &mut band[((y - rows.start) * width + x) * 4..],
+                );
+            }
+            task.row_done();
+        }
+        Ok(())
+    })
+}
+
+/// Bilinear sampling of RGBA image at fractional coordinates
+struct Sampler<'a, T> {
+    src: &'a [T],
+    width: usize,
+    height: usize,
+    channels: usize,
+    edge: Edge,
+}
+
+impl<'a, T: Sample> Sampler<'a, T> {
+    fn new(src: &'a [T], width: usize, height: usize, options: &FilterOptions) -> Self {
+        Sampler {
+            src,
+            width,
+            height,
+            channels: options.channels(),
+            edge: options.edge.unwrap_or(Edge::Clamp),
+        }
+    }
+
+    /// Write average of samples at `points` to blurred channels of `out` and copy other channels of pixel `(x, y)`
+    fn average(&self, x: usize, y: usize, points: impl Iterator<Item = (f32, f32)>, out: &mut [T]) {
+        let src_idx = (y * self.width + x) * 4;
+        out[..4].copy_from_slice(&self.src[src_idx..src_idx + 4]);
+
+        let mut acc = [0f32; 4];
+        let mut weights = 0.0;
+        for (px, py) in points {
+            let (x0, y0) = (px.floor(), py.floor());
+            let (fx, fy) = (px - x0, py - y0);
+            // Coordinates far outside of image saturate, so neighbors are computed without overflow
+            let (x0, y0) = (x0 as isize, y0 as isize);
+            let (x1, y1) = (x0.saturating_add(1), y0.saturating_add(1));
+
+            for (sx, sy, weight) in [
+                (x0, y0, (1.0 - fx) * (1.0 - fy)),
+                (x1, y0, fx * (1.0 - fy)),
+                (x0, y1, (1.0 - fx) * fy),
+                (x1, y1, fx * fy),
+            ] {
+                if weight == 0.0 {
+                    continue;
+                }
+                let pixel = match (
+                    self.edge.source(sx, self.width),
+                    self.edge.source(sy, self.height),
+                ) {
+                    (Some(sx), Some(sy)) => {
+                        let idx = (sy * self.width + sx) * 4;
+                        [
+                            self.src[idx],
+                            self.src[idx + 1],
+                            self.src[idx + 2],
+                            self.src[idx + 3],
+                        ]
+                    }
+                    _ if self.edge == Edge::Shrink => continue,
+                    _ => self.edge.fill(),
+                };
+                for (acc, value) in acc.iter_mut().zip(pixel) {
+                    *acc += value.to_f32() * weight;
+                }
+                weights += weight;
+            }
+        }
+
+        if weights > 0.0 {
+            for c in 0..self.channels {
+                out[c] = T::from_f32(acc[c] / weights);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filters::{Window, box_blur};
+    use crate::test_util::{max_difference, options};
+
+    fn gradient_image(width: usize, height: usize) -> Vec<u8> {
+        (0..height)
+            .flat_map(|y| (0..width).flat_map(move |x| [(x * 20) as u8, (y * 20) as u8, 7, 255]))
+            .collect()
+    }
+
+    #[test]
+    fn test_motion_blur_matches_one_dimensional_box_blur() {
+        let (width, height) = (9, 6);
+        let src = gradient_image(width, height);
+        let task = Task::new(None, height as u64);
+
+        for (angle, window) in [
+            (
+                0.0,
+                Window {
+                    radius_y: 0,
+                    ..Window::square(2)
+                },
+            ),
+            (
+                90.0,
+                Window {
+                    radius_x: 0,
+                    ..Window::square(2)
+                },
+            ),
+        ] {
+            for edge in [Edge::Clamp, Edge::Wrap, Edge::Shrink] {
+                let mut expected = vec![0; src.len()];
+                box_blur(
+                    width,
+                    height,
+                    &src,
+                    &mut expected,
+                    window,
+                    &options(2, Some(edge)),
+                    &task,
+                )
+                .unwrap();
+                let mut actual = vec![0; src.len()];
+                motion_blur(
+                    width,
+                    height,
+                    &src,
+                    &mut actual,
+                    angle,
+                    4.0,
+                    &options(2, Some(edge)),
+                    &task,
+                )
+                .unwrap();
+
+                assert!(
+                    max_difference(&actual, &expected) <= 1,
+                    "angle {angle} {edge:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_motion_blur_zero_length_keeps_image() {
+        let src = gradient_image(5, 4);
+        let task = Task::new(None, 4);
+        let mut dst = vec![0; src.len()];
+        motion_blur(
+            5,
+            4,
+            &src,
+            &mut dst,
+            30.0,
+            0.0,
+            &options(2, Some(Edge::Clamp)),
+            &task,
+        )
+        .unwrap();
+        assert_eq!(dst, src);
+    }
+
+    #[test]
+    fn test_motion_blur_diagonal() {
+        // Single white pixel in the middle of black image is spread along diagonal
+        let (width, height) = (5, 5);
+        let mut src = vec![0u8; width * height * 4];
+        src[(2 * width + 2) * 4] = 255;
+        let task = Task::new(None, height as u64);
+        let mut dst = vec![0; src.len()];
+        let length = 2.0 * 2f32.sqrt();
+        motion_blur(
+            width,
+            height,
+            &src,
+            &mut dst,
+            45.0,
+            length,
+            &options(2, Some(Edge::Clamp)),
+            &task,
+        )
+        .unwrap();
+
+        let red = |x: usize, y: usize| dst[(y * width + x) * 4];
+        // 45 degrees counterclockwise goes up and to the right
+        assert!(red(1, 3) > 0 && red(3, 1) > 0);
+        assert_eq!(red(1, 1), 0);
+        assert_eq!(red(3, 3), 0);
+        assert!(red(2, 2) > 0);
+    }
+
+    #[test]
+    fn test_radial_blur_keeps_center() {
+        let (width, height) = (7, 7);
+        let src = gradient_image(width, height);
+        let task = Task::new(None, height as u64);
+        let mut dst = vec![0; src.len()];
+        radial_blur(
+            width,
+            height,
+            &src,
+            &mut dst,
+            (3.0, 3.0),
+            0.5,
+            &options(2, Some(Edge::Clamp)),
+            &task,
+        )
+        .unwrap();
+
+        let center = (3 * width + 3) * 4;
+        assert_eq!(dst[center..center + 4], src[center..center + 4]);
+        // Pixels are averaged towards center, so corners become closer to center
+        assert!(dst[0] > src[0] && dst[1] > src[1]);
+        let last = (width * height - 1) * 4;
+        assert!(dst[last] < src[last] && dst[last + 1] < src[last + 1]);
+        // Alpha is copied
+        assert!(dst.chunks(4).all(|pixel| pixel[3] == 255));
+    }
+
+    #[test]
+    fn test_radial_blur_zero_strength_keeps_image() {
+        let src = gradient_image(6, 5);
+        let task = Task::new(None, 5);
+        let mut dst = vec![0; src.len()];
+        radial_blur(
+            6,
+            5,
+            &src,
+            &mut dst,
+            (0.0, 4.0),
+            0.0,
+            &options(2, Some(Edge::Clamp)),
+            &task,
+        )
+        .unwrap();
+        assert_eq!(dst, src);
+    }
+
+    #[test]
+    fn test_huge_length_and_center() {
+        let (width, height) = (6, 5);
+        let src = gradient_image(width, height);
+        let task = Task::new(None, height as u64);
+
+        // Segment longer than diagonal of image gives the same result as diagonal one
+        let mut expected = vec![0; src.len()];
+        let diagonal = (width as f32).hypot(height as f32);
+        let options = options(2, Some(Edge::Wrap));
+        motion_blur(
+            width,
+            height,
+            &src,
+            &mut expected,
+            30.0,
+            diagonal,
+            &options,
+            &task,
+        )
+        .unwrap();
+        let mut dst = vec![0; src.len()];
+        motion_blur(width, height, &src, &mut dst, 30.0, 1e20, &options, &task).unwrap();
+        assert_eq!(dst, expected);
+
+        for center in [(1e30, -1e30), (f32::MAX, f32::MAX)] {
+            radial_blur(width, height, &src, &mut dst, center, 1.0, &options, &task).unwrap();
+        }
+    }
+}
@@ -0,0 +1,500 @@
+//! Edge-preserving smoothing filters: median and bilateral
+//!
+//! Both filters remove noise but keep sharp borders between areas of different color,
+//! which makes them suitable for denoising of scanned documents
+use plugin_errors::PluginError;
+
+use crate::filters::{Edge, FilterOptions, Rows, Window, process_bands};
+use crate::sample::Sample;
+use crate::task::Task;
+
+/// Median filter replacing every channel with median of window values
+///
+/// Uses sliding histograms of 8-bit values, so every pixel costs O(radius_y) to update histograms
+/// and O(256) to find median. 16-bit and floating point values are kept in sorted lists instead,
+/// which costs O(radius_x * radius_y) per updated value. If window contains even number of pixels,
+/// lower median is used. Coordinates outside of image are clamped unless `options.edge` is set
+pub(crate) fn median_blur<T: Sample>(
+    width: usize,
+    height: usize,
+    src: &[T],
+    dst: &mut [T],
+    window: Window,
+    options: &FilterOptions,
+    task: &Task,
+) -> Result<(), PluginError> {
+    let Window {
+        radius_x, radius_y, ..
+    } = window;
+    let channels = options.channels();
+    let edge = options.edge.unwrap_or(Edge::Clamp);
+    let rows = Rows::new(src, width, height, radius_y, edge);
+    let columns = Columns::new(width, radius_x, edge);
+
+    process_bands(width, height, dst, options.threads, |band_rows, band| {
+        let mut values = WindowValues::new(channels);
+        let mut window_rows = Vec::with_capacity(2 * radius_y + 1);
+
+        for y in band_rows.clone() {
+            task.check()?;
+
+            window_rows.clear();
+            window_rows.extend((y..=y + 2 * radius_y).filter_map(|v| rows.get(v)));
+
+            // Window values cover extended columns `x..=x + 2 * radius_x` for output pixel `x`
+            values.clear();
+            for p in 0..2 * radius_x + 1 {
+                values.update(&window_rows, &columns, p, true);
+            }
+
+            for x in 0..width {
+                let src_idx = (y * width + x) * 4;
+                let out_idx = ((y - band_rows.start) * width + x) * 4;
+                band[out_idx..out_idx + 4].copy_from_slice(&src[src_idx..src_idx + 4]);
+                for c in 0..channels {
+                    band[out_idx + c] = values.median(c);
+                }
+
+                if x + 1 < width {
+                    values.update(&window_rows, &columns, x + 2 * radius_x + 1, true);
+                    values.update(&window_rows, &columns, x, false);
+                }
+            }
+
+            task.row_done();
+        }
+
+        Ok(())
+    })
+}
+
+/// Bilateral filter weighting window pixels by distance and by color difference
+///
+/// Spatial weights are Gaussian with `window.sigma_x` and `window.sigma_y`,
+/// range weights are Gaussian of Euclidean distance of RGB colors with `sigma_range` given in 8-bit units.
+/// Every pixel costs O(radius_x * radius_y).
+/// Coordinates outside of image are clamped unless `options.edge` is set
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn bilateral_blur<T: Sample>(
+    width: usize,
+    height: usize,
+    src: &[T],
+    dst: &mut [T],
+    window: Window,
+    sigma_range: f32,
+    options: &FilterOptions,
+    task: &Task,
+) -> Result<(), PluginError> {
+    let Window {
+        radius_x, radius_y, ..
+    } = window;
+    let channels = options.channels();
+    let edge = options.edge.unwrap_or(Edge::Clamp);
+    let rows = Rows::new(src, width, height, radius_y, edge);
+    let columns = Columns::new(width, radius_x, edge);
+
+    let exponent = |d: isize, sigma: f32| match d {
+        0 => 0.0,
+        d => (d * d) as f32 / (2.0 * sigma * sigma),
+    };
+    let spatial: Vec<f32> = (-(radius_y as isize)..=radius_y as isize)
+        .flat_map(|dy| {
+            (-(radius_x as isize)..=radius_x as isize)
+                .map(move |dx| (-exponent(dx, window.sigma_x) - exponent(dy, window.sigma_y)).exp())
+        })
+        .collect();
+    let range_weight = |d2: f32| (-d2 / (2.0 * sigma_range * sigma_range)).exp();
+    // Range weight of every possible squared RGB distance of 8-bit colors
+    let range: Option<Vec<f32>> = T::LEVELS.map(|levels| {
+        let max_distance = 3 * (levels - 1) * (levels - 1);
+        (0..=max_distance)
+            .map(|d2| range_weight(d2 as f32))
+            .collect()
+    });
+
+    process_bands(width, height, dst, options.threads, |band_rows, band| {
+        for y in band_rows.clone() {
+            task.check()?;
+
+            for x in 0..width {
+                let src_idx = (y * width + x) * 4;
+                let center = &src[src_idx..src_idx + 4];
+
+                let mut acc = [0f32; 4];
+                let mut weights = 0.0;
+                for (ky, v) in (y..=y + 2 * radius_y).enumerate() {
+                    let Some(row) = rows.get(v) else {
+                        continue;
+                    };
+                    for kx in 0..2 * radius_x + 1 {
+                        let Some(pixel) = columns.pixel(row, x + kx) else {
+                            continue;
+                        };
+                        // Squared distance in 8-bit units, exact integer for 8-bit colors
+                        let distance: f32 = (0..3)
+                            .map(|c| ((pixel[c].to_f32() - center[c].to_f32()) / T::SCALE).powi(2))
+                            .sum();
+                        let range_weight = match &range {
+                            Some(range) => range[distance as usize],
+                            None => range_weight(distance),
+                        };
+                        let weight = spatial[ky * (2 * radius_x + 1) + kx] * range_weight;
+                        for (acc, value) in acc.iter_mut().zip(pixel) {
+                            *acc += value.to_f32() * weight;
+                        }
+                        weights += weight;
+                    }
+                }
+
+                let out_idx = ((y - band_rows.start) * width + x) * 4;
+                band[out_idx..out_idx + 4].copy_from_slice(center);
+                for c in 0..channels {
+                    band[out_idx + c] = T::from_f32(acc[c] / weights);
+                }
+            }
+
+            task.row_done();
+        }
+
+        Ok(())
+    })
+}
+
+/// Columns of image row extended by `radius` pixels on both sides according to edge mode
+struct Columns<T> {
+    sources: Vec<Option<usize>>,
+    edge: Edge,
+    fill: [T; 4],
+}
+
+impl<T: Sample> Columns<T> {
+    fn new(width: usize, radius: usize, edge: Edge) -> Self {
+        Columns {
+            sources: (0..width + 2 * radius)
+                .map(|p| edge.source(p as isize - radius as isize, width))
+                .collect(),
+            edge,
+            fill: edge.fill(),
+        }
+    }
+
+    /// Pixel at extended column `p` of `row`, `None` if pixel is ignored
+    fn pixel<'a>(&'a self, row: &'a [T], p: usize) -> Option<&'a [T]> {
+        match self.sources[p] {
+            Some(x) => Some(&row[x * 4..(x + 1) * 4]),
+            None if self.edge == Edge::Shrink => None,
+            None => Some(&self.fill),
+        }
+    }
+}
+
+/// Values of every blurred channel in filter window
+enum Values<T> {
+    /// Histograms of 8-bit values
+    Histograms(Vec<Vec<u32>>),
+    /// Sorted lists of other values
+    Sorted(Vec<Vec<T>>),
+}
+
+/// Values of every blurred channel in sliding filter window
+struct WindowValues<T> {
+    values: Values<T>,
+    count: u32,
+}
+
+impl<T: Sample> WindowValues<T> {
+    fn new(channels: usize) -> Self {
+        let values = match T::LEVELS {
+            Some(levels) => Values::Histograms(vec![vec![0; levels]; channels]),
+            None => Values::Sorted(vec![Vec::new(); channels]),
+        };
+        WindowValues { values, count: 0 }
+    }
+
+    fn clear(&mut self) {
+        match &mut self.values {
+            Values::Histograms(histograms) => histograms.iter_mut().for_each(|bins| bins.fill(0)),
+            Values::Sorted(lists) => lists.iter_mut().for_each(Vec::clear),
+        }
+        self.count = 0;
+    }
+
+    /// Add or remove pixels of extended column `p` of all `rows`
+    fn update(&mut self, rows: &[&[T]], columns: &Columns<T>, p: usize, add: bool) {
+        for row in rows {
+            let Some(pixel) = columns.pixel(row, p) else {
+                continue;
+            };
+            match &mut self.values {
+                Values::Histograms(histograms) => {
+                    for (bins, value) in histograms.iter_mut().zip(pixel) {
+                        let bin = value.to_f32() as usize;
+                        match add {
+                            true => bins[bin] += 1,
+                            false => bins[bin] -= 1,
+                        }
+                    }
+                }
+                Values::Sorted(lists) => {
+                    for (list, value) in lists.iter_mut().zip(pixel) {
+                        match (add, list.binary_search_by(|v| v.total_cmp(value))) {
+                            (true, Ok(idx) | Err(idx)) => list.insert(idx, *value),
+                            (false, Ok(idx)) => {
+                                list.remove(idx);
+                            }
+                            (false, Err(_)) => {}
+                        }
+                    }
+                }
+            }
+            match add {
+                true => self.count += 1,
+                false => self.count -= 1,
+            }
+        }
+    }
+
+    /// Lower median of channel `c`
+    fn median(&self, c: usize) -> T {
+        let mut remaining = self.count.saturating_sub(1) / 2;
+        match &self.values {
+            Values::Histograms(histograms) => {
+                for (value, count) in histograms[c].iter().enumerate() {
+                    if *count > remaining {
+                        return T::from_f32(value as f32);
+                    }
+                    remaining -= count;
+                }
+                T::from_f32(f32::MAX)
+            }
+            Values::Sorted(lists) => lists[c]
+                .get(remaining as usize)
+                .copied()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{noise_image, options};
+
+    /// Left half black, right half white
+    fn step_image(width: usize, height: usize) -> Vec<u8> {
+        (0..height)
+            .flat_map(|_| (0..width).flat_map(move |x| [if x < width / 2 { 0 } else { 255 }; 4]))
+            .collect()
+    }
+
+    /// Median of window values found by sorting
+    fn naive_median(width: usize, height: usize, src: &[u8], radius: usize, edge: Edge) -> Vec<u8> {
+        let radius = radius as isize;
+        let mut dst = src.to_vec();
+        for y in 0..height {
+            for x in 0..width {
+                for c in 0..3 {
+                    let mut values = Vec::new();
+                    for dy in -radius..=radius {
+                        for dx in -radius..=radius {
+                            match (
+                                edge.source(y as isize + dy, height),
+                                edge.source(x as isize + dx, width),
+                            ) {
+                                (Some(y), Some(x)) => values.push(src[(y * width + x) * 4 + c]),
+                                _ if edge == Edge::Shrink => {}
+                                _ => values.push(edge.fill()[c]),
+                            }
+                        }
+                    }
+                    values.sort();
+                    dst[(y * width + x) * 4 + c] = values[(values.len() - 1) / 2];
+                }
+            }
+        }
+        dst
+    }
+
+    #[test]
+    fn test_median_matches_naive() {
+        for (width, height, radius) in [(7, 5, 1), (6, 6, 2), (3, 4, 4)] {
+            let src = noise_image(width, height);
+            let task = Task::new(None, height as u64);
+            for edge in [
+                Edge::Clamp,
+                Edge::Mirror,
+                Edge::Wrap,
+                Edge::Constant([9, 99, 199, 255]),
+                Edge::Shrink,
+            ] {
+                let expected = naive_median(width, height, &src, radius, edge);
+                let mut actual = vec![0; src.len()];
+                median_blur(
+                    width,
+                    height,
+                    &src,
+                    &mut actual,
+                    Window::square(radius),
+                    &options(3, Some(edge)),
+                    &task,
+                )
+                .unwrap();
+                assert_eq!(
+                    actual, expected,
+                    "{width}x{height} radius {radius} {edge:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_median_of_sorted_values_matches_histograms() {
+        let (width, height) = (7, 6);
+        let src = noise_image(width, height);
+        let task = Task::new(None, height as u64);
+        let options = options(3, Some(Edge::Constant([9, 99, 199, 255])));
+
+        let mut expected = vec![0; src.len()];
+        median_blur(
+            width,
+            height,
+            &src,
+            &mut expected,
+            Window::square(2),
+            &options,
+            &task,
+        )
+        .unwrap();
+
+        // Scaling keeps order of values, so median of scaled values is scaled median
+        let src: Vec<u16> = src.iter().map(|v| *v as u16 * 257).collect();
+        let mut actual = vec![0; src.len()];
+        median_blur(
+            width,
+            height,
+            &src,
+            &mut actual,
+            Window::square(2),
+            &options,
+            &task,
+        )
+        .unwrap();
+        let expected: Vec<u16> = expected.iter().map(|v| *v as u16 * 257).collect();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_median_removes_impulse_noise() {
+        let (width, height) = (5, 5);
+        let mut src = vec![100u8; width * height * 4];
+        src[(2 * width + 2) * 4..(2 * width + 3) * 4].copy_from_slice(&[255, 0, 255, 255]);
+        let task = Task::new(None, height as u64);
+        let mut dst = vec![0; src.len()];
+        median_blur(
+            width,
+            height,
+            &src,
+            &mut dst,
+            Window::square(1),
+            &options(3, Some(Edge::Clamp)),
+            &task,
+        )
+        .unwrap();
+        assert!(dst.chunks(4).all(|pixel| pixel[..3] == [100, 100, 100]));
+        // Alpha is copied
+        assert_eq!(dst[(2 * width + 2) * 4 + 3], 255);
+    }
+
+    #[test]
+    fn test_filters_keep_edges() {
+        let (width, height) = (8, 4);
+        let src = step_image(width, height);
+        let task = Task::new(None, height as u64);
+
+        let mut dst = vec![0; src.len()];
+        median_blur(
+            width,
+            height,
+            &src,
+            &mut dst,
+            Window::square(2),
+            &options(3, Some(Edge::Clamp)),
+            &task,
+        )
+        .unwrap();
+        assert_eq!(dst, src);
+
+        bilateral_blur(
+            width,
+            height,
+            &src,
+            &mut dst,
+            Window::square(2),
+            10.0,
+            &options(3, Some(Edge::Clamp)),
+            &task,
+        )
+        .unwrap();
+        assert_eq!(dst, src);
+    }
+
+    #[test]
+    fn test_bilateral_with_large_range_sigma_smooths() {
+        let (width, height) = (8, 4);
+        let src = step_image(width, height);
+        let task = Task::new(None, height as u64);
+        let mut dst = vec![0; src.len()];
+        bilateral_blur(
+            width,
+            height,
+            &src,
+            &mut dst,
+            Window::square(2),
+            10_000.0,
+            &options(3, Some(Edge::Clamp)),
+            &task,
+        )
+        .unwrap();
+
+        let left_of_edge = &dst[(width / 2 - 1) * 4..(width / 2) * 4];
+        assert!(
+            left_of_edge[0] > 0 && left_of_edge[0] < 128,
+            "{left_of_edge:?}"
+        );
+    }
+
+    #[test]
+    fn test_bilateral_smooths_noise() {
+        let (width, height) = (16, 16);
+        let src: Vec<u8> = noise_image(width, height)
+            .chunks(4)
+            .flat_map(|pixel| {
+                [
+                    100 + pixel[0] / 16,
+                    100 + pixel[1] / 16,
+                    100 + pixel[2] / 16,
+                    255,
+                ]
+            })
+            .collect();
+        let task = Task::new(None, height as u64);
+        let mut dst = vec![0; src.len()];
+        bilateral_blur(
+            width,
+            height,
+            &src,
+            &mut dst,
+            Window::square(2),
+            30.0,
+            &options(3, Some(Edge::Mirror)),
+            &task,
+        )
+        .unwrap();
+
+        let spread = |image: &[u8]| {
+            let red = image.chunks(4).map(|pixel| pixel[0]);
+            red.clone().max().unwrap() - red.min().unwrap()
+        };
+        assert!(spread(&dst) < spread(&src));
+    }
+}
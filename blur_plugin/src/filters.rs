@@ -0,0 +1,779 @@
+//! Separable blur filters with cost independent of kernel area
+//!
+//! Both filters process output rows from top to bottom keeping only a few rows of intermediate data,
+//! so memory usage depends on image width and radius, but not on image height
+use std::{ops::Range, panic, thread};
+
+use plugin_errors::PluginError;
+
+use crate::sample::Sample;
+use crate::task::Task;
+
+/// Source of pixels outside of image covered by filter window
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Edge {
+    /// Nearest pixel of image border is repeated
+    Clamp,
+    /// Image is reflected at border including border pixel: `... 2 1 0 | 0 1 2 ...`
+    Mirror,
+    /// Image is repeated from the opposite side
+    Wrap,
+    /// Pixels outside of image have constant RGBA color given in 8-bit units
+    Constant([u8; 4]),
+    /// Pixels outside of image are ignored, so window shrinks at borders
+    Shrink,
+}
+
+impl Edge {
+    /// Coordinate inside `0..len` providing pixel at `pos`, `None` if pixel is not taken from image
+    pub(crate) fn source(&self, pos: isize, len: usize) -> Option<usize> {
+        let len = len as isize;
+        if (0..len).contains(&pos) {
+            return Some(pos as usize);
+        }
+
+        match self {
+            Edge::Clamp => Some(pos.clamp(0, len - 1) as usize),
+            Edge::Mirror => {
+                let pos = pos.rem_euclid(2 * len);
+                Some(if pos < len { pos } else { 2 * len - 1 - pos } as usize)
+            }
+            Edge::Wrap => Some(pos.rem_euclid(len) as usize),
+            Edge::Constant(_) | Edge::Shrink => None,
+        }
+    }
+
+    /// Value of pixel outside of image, which is not taken from image
+    pub(crate) fn fill<T: Sample>(&self) -> [T; 4] {
+        match self {
+            Edge::Constant(color) => color.map(T::from_u8),
+            _ => [T::default(); 4],
+        }
+    }
+}
+
+/// Size of filter window and shape of Gaussian kernel along each axis
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Window {
+    /// Horizontal radius in pixels, window is `2 * radius_x + 1` pixels wide
+    pub radius_x: usize,
+    /// Vertical radius in pixels, window is `2 * radius_y + 1` pixels high
+    pub radius_y: usize,
+    /// Horizontal standard deviation of Gaussian kernel, ignored by box blur
+    pub sigma_x: f32,
+    /// Vertical standard deviation of Gaussian kernel, ignored by box blur
+    pub sigma_y: f32,
+}
+
+impl Window {
+    /// Square window with `sigma = radius / 2`
+    #[cfg(any(test, feature = "bench"))]
+    pub fn square(radius: usize) -> Self {
+        Window {
+            radius_x: radius,
+            radius_y: radius,
+            sigma_x: radius as f32 / 2.0,
+            sigma_y: radius as f32 / 2.0,
+        }
+    }
+}
+
+/// Settings shared by all filters
+#[derive(Debug, Clone, Copy)]
+pub struct FilterOptions {
+    /// Number of threads processing image
+    pub threads: usize,
+
+    /// Blur alpha channel like color channels instead of copying it from source.
+    /// Should be used for images with premultiplied alpha only
+    pub blur_alpha: bool,
+
+    /// Handling of image borders, `None` uses default of filter
+    pub edge: Option<Edge>,
+}
+
+impl Default for FilterOptions {
+    fn default() -> Self {
+        FilterOptions {
+            threads: 1,
+            blur_alpha: false,
+            edge: None,
+        }
+    }
+}
+
+impl FilterOptions {
+    /// Number of leading RGBA channels which are blurred
+    pub(crate) fn channels(&self) -> usize {
+        match self.blur_alpha {
+            true => 4,
+            false => 3,
+        }
+    }
+}
+
+/// Rows of source image extended by `radius` virtual rows above and below according to edge mode
+///
+/// Row `v` of extended image corresponds to row `v - radius` of source image
+pub(crate) struct Rows<'a, T> {
+    src: &'a [T],
+    width: usize,
+    height: usize,
+    radius: usize,
+    edge: Edge,
+    /// Row used for pixels outside of image in `Constant` mode
+    fill_row: Vec<T>,
+}
+
+impl<'a, T: Sample> Rows<'a, T> {
+    pub(crate) fn new(
+        src: &'a [T],
+        width: usize,
+        height: usize,
+        radius: usize,
+        edge: Edge,
+    ) -> Self {
+        Rows {
+            src,
+            width,
+            height,
+            radius,
+            edge,
+            fill_row: edge.fill().repeat(width),
+        }
+    }
+
+    /// Number of rows of extended image
+    fn len(&self) -> usize {
+        self.height + 2 * self.radius
+    }
+
+    /// Data of row `v` of extended image, `None` if row is ignored
+    pub(crate) fn get(&self, v: usize) -> Option<&[T]> {
+        let row_len = self.width * 4;
+        match self
+            .edge
+            .source(v as isize - self.radius as isize, self.height)
+        {
+            Some(y) => Some(&self.src[y * row_len..(y + 1) * row_len]),
+            None if self.edge == Edge::Shrink => None,
+            None => Some(&self.fill_row),
+        }
+    }
+}
+
+/// Box blur averaging pixels of rectangular window
+///
+/// Uses sliding window sums, so every pixel costs O(1) regardless of window size.
+/// Window shrinks at image borders unless `options.edge` is set,
+/// in this case result for square window is identical to `reference::apply_box_blur` for any number of threads
+pub fn box_blur<T: Sample>(
+    width: usize,
+    height: usize,
+    src: &[T],
+    dst: &mut [T],
+    window: Window,
+    options: &FilterOptions,
+    task: &Task,
+) -> Result<(), PluginError> {
+    let Window {
+        radius_x, radius_y, ..
+    } = window;
+    let channels = options.channels();
+    let edge = options.edge.unwrap_or(Edge::Shrink);
+    let rows = Rows::new(src, width, height, radius_y, edge);
+
+    process_bands(width, height, dst, options.threads, |band_rows, band| {
+        let mut padded = Vec::with_capacity((width + 2 * radius_x) * 4);
+        let mut row_sums = vec![0f64; width * channels];
+        let mut column_sums = vec![0f64; width * channels];
+
+        let mut add_row = |column_sums: &mut [f64], v: usize, add: bool| {
+            let Some(row) = rows.get(v) else {
+                return;
+            };
+            pad_row(row, radius_x, edge, &mut padded);
+            horizontal_box_sums(&padded, radius_x, channels, &mut row_sums);
+            for (column_sum, row_sum) in column_sums.iter_mut().zip(&row_sums) {
+                match add {
+                    true => *column_sum += *row_sum,
+                    false => *column_sum -= *row_sum,
+                }
+            }
+        };
+
+        // Output row `y` is average of extended rows `y..=y + 2 * radius_y`
+        for v in band_rows.start..band_rows.start + 2 * radius_y + 1 {
+            add_row(&mut column_sums, v, true);
+        }
+
+        for y in band_rows.clone() {
+            task.check()?;
+
+            let count_y = match edge {
+                Edge::Shrink => window_len(y, radius_y, height),
+                _ => 2 * radius_y + 1,
+            } as u64;
+            for x in 0..width {
+                let count_x = match edge {
+                    Edge::Shrink => window_len(x, radius_x, width),
+                    _ => 2 * radius_x + 1,
+                } as u64;
+                let count = count_x * count_y;
+                let src_idx = (y * width + x) * 4;
+                let out_idx = ((y - band_rows.start) * width + x) * 4;
+                band[out_idx..out_idx + 4].copy_from_slice(&src[src_idx..src_idx + 4]);
+                for c in 0..channels {
+                    band[out_idx + c] = T::from_mean(column_sums[x * channels + c], count);
+                }
+            }
+
+            if y + 2 * radius_y + 1 < rows.len() {
+                add_row(&mut column_sums, y + 2 * radius_y + 1, true);
+            }
+            add_row(&mut column_sums, y, false);
+
+            task.row_done();
+        }
+
+        Ok(())
+    })
+}
+
+/// Gaussian blur with separate radius and sigma along each axis
+///
+/// Applies 1D kernel horizontally and then vertically, so every pixel costs O(radius) instead of O(radius²).
+/// Coordinates outside of image are clamped unless `options.edge` is set,
+/// in this case result for `Window::square` differs from `reference::apply_weighted_blur` by at most 1 because of rounding
+/// and does not depend on number of threads
+pub fn gaussian_blur<T: Sample>(
+    width: usize,
+    height: usize,
+    src: &[T],
+    dst: &mut [T],
+    window: Window,
+    options: &FilterOptions,
+    task: &Task,
+) -> Result<(), PluginError> {
+    let kernel_x = gaussian_kernel(window.radius_x, window.sigma_x);
+    let kernel_y = gaussian_kernel(window.radius_y, window.sigma_y);
+    let channels = options.channels();
+    let row_len = width * channels;
+    let edge = options.edge.unwrap_or(Edge::Clamp);
+    let rows = Rows::new(src, width, height, window.radius_y, edge);
+
+    // In `Shrink` mode weights of pixels inside of image are normalized to sum 1
+    let norm = |kernel: &[f32], pos: usize, len: usize| match edge {
+        Edge::Shrink => {
+            let radius = kernel.len() / 2;
+            let first = radius.saturating_sub(pos);
+            let last = (radius + len - 1 - pos).min(2 * radius);
+            1.0 / kernel[first..=last].iter().sum::<f32>()
+        }
+        _ => 1.0,
+    };
+    let norm_x: Vec<f32> = (0..width).map(|x| norm(&kernel_x, x, width)).collect();
+
+    process_bands(width, height, dst, options.threads, |band_rows, band| {
+        // Ring of horizontally blurred rows of extended image, row `v` is stored in slot `v % ring_rows`.
+        // Output row needs `ring_rows` consecutive extended rows, so slots never collide
+        let ring_rows = kernel_y.len();
+        let mut ring = vec![0f32; ring_rows * row_len];
+        let mut padded = Vec::with_capacity((width + 2 * window.radius_x) * 4);
+        let mut blur_row = |ring: &mut [f32], v: usize| {
+            let slot = v % ring_rows;
+            let out = &mut ring[slot * row_len..(slot + 1) * row_len];
+            match rows.get(v) {
+                Some(row) => {
+                    pad_row(row, window.radius_x, edge, &mut padded);
+                    horizontal_gaussian(&padded, &kernel_x, &norm_x, channels, out);
+                }
+                None => out.fill(0.0),
+            }
+        };
+
+        for v in band_rows.start..band_rows.start + ring_rows {
+            blur_row(&mut ring, v);
+        }
+
+        let mut acc = vec![0f32; row_len];
+        for y in band_rows.clone() {
+            task.check()?;
+
+            acc.fill(0.0);
+            for (k, weight) in kernel_y.iter().enumerate() {
+                let slot = (y + k) % ring_rows;
+                let row = &ring[slot * row_len..(slot + 1) * row_len];
+                for (acc, value) in acc.iter_mut().zip(row) {
+                    *acc += value * weight;
+                }
+            }
+
+            let norm_y = norm(&kernel_y, y, height);
+            for x in 0..width {
+                let src_idx = (y * width + x) * 4;
+                let out_idx = ((y - band_rows.start) * width + x) * 4;
+                band[out_idx..out_idx + 4].copy_from_slice(&src[src_idx..src_idx + 4]);
+                for c in 0..channels {
+                    band[out_idx + c] = T::from_f32(acc[x * channels + c] * norm_y);
+                }
+            }
+
+            if y + ring_rows < rows.len() {
+                blur_row(&mut ring, y + ring_rows);
+            }
+
+            task.row_done();
+        }
+
+        Ok(())
+    })
+}
+
+/// Split RGBA `dst` into bands of consecutive rows and run `process` for every band on up to `threads` threads
+///
+/// Number of threads is limited by number of rows and available CPUs. `process` receives range of image rows
+/// and data of these rows. Bands are processed independently, so result does not depend on number of threads
+pub(crate) fn process_bands<T, F>(
+    width: usize,
+    height: usize,
+    dst: &mut [T],
+    threads: usize,
+    process: F,
+) -> Result<(), PluginError>
+where
+    T: Send,
+    F: Fn(Range<usize>, &mut [T]) -> Result<(), PluginError> + Sync,
+{
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
+
+    let cpus = thread::available_parallelism().map_or(1, |n| n.get());
+    let threads = threads.min(cpus).clamp(1, height);
+    if threads == 1 {
+        return process(0..height, dst);
+    }
+
+    let band_rows = height.div_ceil(threads);
+    let process = &process;
+    thread::scope(|scope| {
+        let handles: Vec<_> = dst
+            .chunks_mut(band_rows * width * 4)
+            .enumerate()
+            .map(|(idx, band)| {
+                let first_row = idx * band_rows;
+                let rows = first_row..first_row + band.len() / (width * 4);
+                scope.spawn(move || process(rows, band))
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .try_for_each(|handle| handle.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+    })
+}
+
+/// Number of pixels of window `[pos - radius, pos + radius]` inside `0..len`
+fn window_len(pos: usize, radius: usize, len: usize) -> usize {
+    (pos + radius).min(len - 1) - pos.saturating_sub(radius) + 1
+}
+
+/// Extend RGBA `row` by `radius` pixels on both sides according to `edge` mode
+///
+/// Ignored pixels of `Shrink` mode are zero, so they do not change sums
+fn pad_row<T: Sample>(row: &[T], radius: usize, edge: Edge, padded: &mut Vec<T>) {
+    let width = row.len() / 4;
+    padded.clear();
+    for pos in -(radius as isize)..(width + radius) as isize {
+        match edge.source(pos, width) {
+            Some(x) => padded.extend_from_slice(&row[x * 4..(x + 1) * 4]),
+            None => padded.extend_from_slice(&edge.fill()),
+        }
+    }
+}
+
+/// Sums of first `channels` channels over window `[x, x + 2 * radius]` of every pixel in RGBA row `padded`
+///
+/// `padded` is image row extended by `radius` pixels on both sides, so window is centered at image pixel `x`
+fn horizontal_box_sums<T: Sample>(padded: &[T], radius: usize, channels: usize, sums: &mut [f64]) {
+    let width = padded.len() / 4 - 2 * radius;
+    let mut acc = [0f64; 4];
+    let acc = &mut acc[..channels];
+
+    for x in 0..2 * radius + 1 {
+        for (c, acc) in acc.iter_mut().enumerate() {
+            *acc += padded[x * 4 + c].to_f32() as f64;
+        }
+    }
+
+    for x in 0..width {
+        sums[x * channels..(x + 1) * channels].copy_from_slice(acc);
+
+        if x + 1 < width {
+            for (c, acc) in acc.iter_mut().enumerate() {
+                *acc += padded[(x + 2 * radius + 1) * 4 + c].to_f32() as f64;
+                *acc -= padded[x * 4 + c].to_f32() as f64;
+            }
+        }
+    }
+}
+
+/// Convolve first `channels` channels of RGBA row `padded` with `kernel` and multiply by `norm` of every pixel
+///
+/// `padded` is image row extended by `kernel.len() / 2` pixels on both sides
+fn horizontal_gaussian<T: Sample>(
+    padded: &[T],
+    kernel: &[f32],
+    norm: &[f32],
+    channels: usize,
+    out: &mut [f32],
+) {
+    for (x, norm) in norm.iter().enumerate() {
+        let mut acc = [0f32; 4];
+        let acc = &mut acc[..channels];
+        for (k, weight) in kernel.iter().enumerate() {
+            for (c, acc) in acc.iter_mut().enumerate() {
+                *acc += padded[(x + k) * 4 + c].to_f32() * weight;
+            }
+        }
+        for (c, acc) in acc.iter().enumerate() {
+            out[x * channels + c] = acc * norm;
+        }
+    }
+}
+
+/// Normalized 1D Gaussian kernel of `2 * radius + 1` weights
+fn gaussian_kernel(radius: usize, sigma: f32) -> Vec<f32> {
+    if radius == 0 {
+        return vec![1.0];
+    }
+
+    let radius = radius as isize;
+    let mut kernel: Vec<f32> = (-radius..=radius)
+        .map(|k| (-((k * k) as f32) / (2.0 * sigma * sigma)).exp())
+        .collect();
+
+    let sum: f32 = kernel.iter().sum();
+    for weight in kernel.iter_mut() {
+        *weight /= sum;
+    }
+
+    kernel
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reference;
+    use crate::test_util::{max_difference, noise_image, options};
+
+    #[test]
+    fn test_box_blur_matches_reference() {
+        for (width, height, radius) in [(13, 7, 1), (13, 7, 3), (5, 9, 6), (1, 1, 2), (20, 1, 4)] {
+            let src = noise_image(width, height);
+            let task = Task::new(None, height as u64);
+
+            let mut expected = vec![0; src.len()];
+            reference::apply_box_blur(width, height, &src, &mut expected, radius, &task).unwrap();
+            let mut actual = vec![0; src.len()];
+            box_blur(
+                width,
+                height,
+                &src,
+                &mut actual,
+                Window::square(radius),
+                &options(1, None),
+                &task,
+            )
+            .unwrap();
+
+            assert_eq!(actual, expected, "{width}x{height} radius {radius}");
+        }
+    }
+
+    #[test]
+    fn test_gaussian_blur_close_to_reference() {
+        for (width, height, radius) in [(13, 7, 1), (13, 7, 3), (5, 9, 6), (1, 1, 2), (20, 1, 4)] {
+            let src = noise_image(width, height);
+            let task = Task::new(None, height as u64);
+
+            let mut expected = vec![0; src.len()];
+            reference::apply_weighted_blur(width, height, &src, &mut expected, radius, &task)
+                .unwrap();
+            let mut actual = vec![0; src.len()];
+            gaussian_blur(
+                width,
+                height,
+                &src,
+                &mut actual,
+                Window::square(radius),
+                &options(1, None),
+                &task,
+            )
+            .unwrap();
+
+            assert!(
+                max_difference(&actual, &expected) <= 1,
+                "{width}x{height} radius {radius}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_threads_do_not_change_result() {
+        let (width, height) = (17, 23);
+        let src = noise_image(width, height);
+
+        for filter in [box_blur, gaussian_blur] {
+            for radius in [1, 4, 30] {
+                let task = Task::new(None, height as u64);
+                let mut single = vec![0; src.len()];
+                filter(
+                    width,
+                    height,
+                    &src,
+                    &mut single,
+                    Window::square(radius),
+                    &options(1, None),
+                    &task,
+                )
+                .unwrap();
+
+                for threads in [2, 3, 8, 64] {
+                    let mut multi = vec![0; src.len()];
+                    filter(
+                        width,
+                        height,
+                        &src,
+                        &mut multi,
+                        Window::square(radius),
+                        &options(threads, None),
+                        &task,
+                    )
+                    .unwrap();
+                    assert_eq!(multi, single, "radius {radius}, {threads} threads");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_alpha_is_copied() {
+        let src = noise_image(4, 4);
+        let task = Task::new(None, 4);
+        let mut dst = vec![0; src.len()];
+        gaussian_blur(
+            4,
+            4,
+            &src,
+            &mut dst,
+            Window::square(2),
+            &options(1, None),
+            &task,
+        )
+        .unwrap();
+
+        for (dst, src) in dst.chunks(4).zip(src.chunks(4)) {
+            assert_eq!(dst[3], src[3]);
+        }
+    }
+
+    #[test]
+    fn test_blur_alpha() {
+        let mut src = vec![0u8; 3 * 4];
+        src[4..8].copy_from_slice(&[90, 90, 90, 255]);
+        let task = Task::new(None, 1);
+        let options = FilterOptions {
+            blur_alpha: true,
+            ..FilterOptions::default()
+        };
+
+        let mut dst = vec![0u8; src.len()];
+        box_blur(3, 1, &src, &mut dst, Window::square(1), &options, &task).unwrap();
+        assert_eq!(dst, [45, 45, 45, 127, 30, 30, 30, 85, 45, 45, 45, 127]);
+    }
+
+    /// Naive 2D convolution of color channels with separable kernel taking pixels outside of image by `edge`
+    fn naive_blur(
+        width: usize,
+        height: usize,
+        src: &[u8],
+        (kernel_x, kernel_y): (&[f32], &[f32]),
+        edge: Edge,
+    ) -> Vec<u8> {
+        let radius_x = (kernel_x.len() / 2) as isize;
+        let radius_y = (kernel_y.len() / 2) as isize;
+        let mut dst = src.to_vec();
+        for y in 0..height {
+            for x in 0..width {
+                let mut acc = [0f32; 3];
+                let mut weights = 0.0;
+                for dy in -radius_y..=radius_y {
+                    for dx in -radius_x..=radius_x {
+                        let weight =
+                            kernel_y[(dy + radius_y) as usize] * kernel_x[(dx + radius_x) as usize];
+                        let pixel = match (
+                            edge.source(y as isize + dy, height),
+                            edge.source(x as isize + dx, width),
+                        ) {
+                            (Some(y), Some(x)) => &src[(y * width + x) * 4..],
+                            _ if edge == Edge::Shrink => continue,
+                            _ => &edge.fill()[..],
+                        };
+                        for (c, acc) in acc.iter_mut().enumerate() {
+                            *acc += pixel[c] as f32 * weight;
+                        }
+                        weights += weight;
+                    }
+                }
+                for (c, acc) in acc.iter().enumerate() {
+                    dst[(y * width + x) * 4 + c] = (acc / weights).round() as u8;
+                }
+            }
+        }
+        dst
+    }
+
+    const EDGES: [Edge; 5] = [
+        Edge::Clamp,
+        Edge::Mirror,
+        Edge::Wrap,
+        Edge::Constant([200, 10, 0, 255]),
+        Edge::Shrink,
+    ];
+
+    #[test]
+    fn test_box_blur_edge_corners() {
+        // 3x3 image with red channel 10, 20, ..., 90
+        let src: Vec<u8> = (1..=9).flat_map(|v| [v * 10, 0, 0, 255]).collect();
+        let task = Task::new(None, 3);
+
+        for (edge, top_left, bottom_right) in [
+            (Edge::Clamp, 34, 66),
+            (Edge::Mirror, 42, 58),
+            (Edge::Wrap, 58, 42),
+            (Edge::Constant([200, 10, 0, 255]), 146, 146),
+            (Edge::Shrink, 50, 50),
+        ] {
+            let options = FilterOptions {
+                edge: Some(edge),
+                ..FilterOptions::default()
+            };
+            let mut dst = vec![0; src.len()];
+            box_blur(3, 3, &src, &mut dst, Window::square(2), &options, &task).unwrap();
+
+            assert_eq!(dst[0], top_left, "{edge:?}");
+            assert_eq!(dst[8 * 4], bottom_right, "{edge:?}");
+        }
+    }
+
+    #[test]
+    fn test_edge_modes_match_naive_blur() {
+        for (width, height, radius) in [(6, 5, 2), (4, 3, 5), (1, 4, 1)] {
+            let src = noise_image(width, height);
+            let task = Task::new(None, height as u64);
+
+            for edge in EDGES {
+                let options = FilterOptions {
+                    threads: 2,
+                    edge: Some(edge),
+                    ..FilterOptions::default()
+                };
+                let case = format!("{width}x{height} radius {radius} {edge:?}");
+
+                let kernel = gaussian_kernel(radius, radius as f32 / 2.0);
+                let expected = naive_blur(width, height, &src, (&kernel, &kernel), edge);
+                let mut actual = vec![0; src.len()];
+                gaussian_blur(
+                    width,
+                    height,
+                    &src,
+                    &mut actual,
+                    Window::square(radius),
+                    &options,
+                    &task,
+                )
+                .unwrap();
+                assert!(max_difference(&actual, &expected) <= 1, "gaussian {case}");
+
+                // Box blur truncates average, so it may differ from rounded one by 1
+                let ones = vec![1.0; 2 * radius + 1];
+                let expected = naive_blur(width, height, &src, (&ones, &ones), edge);
+                box_blur(
+                    width,
+                    height,
+                    &src,
+                    &mut actual,
+                    Window::square(radius),
+                    &options,
+                    &task,
+                )
+                .unwrap();
+                assert!(max_difference(&actual, &expected) <= 1, "box {case}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_anisotropic_window_matches_naive_blur() {
+        let (width, height) = (9, 7);
+        let src = noise_image(width, height);
+        let task = Task::new(None, height as u64);
+
+        for (radius_x, radius_y, sigma_x, sigma_y) in
+            [(3, 0, 1.0, 0.0), (0, 2, 0.0, 0.7), (4, 1, 3.0, 0.5)]
+        {
+            let window = Window {
+                radius_x,
+                radius_y,
+                sigma_x,
+                sigma_y,
+            };
+            let case = format!("{window:?}");
+            for edge in EDGES {
+                let options = FilterOptions {
+                    edge: Some(edge),
+                    ..FilterOptions::default()
+                };
+                let mut actual = vec![0; src.len()];
+
+                let kernel_x = gaussian_kernel(radius_x, sigma_x);
+                let kernel_y = gaussian_kernel(radius_y, sigma_y);
+                let expected = naive_blur(width, height, &src, (&kernel_x, &kernel_y), edge);
+                gaussian_blur(width, height, &src, &mut actual, window, &options, &task).unwrap();
+                assert!(
+                    max_difference(&actual, &expected) <= 1,
+                    "gaussian {case} {edge:?}"
+                );
+
+                let ones_x = vec![1.0; 2 * radius_x + 1];
+                let ones_y = vec![1.0; 2 * radius_y + 1];
+                let expected = naive_blur(width, height, &src, (&ones_x, &ones_y), edge);
+                box_blur(width, height, &src, &mut actual, window, &options, &task).unwrap();
+                assert!(
+                    max_difference(&actual, &expected) <= 1,
+                    "box {case} {edge:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_edge_mirror_and_wrap_sources() {
+        let mirror: Vec<_> = (-4..7).map(|pos| Edge::Mirror.source(pos, 3)).collect();
+        let expected = [2, 2, 1, 0, 0, 1, 2, 2, 1, 0, 0].map(Some);
+        assert_eq!(mirror, expected);
+
+        let wrap: Vec<_> = (-4..7).map(|pos| Edge::Wrap.source(pos, 3)).collect();
+        assert_eq!(wrap, [2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0].map(Some));
+    }
+
+    #[test]
+    fn test_gaussian_kernel_is_normalized() {
+        let kernel = gaussian_kernel(5, 2.5);
+        assert_eq!(kernel.len(), 11);
+        assert!((kernel.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+        assert_eq!(kernel[0], kernel[10]);
+    }
+}
@@ -3,18 +3,380 @@
 #![deny(unreachable_pub)]
 #![warn(missing_docs)]
 
+mod alpha;
+mod directional;
+mod edge_preserving;
+mod sharpen;
+
+// Filters are public only for benchmarks comparing them with reference implementations
+#[cfg(feature = "bench")]
+pub mod filters;
+#[cfg(feature = "bench")]
+pub mod reference;
+#[cfg(feature = "bench")]
+pub mod sample;
+#[cfg(feature = "bench")]
+pub mod task;
+
+#[cfg(not(feature = "bench"))]
+#[allow(unreachable_pub)]
+mod filters;
+#[cfg(all(test, not(feature = "bench")))]
+#[allow(unreachable_pub)]
+mod reference;
+#[cfg(not(feature = "bench"))]
+#[allow(unreachable_pub)]
+mod sample;
+#[cfg(not(feature = "bench"))]
+#[allow(unreachable_pub)]
+mod task;
+#[cfg(test)]
+mod test_util;
+
 use log::error;
-use plugin_errors::PluginError;
+use plugin_abi::{ABI_VERSION, PixelFormat, PluginInfo, ProcessContext, capabilities};
+use plugin_errors::{PluginError, clear_last_error, last_error_ptr, panic_message};
 use serde::Deserialize;
 use std::ffi::CStr;
+use std::mem;
 use std::os::raw::{c_char, c_uchar};
 use std::panic::catch_unwind;
+use std::thread;
+
+use crate::alpha::{premultiply, unpremultiply};
+use crate::directional::{motion_blur, radial_blur};
+use crate::edge_preserving::{bilateral_blur, median_blur};
+use crate::filters::{Edge, FilterOptions, Window, box_blur, gaussian_blur};
+use crate::sample::Sample;
+use crate::sharpen::unsharp_mask;
+use crate::task::Task;
+
+/// Maximum value of `threads` param, bigger values are limited by number of CPUs anyway
+const MAX_THREADS: u32 = 1024;
+
+/// Blur algorithm
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum BlurMode {
+    Box,
+    Gaussian,
+    /// Directional blur along segment of `length` pixels at `angle`
+    Motion,
+    /// Zoom blur towards `center`
+    #[serde(alias = "zoom")]
+    Radial,
+    /// Median of window, removes impulse noise
+    Median,
+    /// Gaussian weighted by color difference, smooths areas but keeps edges
+    Bilateral,
+    /// Sharpening by unsharp mask with Gaussian blur
+    #[serde(alias = "sharpen")]
+    Unsharp,
+}
+
+/// Handling of alpha channel
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum AlphaMode {
+    /// Blur color channels only, alpha is copied unchanged
+    #[default]
+    Copy,
+    /// Blur all channels of image converted to premultiplied alpha,
+    /// so colors of transparent pixels do not bleed into visible ones
+    Premultiplied,
+}
 
-#[derive(Debug, Deserialize)]
+/// Handling of image borders, see `filters::Edge`
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum EdgeMode {
+    Clamp,
+    #[serde(alias = "reflect")]
+    Mirror,
+    Wrap,
+    /// Pixels outside of image have color `edge_color`
+    #[serde(alias = "transparent")]
+    Constant,
+    Shrink,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(default)]
 struct BlurParams {
-    radius: u32,
+    /// Blur algorithm, by default box or Gaussian depending on `weighted`
+    mode: Option<BlurMode>,
+    /// Radius along both axes, by default 1 or `3 * sigma` for weighted blur with explicit sigma
+    radius: Option<u32>,
+    radius_x: Option<u32>,
+    radius_y: Option<u32>,
+    /// Gaussian standard deviation along both axes, by default half of radius
+    sigma: Option<f32>,
+    sigma_x: Option<f32>,
+    sigma_y: Option<f32>,
+    /// Bilateral filter standard deviation of color difference
+    sigma_range: f32,
+    /// Motion blur direction in degrees counterclockwise from horizontal
+    angle: f32,
+    /// Motion blur length in pixels
+    length: f32,
+    /// Radial blur center relative to image size, `[0.5, 0.5]` is center of image
+    center: [f32; 2],
+    /// Part of distance to center covered by radial blur
+    strength: f32,
+    /// Unsharp mask multiplier of difference between image and its blur
+    amount: f32,
+    /// Unsharp mask minimum difference of channel which is sharpened
+    threshold: u8,
     iterations: u32,
     weighted: bool,
+    /// Number of threads, 0 uses number recommended by host or all CPUs
+    threads: u32,
+    alpha: AlphaMode,
+    /// Edge mode, by default window shrinks for box blur and coordinates are clamped for weighted one
+    edge: Option<EdgeMode>,
+    edge_color: [u8; 4],
+}
+
+impl Default for BlurParams {
+    fn default() -> Self {
+        BlurParams {
+            mode: None,
+            radius: None,
+            radius_x: None,
+            radius_y: None,
+            sigma: None,
+            sigma_x: None,
+            sigma_y: None,
+            sigma_range: 30.0,
+            angle: 0.0,
+            length: 10.0,
+            center: [0.5, 0.5],
+            strength: 0.1,
+            amount: 1.0,
+            threshold: 0,
+            iterations: 1,
+            weighted: false,
+            threads: 0,
+            alpha: AlphaMode::Copy,
+            edge: None,
+            edge_color: [0; 4],
+        }
+    }
+}
+
+impl BlurParams {
+    /// Number of threads to use: explicit `threads` param, host recommendation or number of CPUs
+    fn thread_count(&self, ctx: Option<&ProcessContext>) -> usize {
+        match self.threads {
+            0 => ctx
+                .and_then(ProcessContext::thread_count)
+                .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get())),
+            threads => threads as usize,
+        }
+    }
+
+    fn mode(&self) -> BlurMode {
+        match (self.mode, self.weighted) {
+            (Some(mode), _) => mode,
+            (None, true) => BlurMode::Gaussian,
+            (None, false) => BlurMode::Box,
+        }
+    }
+
+    /// Check values which can not be rejected by deserialization
+    fn validate(&self) -> Result<(), String> {
+        if let Some(sigma) = [self.sigma, self.sigma_x, self.sigma_y]
+            .into_iter()
+            .flatten()
+            .find(|sigma| !(sigma.is_finite() && *sigma > 0.0))
+        {
+            return Err(format!("sigma must be positive, found {sigma}"));
+        }
+        if !(self.sigma_range.is_finite() && self.sigma_range > 0.0) {
+            return Err(format!(
+                "sigma_range must be positive, found {}",
+                self.sigma_range
+            ));
+        }
+        if !(self.length.is_finite() && self.length >= 0.0) {
+            return Err(format!(
+                "length must not be negative, found {}",
+                self.length
+            ));
+        }
+        if !self.angle.is_finite() || !self.center.iter().all(|c| c.is_finite()) {
+            return Err("angle and center must be finite numbers".to_string());
+        }
+        if !(self.amount.is_finite() && self.amount >= 0.0) {
+            return Err(format!(
+                "amount must not be negative, found {}",
+                self.amount
+            ));
+        }
+        if self.threads > MAX_THREADS {
+            return Err(format!(
+                "threads must not exceed {MAX_THREADS}, found {}",
+                self.threads
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.strength) {
+            return Err(format!(
+                "strength must be between 0 and 1, found {}",
+                self.strength
+            ));
+        }
+        Ok(())
+    }
+
+    /// Whether blur with these params leaves image of given size unchanged
+    fn is_noop(&self, width: usize, height: usize) -> bool {
+        let window = self.window(width, height);
+        self.iterations == 0
+            || match self.mode() {
+                BlurMode::Box | BlurMode::Gaussian | BlurMode::Median | BlurMode::Bilateral => {
+                    window.radius_x == 0 && window.radius_y == 0
+                }
+                BlurMode::Unsharp => {
+                    window.radius_x == 0 && window.radius_y == 0 || self.amount == 0.0
+                }
+                BlurMode::Motion => self.length == 0.0,
+                BlurMode::Radial => self.strength == 0.0,
+            }
+    }
+
+    /// Filter window for image of given size, `radius_x`/`radius_y` and `sigma_x`/`sigma_y` override
+    /// common `radius` and `sigma`
+    ///
+    /// Radius is limited by image size along its axis: larger window adds only repeated border pixels,
+    /// but its buffers could exhaust memory for huge radius or sigma
+    fn window(&self, width: usize, height: usize) -> Window {
+        let axis = |radius: Option<u32>, sigma: Option<f32>, size: usize| {
+            let sigma = sigma.or(self.sigma);
+            let radius = match (radius.or(self.radius), sigma) {
+                (Some(radius), _) => radius as usize,
+                (None, Some(sigma))
+                    if matches!(
+                        self.mode(),
+                        BlurMode::Gaussian | BlurMode::Bilateral | BlurMode::Unsharp
+                    ) =>
+                {
+                    (3.0 * sigma).ceil() as usize
+                }
+                (None, _) => 1,
+            };
+            (radius.min(size), sigma.unwrap_or(radius as f32 / 2.0))
+        };
+
+        let (radius_x, sigma_x) = axis(self.radius_x, self.sigma_x, width);
+        let (radius_y, sigma_y) = axis(self.radius_y, self.sigma_y, height);
+        Window {
+            radius_x,
+            radius_y,
+            sigma_x,
+            sigma_y,
+        }
+    }
+
+    /// Edge mode of filters, constant color is premultiplied if alpha is blurred
+    fn edge(&self) -> Option<Edge> {
+        let edge = match self.edge? {
+            EdgeMode::Clamp => Edge::Clamp,
+            EdgeMode::Mirror => Edge::Mirror,
+            EdgeMode::Wrap => Edge::Wrap,
+            EdgeMode::Constant => {
+                let mut color = self.edge_color;
+                if self.alpha == AlphaMode::Premultiplied {
+                    premultiply(&mut color);
+                }
+                Edge::Constant(color)
+            }
+            EdgeMode::Shrink => Edge::Shrink,
+        };
+        Some(edge)
+    }
+}
+
+static PARAMS_SCHEMA: &CStr = cr#"{
+    "type": "object",
+    "properties": {
+        "mode": {
+            "type": "string",
+            "enum": ["box", "gaussian", "motion", "radial", "zoom", "median", "bilateral", "unsharp", "sharpen"],
+            "description": "Blur algorithm, by default gaussian if weighted is set and box otherwise"
+        },
+        "radius": { "type": "integer", "minimum": 0, "default": 1, "description": "Blur radius in pixels, 0 leaves image unchanged. By default 3 * sigma for gaussian, bilateral and unsharp modes with sigma" },
+        "radius_x": { "type": "integer", "minimum": 0, "description": "Horizontal blur radius, overrides radius" },
+        "radius_y": { "type": "integer", "minimum": 0, "description": "Vertical blur radius, overrides radius" },
+        "sigma": { "type": "number", "exclusiveMinimum": 0, "description": "Standard deviation of gaussian, bilateral and unsharp modes, by default half of radius" },
+        "sigma_x": { "type": "number", "exclusiveMinimum": 0, "description": "Horizontal standard deviation of gaussian, bilateral and unsharp modes, overrides sigma" },
+        "sigma_y": { "type": "number", "exclusiveMinimum": 0, "description": "Vertical standard deviation of gaussian, bilateral and unsharp modes, overrides sigma" },
+        "sigma_range": { "type": "number", "exclusiveMinimum": 0, "default": 30, "description": "Standard deviation of RGB color difference of bilateral mode" },
+        "angle": { "type": "number", "default": 0, "description": "Motion blur direction in degrees counterclockwise from horizontal" },
+        "length": { "type": "number", "minimum": 0, "default": 10, "description": "Motion blur length in pixels" },
+        "center": {
+            "type": "array",
+            "items": { "type": "number" },
+            "minItems": 2,
+            "maxItems": 2,
+            "default": [0.5, 0.5],
+            "description": "Radial blur center as parts of image width and height"
+        },
+        "strength": { "type": "number", "minimum": 0, "maximum": 1, "default": 0.1, "description": "Part of distance to center covered by radial blur" },
+        "amount": { "type": "number", "minimum": 0, "default": 1, "description": "Unsharp mask strength, multiplier of difference between image and its blur" },
+        "threshold": { "type": "integer", "minimum": 0, "maximum": 255, "default": 0, "description": "Unsharp mask minimum difference of channel which is sharpened" },
+        "iterations": { "type": "integer", "minimum": 0, "default": 1, "description": "Number of blur passes" },
+        "weighted": { "type": "boolean", "default": false, "description": "Use gaussian mode if mode is not set" },
+        "threads": { "type": "integer", "minimum": 0, "maximum": 1024, "default": 0, "description": "Number of threads, 0 uses host recommendation or all CPUs" },
+        "alpha": { "type": "string", "enum": ["copy", "premultiplied"], "default": "copy", "description": "Copy alpha unchanged or blur all channels in premultiplied alpha" },
+        "edge": {
+            "type": "string",
+            "enum": ["clamp", "mirror", "reflect", "wrap", "constant", "transparent", "shrink"],
+            "description": "Pixels outside of image: repeated border, mirrored image, opposite side of image, edge_color or ignored. By default shrink for box blur and clamp for weighted"
+        },
+        "edge_color": {
+            "type": "array",
+            "items": { "type": "integer", "minimum": 0, "maximum": 255 },
+            "minItems": 4,
+            "maxItems": 4,
+            "default": [0, 0, 0, 0],
+            "description": "RGBA color of pixels outside of image for constant edge mode"
+        }
+    },
+    "additionalProperties": false
+}"#;
+
+static PLUGIN_INFO: PluginInfo = PluginInfo {
+    abi_version: ABI_VERSION,
+    name: c"blur".as_ptr(),
+    version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr().cast(),
+    description: c"Blurs image with box, Gaussian, motion, radial, median or bilateral filter, or sharpens it with unsharp mask"
+        .as_ptr(),
+    capabilities: capabilities::IN_PLACE
+        | capabilities::ERROR_MESSAGE
+        | capabilities::PARAMS_SCHEMA,
+    pixel_formats: PixelFormat::Rgba8.flag()
+        | PixelFormat::Rgba16.flag()
+        | PixelFormat::Rgba32F.flag(),
+};
+
+/// Plugin description used by host to check ABI compatibility and supported features
+#[unsafe(no_mangle)]
+pub extern "C" fn plugin_info() -> *const PluginInfo {
+    &PLUGIN_INFO
+}
+
+/// JSON Schema of params accepted by `process_image`
+#[unsafe(no_mangle)]
+pub extern "C" fn params_schema() -> *const c_char {
+    PARAMS_SCHEMA.as_ptr()
+}
+
+/// Description of last error happened in plugin function called on current thread
+///
+/// Returns null if last call finished successfully. Pointer stays valid until next plugin function call on the same thread
+#[unsafe(no_mangle)]
+pub extern "C" fn last_error_message() -> *const c_char {
+    last_error_ptr()
 }
 
 /// Image conversion function. Runs in-place
@@ -23,26 +385,33 @@ struct BlurParams {
 ///
 /// * `width` - image width in pixels
 /// * `height` - image height in pixels
-/// * `rgba_data` - pointer to image data. Image conversion runs in place so it will contain result data in case of successful conversion
+/// * `pixel_format` - `PixelFormat` of image data: RGBA with 8-bit, 16-bit or floating point channels
+/// * `data` - pointer to image data. Image conversion runs in place so it will contain result data in case of successful conversion
 /// * `params` - pointer to params string
+/// * `ctx` - pointer to processing context with cancellation flag, may be null
 ///
 /// # Safety
 ///
 /// Pointers are checked for being non-null before usage
 /// `params` should point to a valid UTF-8 string ending with nul-terminator
-/// `rgba_data` must have at least data_size bytes
+/// `data` must have at least data_size bytes and be aligned to channel size
+/// `ctx` should be null or point to `ProcessContext` valid for the whole call
 ///
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn process_image(
     width: u32,
     height: u32,
-    rgba_data: *mut c_uchar,
+    pixel_format: u32,
+    data: *mut c_uchar,
     params: *const c_char,
+    ctx: *const ProcessContext,
 ) -> i32 {
+    clear_last_error();
+
     let result = catch_unwind(move || {
         // Prevent usage of null pointers
-        if rgba_data.is_null() || params.is_null() {
-            return PluginError::NullPointer as i32;
+        if data.is_null() || params.is_null() {
+            return PluginError::NullPointer.with_message("image data or params pointer is null");
         }
 
         // SAFETY: `params` should point to a valid UTF-8 string ending with nul-terminator
@@ -51,146 +420,178 @@ pub unsafe extern "C" fn process_image(
 
         let config: BlurParams = match serde_json::from_str(&params_str) {
             Ok(p) => p,
-            Err(_) => return PluginError::InvalidParams as i32,
+            Err(e) => return PluginError::InvalidParams.with_message(e.to_string()),
         };
 
-        if config.radius == 0 || config.iterations == 0 {
+        if let Err(message) = config.validate() {
+            return PluginError::InvalidParams.with_message(message);
+        }
+
+        let format = match PixelFormat::from_u32(pixel_format) {
+            Some(format @ (PixelFormat::Rgba8 | PixelFormat::Rgba16 | PixelFormat::Rgba32F)) => {
+                format
+            }
+            Some(format) => {
+                return PluginError::UnsupportedPixelFormat
+                    .with_message(format!("pixel format {} is not supported", format.name()));
+            }
+            None => {
+                return PluginError::UnsupportedPixelFormat
+                    .with_message(format!("unknown pixel format {pixel_format}"));
+            }
+        };
+
+        if config.is_noop(width as usize, height as usize) {
             return PluginError::Ok as i32;
         }
 
-        let Some(data_size) = (width as usize)
-            .checked_mul(height as usize)
-            .and_then(|res| res.checked_mul(4))
-        else {
-            return PluginError::SizeIsTooBig as i32;
+        let Some(data_size) = format.data_size(width, height) else {
+            return PluginError::SizeIsTooBig
+                .with_message(format!("image {width}x{height} is too big"));
         };
+        let len = data_size / format.channel_size();
 
-        // SAFETY: rgba_data must have at least data_size bytes
-        let pixels = unsafe { std::slice::from_raw_parts_mut(rgba_data, data_size) };
-
-        let mut buffer = vec![0u8; pixels.len()];
-
-        for _ in 0..config.iterations {
-            if config.weighted {
-                apply_weighted_blur(
-                    width as usize,
-                    height as usize,
-                    pixels,
-                    &mut buffer,
-                    config.radius as usize,
-                );
-            } else {
-                apply_box_blur(
-                    width as usize,
-                    height as usize,
-                    pixels,
-                    &mut buffer,
-                    config.radius as usize,
-                );
+        // SAFETY: ctx should be null or point to valid ProcessContext
+        let ctx = unsafe { ProcessContext::from_ptr(ctx) };
+        // SAFETY: data must have at least data_size bytes
+        let result = unsafe {
+            match format {
+                PixelFormat::Rgba16 => channels::<u16>(data, len)
+                    .map(|pixels| blur(width, height, pixels, &config, ctx)),
+                PixelFormat::Rgba32F => channels::<f32>(data, len)
+                    .map(|pixels| blur(width, height, pixels, &config, ctx)),
+                _ => channels::<u8>(data, len)
+                    .map(|pixels| blur(width, height, pixels, &config, ctx)),
             }
+        };
 
-            pixels.copy_from_slice(&buffer);
+        match result {
+            Some(Ok(())) => PluginError::Ok as i32,
+            Some(Err(PluginError::Cancelled)) => {
+                PluginError::Cancelled.with_message("processing is cancelled by host")
+            }
+            Some(Err(e)) => e as i32,
+            None => {
+                PluginError::InvalidData.with_message("image data is not aligned to channel size")
+            }
         }
-
-        PluginError::Ok as i32
     });
 
     match result {
         Ok(status) => status,
         Err(e) => {
-            error!("panic in process_image {e:?}");
-            PluginError::Panic as i32
+            let message = panic_message(e.as_ref());
+            error!("panic in process_image {message}");
+            PluginError::Panic.with_message(format!("panic in process_image: {message}"))
         }
     }
 }
 
-fn apply_box_blur(width: usize, height: usize, src: &[u8], dst: &mut [u8], radius: usize) {
-    for y in 0..height {
-        for x in 0..width {
-            let mut r_acc = 0u32;
-            let mut g_acc = 0u32;
-            let mut b_acc = 0u32;
-            let mut count = 0u32;
-
-            for ky in (y as isize - radius as isize)..=(y as isize + radius as isize) {
-                for kx in (x as isize - radius as isize)..=(x as isize + radius as isize) {
-                    if ky >= 0 && ky < height as isize && kx >= 0 && kx < width as isize {
-                        let idx = (ky as usize * width + kx as usize) * 4;
-                        r_acc += src[idx] as u32;
-                        g_acc += src[idx + 1] as u32;
-                        b_acc += src[idx + 2] as u32;
-                        count += 1;
-                    }
-                }
-            }
-
-            let out_idx = (y * width + x) * 4;
-            dst[out_idx] = (r_acc / count) as u8;
-            dst[out_idx + 1] = (g_acc / count) as u8;
-            dst[out_idx + 2] = (b_acc / count) as u8;
-            dst[out_idx + 3] = src[out_idx + 3];
-        }
-    }
+/// Image data as `len` channel values of type `T`, None if pointer is not aligned for `T`
+///
+/// # Safety
+///
+/// `data` must point to at least `len * size_of::<T>()` bytes
+unsafe fn channels<'a, T>(data: *mut c_uchar, len: usize) -> Option<&'a mut [T]> {
+    let data = data.cast::<T>();
+    data.is_aligned()
+        .then(|| unsafe { std::slice::from_raw_parts_mut(data, len) })
 }
 
-fn apply_weighted_blur(width: usize, height: usize, src: &[u8], dst: &mut [u8], radius: usize) {
-    let radius_i = radius as isize;
-    let sigma = (radius as f32) / 2.0;
-
-    // generate weight kernel
-    let size = radius * 2 + 1;
-    let mut kernel = vec![0.0f32; size * size];
-    let mut sum = 0.0f32;
-
-    for ky in -radius_i..=radius_i {
-        for kx in -radius_i..=radius_i {
-            let dist_sq = (kx * kx + ky * ky) as f32;
-            let weight = (-(dist_sq / (2.0 * sigma * sigma))).exp();
-            kernel[((ky + radius_i) as usize * size) + (kx + radius_i) as usize] = weight;
-            sum += weight;
-        }
-    }
+/// Apply blur with `config` to RGBA `pixels` in-place
+fn blur<T: Sample>(
+    width: u32,
+    height: u32,
+    pixels: &mut [T],
+    config: &BlurParams,
+    ctx: Option<&ProcessContext>,
+) -> Result<(), PluginError> {
+    let task = Task::new(ctx, height as u64 * config.iterations as u64);
+    let options = FilterOptions {
+        threads: config.thread_count(ctx),
+        blur_alpha: config.alpha == AlphaMode::Premultiplied,
+        edge: config.edge(),
+    };
 
-    // normalize weights
-    for w in kernel.iter_mut() {
-        *w /= sum;
+    // Image is processed in scratch buffers, so it is left unchanged if processing is cancelled
+    let mut source = pixels.to_vec();
+    if options.blur_alpha {
+        premultiply(&mut source);
     }
 
-    // apply weighted blur
-    for y in 0..height {
-        for x in 0..width {
-            let mut r_acc = 0.0f32;
-            let mut g_acc = 0.0f32;
-            let mut b_acc = 0.0f32;
+    let (width, height) = (width as usize, height as usize);
+    let window = config.window(width, height);
+    let center = (
+        config.center[0] * width.saturating_sub(1) as f32,
+        config.center[1] * height.saturating_sub(1) as f32,
+    );
+    let mut buffer = vec![T::default(); pixels.len()];
 
-            for ky in -radius_i..=radius_i {
-                for kx in -radius_i..=radius_i {
-                    let py = (y as isize + ky).clamp(0, height as isize - 1) as usize;
-                    let px = (x as isize + kx).clamp(0, width as isize - 1) as usize;
+    for _ in 0..config.iterations {
+        let (src, dst) = (&source[..], &mut buffer[..]);
+        match config.mode() {
+            BlurMode::Box => box_blur(width, height, src, dst, window, &options, &task),
+            BlurMode::Gaussian => gaussian_blur(width, height, src, dst, window, &options, &task),
+            BlurMode::Motion => motion_blur(
+                width,
+                height,
+                src,
+                dst,
+                config.angle,
+                config.length,
+                &options,
+                &task,
+            ),
+            BlurMode::Median => median_blur(width, height, src, dst, window, &options, &task),
+            BlurMode::Bilateral => bilateral_blur(
+                width,
+                height,
+                src,
+                dst,
+                window,
+                config.sigma_range,
+                &options,
+                &task,
+            ),
+            BlurMode::Unsharp => unsharp_mask(
+                width,
+                height,
+                src,
+                dst,
+                window,
+                config.amount,
+                config.threshold,
+                &options,
+                &task,
+            ),
+            BlurMode::Radial => radial_blur(
+                width,
+                height,
+                src,
+                dst,
+                center,
+                config.strength,
+                &options,
+                &task,
+            ),
+        }?;
 
-                    let weight =
-                        kernel[((ky + radius_i) as usize * size) + (kx + radius_i) as usize];
-                    let idx = (py * width + px) * 4;
-
-                    r_acc += src[idx] as f32 * weight;
-                    g_acc += src[idx + 1] as f32 * weight;
-                    b_acc += src[idx + 2] as f32 * weight;
-                }
-            }
+        mem::swap(&mut source, &mut buffer);
+    }
 
-            let out_idx = (y * width + x) * 4;
-            dst[out_idx] = r_acc.round() as u8;
-            dst[out_idx + 1] = g_acc.round() as u8;
-            dst[out_idx + 2] = b_acc.round() as u8;
-            dst[out_idx + 3] = src[out_idx + 3];
-        }
+    if options.blur_alpha {
+        unpremultiply(&mut source);
     }
+    pixels.copy_from_slice(&source);
+
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::ffi::CString;
+    use std::ffi::{CString, c_void};
+    use std::sync::atomic::AtomicBool;
 
     // Helper function to create a dummy image buffer
     fn create_test_image(width: u32, height: u32, fill_color: u8) -> Vec<u8> {
@@ -198,10 +599,72 @@ mod tests {
     }
 
     #[test]
-    fn test_process_image_null_rgba_data() {
+    fn test_plugin_info() {
+        let info = unsafe { &*plugin_info() };
+        assert_eq!(info.abi_version, ABI_VERSION);
+        assert_eq!(unsafe { CStr::from_ptr(info.name) }, c"blur");
+        assert!(!unsafe { CStr::from_ptr(info.description) }.is_empty());
+        assert_ne!(info.capabilities & capabilities::IN_PLACE, 0);
+    }
+
+    #[test]
+    fn test_params_defaults() {
+        let params: BlurParams = serde_json::from_str(r#"{ "radius": 4 }"#).unwrap();
+        assert_eq!(
+            params,
+            BlurParams {
+                radius: Some(4),
+                ..BlurParams::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_params_schema() {
+        let schema = unsafe { CStr::from_ptr(params_schema()) }.to_str().unwrap();
+        let schema: serde_json::Value = serde_json::from_str(schema).unwrap();
+        let properties: Vec<&String> = schema["properties"].as_object().unwrap().keys().collect();
+        assert_eq!(
+            properties,
+            [
+                "alpha",
+                "amount",
+                "angle",
+                "center",
+                "edge",
+                "edge_color",
+                "iterations",
+                "length",
+                "mode",
+                "radius",
+                "radius_x",
+                "radius_y",
+                "sigma",
+                "sigma_range",
+                "sigma_x",
+                "sigma_y",
+                "strength",
+                "threads",
+                "threshold",
+                "weighted"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_process_image_null_data() {
         let params =
             CString::new(r#"{ "radius": 1, "iterations": 1, "weighted": false }"#).unwrap();
-        let result = unsafe { process_image(1, 1, std::ptr::null_mut(), params.as_ptr()) };
+        let result = unsafe {
+            process_image(
+                1,
+                1,
+                PixelFormat::Rgba8 as u32,
+                std::ptr::null_mut(),
+                params.as_ptr(),
+                std::ptr::null(),
+            )
+        };
         assert_eq!(result, PluginError::NullPointer as i32);
     }
 
@@ -210,8 +673,16 @@ mod tests {
         let width = 1;
         let height = 1;
         let mut rgba_data = create_test_image(width, height, 0);
-        let result =
-            unsafe { process_image(width, height, rgba_data.as_mut_ptr(), std::ptr::null()) };
+        let result = unsafe {
+            process_image(
+                width,
+                height,
+                PixelFormat::Rgba8 as u32,
+                rgba_data.as_mut_ptr(),
+                std::ptr::null(),
+                std::ptr::null(),
+            )
+        };
         assert_eq!(result, PluginError::NullPointer as i32);
     }
 
@@ -222,20 +693,145 @@ mod tests {
         let mut rgba_data = create_test_image(width, height, 0);
         let params =
             CString::new(r#"{ "radius": 1, "iterations": 1, "weighted": false, }"#).unwrap(); // Trailing comma makes it invalid JSON
-        let result =
-            unsafe { process_image(width, height, rgba_data.as_mut_ptr(), params.as_ptr()) };
+        let result = unsafe {
+            process_image(
+                width,
+                height,
+                PixelFormat::Rgba8 as u32,
+                rgba_data.as_mut_ptr(),
+                params.as_ptr(),
+                std::ptr::null(),
+            )
+        };
         assert_eq!(result, PluginError::InvalidParams as i32);
     }
 
     #[test]
-    fn test_process_image_missing_fields_in_params() {
+    fn test_process_image_missing_fields_use_defaults() {
         let width = 1;
         let height = 1;
         let mut rgba_data = create_test_image(width, height, 0);
-        let params = CString::new(r#"{ "radius": 1 }"#).unwrap(); // Missing iterations and weighted
-        let result =
-            unsafe { process_image(width, height, rgba_data.as_mut_ptr(), params.as_ptr()) };
+        let params = CString::new(r#"{ "radius": 1 }"#).unwrap(); // Missing fields use defaults
+        let result = unsafe {
+            process_image(
+                width,
+                height,
+                PixelFormat::Rgba8 as u32,
+                rgba_data.as_mut_ptr(),
+                params.as_ptr(),
+                std::ptr::null(),
+            )
+        };
+        assert_eq!(result, PluginError::Ok as i32);
+    }
+
+    #[test]
+    fn test_process_image_error_message() {
+        let width = 1;
+        let height = 1;
+        let mut rgba_data = create_test_image(width, height, 0);
+        let params = CString::new(r#"{ "radius": "1" }"#).unwrap();
+        let result = unsafe {
+            process_image(
+                width,
+                height,
+                PixelFormat::Rgba8 as u32,
+                rgba_data.as_mut_ptr(),
+                params.as_ptr(),
+                std::ptr::null(),
+            )
+        };
         assert_eq!(result, PluginError::InvalidParams as i32);
+
+        let message = unsafe { CStr::from_ptr(last_error_message()) }.to_string_lossy();
+        assert!(message.starts_with("invalid type"), "{message}");
+        assert!(message.contains("line 1 column"), "{message}");
+    }
+
+    #[test]
+    fn test_process_image_cancelled() {
+        let width = 4;
+        let height = 4;
+        let mut rgba_data = create_test_image(width, height, 7);
+        let params = CString::new(r#"{ "radius": 1, "iterations": 1, "weighted": true }"#).unwrap();
+        let cancel_flag = AtomicBool::new(true);
+        let ctx = ProcessContext {
+            cancel_flag: &cancel_flag,
+            progress: None,
+            progress_user_data: std::ptr::null_mut(),
+            thread_count: 0,
+        };
+        let result = unsafe {
+            process_image(
+                width,
+                height,
+                PixelFormat::Rgba8 as u32,
+                rgba_data.as_mut_ptr(),
+                params.as_ptr(),
+                &ctx,
+            )
+        };
+        assert_eq!(result, PluginError::Cancelled as i32);
+    }
+
+    #[test]
+    fn test_cancelled_premultiplied_image_is_unchanged() {
+        let mut rgba_data: Vec<u8> = (0..64).map(|v| (v * 37 % 256) as u8).collect();
+        let original_data = rgba_data.clone();
+        let params = CString::new(r#"{ "radius": 1, "alpha": "premultiplied" }"#).unwrap();
+        let cancel_flag = AtomicBool::new(true);
+        let ctx = ProcessContext {
+            cancel_flag: &cancel_flag,
+            progress: None,
+            progress_user_data: std::ptr::null_mut(),
+            thread_count: 0,
+        };
+        let result = unsafe {
+            process_image(
+                4,
+                4,
+                PixelFormat::Rgba8 as u32,
+                rgba_data.as_mut_ptr(),
+                params.as_ptr(),
+                &ctx,
+            )
+        };
+        assert_eq!(result, PluginError::Cancelled as i32);
+        assert_eq!(rgba_data, original_data);
+    }
+
+    #[test]
+    fn test_process_image_reports_progress() {
+        unsafe extern "C" fn store(user_data: *mut c_void, done: u64, total: u64) {
+            let reports = unsafe { &mut *(user_data as *mut Vec<(u64, u64)>) };
+            reports.push((done, total));
+        }
+
+        let width = 3;
+        let height = 2;
+        let mut rgba_data = create_test_image(width, height, 7);
+        let params =
+            CString::new(r#"{ "radius": 1, "iterations": 2, "weighted": false }"#).unwrap();
+        let mut reports: Vec<(u64, u64)> = Vec::new();
+        let ctx = ProcessContext {
+            cancel_flag: std::ptr::null(),
+            progress: Some(store),
+            progress_user_data: (&mut reports as *mut Vec<(u64, u64)>).cast(),
+            thread_count: 0,
+        };
+        let result = unsafe {
+            process_image(
+                width,
+                height,
+                PixelFormat::Rgba8 as u32,
+                rgba_data.as_mut_ptr(),
+                params.as_ptr(),
+                &ctx,
+            )
+        };
+
+        assert_eq!(result, PluginError::Ok as i32);
+        assert_eq!(reports, [(1, 4), (2, 4), (3, 4), (4, 4)]);
     }
 
     #[test]
@@ -243,8 +839,16 @@ mod tests {
         let mut rgba_data = vec![0u8; 4];
         let params =
             CString::new(r#"{ "radius": 1, "iterations": 1, "weighted": false }"#).unwrap();
-        let result =
-            unsafe { process_image(u32::MAX, u32::MAX, rgba_data.as_mut_ptr(), params.as_ptr()) };
+        let result = unsafe {
+            process_image(
+                u32::MAX,
+                u32::MAX,
+                PixelFormat::Rgba8 as u32,
+                rgba_data.as_mut_ptr(),
+                params.as_ptr(),
+                std::ptr::null(),
+            )
+        };
 
         assert_eq!(result, PluginError::SizeIsTooBig as i32);
     }
@@ -260,10 +864,327 @@ mod tests {
         let original_data = rgba_data.clone();
         let params =
             CString::new(r#"{ "radius": 1, "iterations": 1, "weighted": false }"#).unwrap();
-        let result =
-            unsafe { process_image(width, height, rgba_data.as_mut_ptr(), params.as_ptr()) };
+        let result = unsafe {
+            process_image(
+                width,
+                height,
+                PixelFormat::Rgba8 as u32,
+                rgba_data.as_mut_ptr(),
+                params.as_ptr(),
+                std::ptr::null(),
+            )
+        };
 
         assert_eq!(result, PluginError::Ok as i32);
+        assert!(last_error_message().is_null());
         assert_ne!(rgba_data, original_data);
     }
+
+    #[test]
+    fn test_process_image_premultiplied_alpha() {
+        // Opaque red pixel next to transparent green ones
+        let mut rgba_data = vec![0, 255, 0, 0, 255, 0, 0, 255, 0, 255, 0, 0];
+        let params = CString::new(r#"{ "radius": 1, "alpha": "premultiplied" }"#).unwrap();
+        let result = unsafe {
+            process_image(
+                3,
+                1,
+                PixelFormat::Rgba8 as u32,
+                rgba_data.as_mut_ptr(),
+                params.as_ptr(),
+                std::ptr::null(),
+            )
+        };
+
+        assert_eq!(result, PluginError::Ok as i32);
+        // Alpha is blurred and transparent green does not bleed into red
+        assert_eq!(rgba_data, [255, 0, 0, 127, 255, 0, 0, 85, 255, 0, 0, 127]);
+    }
+
+    #[test]
+    fn test_params_edge() {
+        let params: BlurParams =
+            serde_json::from_str(r#"{ "edge": "reflect", "edge_color": [1, 2, 3, 4] }"#).unwrap();
+        assert_eq!(params.edge(), Some(Edge::Mirror));
+
+        let params: BlurParams = serde_json::from_str(
+            r#"{ "edge": "transparent", "alpha": "premultiplied", "edge_color": [200, 100, 0, 128] }"#,
+        )
+        .unwrap();
+        assert_eq!(params.edge(), Some(Edge::Constant([100, 50, 0, 128])));
+
+        assert_eq!(BlurParams::default().edge(), None);
+    }
+
+    #[test]
+    fn test_params_window() {
+        let window = |json: &str| {
+            serde_json::from_str::<BlurParams>(json)
+                .unwrap()
+                .window(100, 100)
+        };
+
+        assert_eq!(window("{}"), Window::square(1));
+        assert_eq!(window(r#"{ "radius": 4 }"#), Window::square(4));
+        assert_eq!(
+            window(r#"{ "radius": 4, "radius_y": 0, "sigma_x": 1.5 }"#),
+            Window {
+                radius_x: 4,
+                radius_y: 0,
+                sigma_x: 1.5,
+                sigma_y: 0.0,
+            }
+        );
+        assert_eq!(
+            window(r#"{ "weighted": true, "sigma": 2, "sigma_y": 0.5 }"#),
+            Window {
+                radius_x: 6,
+                radius_y: 2,
+                sigma_x: 2.0,
+                sigma_y: 0.5,
+            }
+        );
+
+        // Radius is limited by image size
+        let params: BlurParams =
+            serde_json::from_str(r#"{ "mode": "gaussian", "sigma": 1e30 }"#).unwrap();
+        let window = params.window(7, 3);
+        assert_eq!((window.radius_x, window.radius_y), (7, 3));
+        let params: BlurParams = serde_json::from_str(r#"{ "radius": 4000000000 }"#).unwrap();
+        assert_eq!(params.window(10, 2).radius_x, 10);
+        assert!(params.is_noop(0, 0));
+    }
+
+    #[test]
+    fn test_process_image_huge_sigma() {
+        for mode in ["gaussian", "bilateral", "unsharp", "median", "box"] {
+            let mut data = create_test_image(5, 4, 100);
+            data[0] = 0;
+            let params = CString::new(format!(
+                r#"{{ "mode": "{mode}", "sigma": 1e30, "radius_x": 4294967295 }}"#
+            ))
+            .unwrap();
+            let result = unsafe {
+                process_image(
+                    5,
+                    4,
+                    PixelFormat::Rgba8 as u32,
+                    data.as_mut_ptr(),
+                    params.as_ptr(),
+                    std::ptr::null(),
+                )
+            };
+            assert_eq!(result, PluginError::Ok as i32, "{mode}");
+        }
+    }
+
+    #[test]
+    fn test_process_image_invalid_sigma() {
+        let mut rgba_data = create_test_image(1, 1, 0);
+        let params = CString::new(r#"{ "weighted": true, "sigma_x": 0 }"#).unwrap();
+        let result = unsafe {
+            process_image(
+                1,
+                1,
+                PixelFormat::Rgba8 as u32,
+                rgba_data.as_mut_ptr(),
+                params.as_ptr(),
+                std::ptr::null(),
+            )
+        };
+        assert_eq!(result, PluginError::InvalidParams as i32);
+
+        let message = unsafe { CStr::from_ptr(last_error_message()) }.to_string_lossy();
+        assert_eq!(message, "sigma must be positive, found 0");
+    }
+
+    #[test]
+    fn test_params_mode() {
+        let mode = |json: &str| serde_json::from_str::<BlurParams>(json).unwrap().mode();
+
+        assert_eq!(mode("{}"), BlurMode::Box);
+        assert_eq!(mode(r#"{ "weighted": true }"#), BlurMode::Gaussian);
+        assert_eq!(
+            mode(r#"{ "weighted": true, "mode": "motion" }"#),
+            BlurMode::Motion
+        );
+        assert_eq!(mode(r#"{ "mode": "zoom" }"#), BlurMode::Radial);
+    }
+
+    #[test]
+    fn test_process_image_other_modes() {
+        let width = 10;
+        let height = 10;
+        let mut original_data = create_test_image(width, height, 0);
+        for (i, data) in original_data.iter_mut().enumerate() {
+            *data = ((i * 7) & 0xff) as u8;
+        }
+
+        for params in [
+            r#"{ "mode": "motion", "angle": 30, "length": 5 }"#,
+            r#"{ "mode": "radial", "center": [0.2, 0.7], "strength": 0.5 }"#,
+            r#"{ "mode": "median", "radius": 2 }"#,
+            r#"{ "mode": "bilateral", "sigma": 1.5, "sigma_range": 50 }"#,
+            r#"{ "mode": "unsharp", "radius": 2, "amount": 1.5, "threshold": 3 }"#,
+        ] {
+            let mut rgba_data = original_data.clone();
+            let params = CString::new(params).unwrap();
+            let result = unsafe {
+                process_image(
+                    width,
+                    height,
+                    PixelFormat::Rgba8 as u32,
+                    rgba_data.as_mut_ptr(),
+                    params.as_ptr(),
+                    std::ptr::null(),
+                )
+            };
+
+            assert_eq!(result, PluginError::Ok as i32);
+            assert_ne!(rgba_data, original_data);
+        }
+    }
+
+    #[test]
+    fn test_process_image_invalid_strength() {
+        let mut rgba_data = create_test_image(1, 1, 0);
+        let params = CString::new(r#"{ "mode": "radial", "strength": 2 }"#).unwrap();
+        let result = unsafe {
+            process_image(
+                1,
+                1,
+                PixelFormat::Rgba8 as u32,
+                rgba_data.as_mut_ptr(),
+                params.as_ptr(),
+                std::ptr::null(),
+            )
+        };
+        assert_eq!(result, PluginError::InvalidParams as i32);
+    }
+
+    #[test]
+    fn test_process_image_too_many_threads() {
+        let mut rgba_data = create_test_image(1, 1, 0);
+        let params = CString::new(r#"{ "threads": 100000 }"#).unwrap();
+        let result = unsafe {
+            process_image(
+                1,
+                1,
+                PixelFormat::Rgba8 as u32,
+                rgba_data.as_mut_ptr(),
+                params.as_ptr(),
+                std::ptr::null(),
+            )
+        };
+        assert_eq!(result, PluginError::InvalidParams as i32);
+    }
+
+    fn process_typed<T>(
+        width: u32,
+        height: u32,
+        format: PixelFormat,
+        data: &mut [T],
+        params: &str,
+    ) -> i32 {
+        let params = CString::new(params).unwrap();
+        unsafe {
+            process_image(
+                width,
+                height,
+                format as u32,
+                data.as_mut_ptr().cast(),
+                params.as_ptr(),
+                std::ptr::null(),
+            )
+        }
+    }
+
+    #[test]
+    fn test_high_precision_formats_match_rgba8() {
+        let (width, height) = (10, 8);
+        // Alpha is kept high, because unpremultiplying of 8-bit values with low alpha is imprecise
+        let original: Vec<u8> = (0..width * height * 4)
+            .map(|i| match i % 4 {
+                3 => (128 + i * 13 % 128) as u8,
+                _ => (i * 37 % 256) as u8,
+            })
+            .collect();
+
+        for params in [
+            r#"{ "radius": 2 }"#,
+            r#"{ "mode": "gaussian", "radius": 3, "edge": "constant", "edge_color": [200, 0, 0, 255] }"#,
+            r#"{ "mode": "gaussian", "radius": 1, "alpha": "premultiplied" }"#,
+            r#"{ "mode": "motion", "angle": 30, "length": 5 }"#,
+            r#"{ "mode": "radial", "center": [0.2, 0.7], "strength": 0.5 }"#,
+            r#"{ "mode": "median", "radius": 2 }"#,
+            r#"{ "mode": "bilateral", "sigma": 1.5, "sigma_range": 50 }"#,
+            r#"{ "mode": "unsharp", "radius": 2, "amount": 1.5 }"#,
+        ] {
+            let mut rgba8 = original.clone();
+            let mut rgba16: Vec<u16> = original.iter().map(|v| *v as u16 * 257).collect();
+            let mut rgba32f: Vec<f32> = original.iter().map(|v| *v as f32 / 255.0).collect();
+            for result in [
+                process_typed(width, height, PixelFormat::Rgba8, &mut rgba8, params),
+                process_typed(width, height, PixelFormat::Rgba16, &mut rgba16, params),
+                process_typed(width, height, PixelFormat::Rgba32F, &mut rgba32f, params),
+            ] {
+                assert_eq!(result, PluginError::Ok as i32, "{params}");
+            }
+
+            // 8-bit result is rounded after every step, so it differs from precise results slightly.
+            // Floating point values are not clamped by sharpening, so overshoots are clamped here
+            for ((v8, v16), v32) in rgba8.iter().zip(&rgba16).zip(&rgba32f) {
+                assert!((*v16 as f32 / 257.0 - *v8 as f32).abs() <= 2.0, "{params}");
+                assert!((v32.min(1.0) * 255.0 - *v8 as f32).abs() <= 2.0, "{params}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_rgba16_keeps_precision() {
+        // Gradient of 16-bit values which are equal in 8 bits stays distinguishable
+        let mut data: Vec<u16> = (0..8).flat_map(|x| [1000 + x * 8, 0, 0, 65535]).collect();
+        let result = process_typed(8, 1, PixelFormat::Rgba16, &mut data, r#"{ "radius": 1 }"#);
+        assert_eq!(result, PluginError::Ok as i32);
+        let red: Vec<u16> = data.chunks(4).map(|pixel| pixel[0]).collect();
+        assert_eq!(red, [1004, 1008, 1016, 1024, 1032, 1040, 1048, 1052]);
+    }
+
+    #[test]
+    fn test_unsupported_pixel_format() {
+        let mut data = vec![7u8; 4];
+        let result = process_typed(2, 2, PixelFormat::Gray8, &mut data, r#"{ "radius": 1 }"#);
+        assert_eq!(result, PluginError::UnsupportedPixelFormat as i32);
+        assert_eq!(data, [7; 4]);
+
+        let params = CString::new(r#"{ "radius": 1 }"#).unwrap();
+        let result = unsafe {
+            process_image(
+                1,
+                1,
+                42,
+                data.as_mut_ptr(),
+                params.as_ptr(),
+                std::ptr::null(),
+            )
+        };
+        assert_eq!(result, PluginError::UnsupportedPixelFormat as i32);
+    }
+
+    #[test]
+    fn test_unaligned_data() {
+        let mut data = vec![0u16; 9];
+        let params = CString::new(r#"{ "radius": 1 }"#).unwrap();
+        let result = unsafe {
+            process_image(
+                2,
+                1,
+                PixelFormat::Rgba16 as u32,
+                data.as_mut_ptr().cast::<u8>().wrapping_add(1),
+                params.as_ptr(),
+                std::ptr::null(),
+            )
+        };
+        assert_eq!(result, PluginError::InvalidData as i32);
+    }
 }
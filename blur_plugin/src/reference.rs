@@ -0,0 +1,117 @@
+//! Original naive O(w·h·r²) blur implementations
+//!
+//! Kept to check accuracy of optimized filters and to compare their performance in benchmarks
+use plugin_errors::PluginError;
+
+use crate::task::Task;
+
+/// Box blur averaging pixels of square window, which shrinks at image borders
+pub fn apply_box_blur(
+    width: usize,
+    height: usize,
+    src: &[u8],
+    dst: &mut [u8],
+    radius: usize,
+    task: &Task,
+) -> Result<(), PluginError> {
+    for y in 0..height {
+        task.check()?;
+
+        for x in 0..width {
+            let mut r_acc = 0u32;
+            let mut g_acc = 0u32;
+            let mut b_acc = 0u32;
+            let mut count = 0u32;
+
+            for ky in (y as isize - radius as isize)..=(y as isize + radius as isize) {
+                for kx in (x as isize - radius as isize)..=(x as isize + radius as isize) {
+                    if ky >= 0 && ky < height as isize && kx >= 0 && kx < width as isize {
+                        let idx = (ky as usize * width + kx as usize) * 4;
+                        r_acc += src[idx] as u32;
+                        g_acc += src[idx + 1] as u32;
+                        b_acc += src[idx + 2] as u32;
+                        count += 1;
+                    }
+                }
+            }
+
+            let out_idx = (y * width + x) * 4;
+            dst[out_idx] = (r_acc / count) as u8;
+            dst[out_idx + 1] = (g_acc / count) as u8;
+            dst[out_idx + 2] = (b_acc / count) as u8;
+            dst[out_idx + 3] = src[out_idx + 3];
+        }
+
+        task.row_done();
+    }
+
+    Ok(())
+}
+
+/// Gaussian blur with 2D kernel and `sigma = radius / 2`, coordinates outside of image are clamped
+pub fn apply_weighted_blur(
+    width: usize,
+    height: usize,
+    src: &[u8],
+    dst: &mut [u8],
+    radius: usize,
+    task: &Task,
+) -> Result<(), PluginError> {
+    let radius_i = radius as isize;
+    let sigma = (radius as f32) / 2.0;
+
+    // generate weight kernel
+    let size = radius * 2 + 1;
+    let mut kernel = vec![0.0f32; size * size];
+    let mut sum = 0.0f32;
+
+    for ky in -radius_i..=radius_i {
+        for kx in -radius_i..=radius_i {
+            let dist_sq = (kx * kx + ky * ky) as f32;
+            let weight = (-(dist_sq / (2.0 * sigma * sigma))).exp();
+            kernel[((ky + radius_i) as usize * size) + (kx + radius_i) as usize] = weight;
+            sum += weight;
+        }
+    }
+
+    // normalize weights
+    for w in kernel.iter_mut() {
+        *w /= sum;
+    }
+
+    // apply weighted blur
+    for y in 0..height {
+        task.check()?;
+
+        for x in 0..width {
+            let mut r_acc = 0.0f32;
+            let mut g_acc = 0.0f32;
+            let mut b_acc = 0.0f32;
+
+            for ky in -radius_i..=radius_i {
+                for kx in -radius_i..=radius_i {
+                    let py = (y as isize + ky).clamp(0, height as isize - 1) as usize;
+                    let px = (x as isize + kx).clamp(0, width as isize - 1) as usize;
+
+                    let weight =
+                        kernel[((ky + radius_i) as usize * size) + (kx + radius_i) as usize];
+                    let idx = (py * width + px) * 4;
+
+                    r_acc += src[idx] as f32 * weight;
+                    g_acc += src[idx + 1] as f32 * weight;
+                    b_acc += src[idx + 2] as f32 * weight;
+                }
+            }
+
+            let out_idx = (y * width + x) * 4;
+            dst[out_idx] = r_acc.round() as u8;
+            dst[out_idx + 1] = g_acc.round() as u8;
+            dst[out_idx + 2] = b_acc.round() as u8;
+            dst[out_idx + 3] = src[out_idx + 3];
+        }
+
+        task.row_done();
+    }
+
+    Ok(())
+}
@@ -0,0 +1,183 @@
+//! Channel value types of supported pixel formats
+//!
+//! Filters are generic over channel type, so 16-bit and floating point images are processed
+//! without conversion to 8 bits. Params given in 8-bit units, like colors and thresholds,
+//! are scaled to channel range with `Sample::from_u8` and `Sample::SCALE`
+use std::cmp::Ordering;
+
+/// Channel value of RGBA image: `u8`, `u16` or `f32`
+pub trait Sample: Copy + Default + PartialEq + Send + Sync + 'static {
+    /// Channel value corresponding to 8-bit value 1: 1 for `u8`, 257 for `u16` and `1 / 255` for `f32`
+    const SCALE: f32;
+
+    /// Number of distinct values if it is small enough for lookup tables and histograms
+    const LEVELS: Option<usize>;
+
+    /// Value as floating point number in channel units
+    fn to_f32(self) -> f32;
+
+    /// Nearest channel value, integer values are clamped to their range
+    fn from_f32(value: f32) -> Self;
+
+    /// Average of `count` values with given `sum`, integer averages are truncated
+    fn from_mean(sum: f64, count: u64) -> Self;
+
+    /// Channel value corresponding to 8-bit value
+    fn from_u8(value: u8) -> Self;
+
+    /// Color channel multiplied by alpha
+    fn premultiply(self, alpha: Self) -> Self;
+
+    /// Premultiplied color channel divided by alpha, fully transparent pixels get black color
+    fn unpremultiply(self, alpha: Self) -> Self;
+
+    /// Total order of values, floating point NaN is ordered after all numbers
+    fn total_cmp(&self, other: &Self) -> Ordering;
+}
+
+impl Sample for u8 {
+    const SCALE: f32 = 1.0;
+    const LEVELS: Option<usize> = Some(256);
+
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+
+    fn from_f32(value: f32) -> Self {
+        value.round() as u8
+    }
+
+    fn from_mean(sum: f64, count: u64) -> Self {
+        (sum as u64 / count) as u8
+    }
+
+    fn from_u8(value: u8) -> Self {
+        value
+    }
+
+    fn premultiply(self, alpha: Self) -> Self {
+        ((self as u32 * alpha as u32 + 127) / 255) as u8
+    }
+
+    fn unpremultiply(self, alpha: Self) -> Self {
+        match alpha as u32 {
+            0 => 0,
+            alpha => ((self as u32 * 255 + alpha / 2) / alpha).min(255) as u8,
+        }
+    }
+
+    fn total_cmp(&self, other: &Self) -> Ordering {
+        self.cmp(other)
+    }
+}
+
+impl Sample for u16 {
+    const SCALE: f32 = 257.0;
+    const LEVELS: Option<usize> = None;
+
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+
+    fn from_f32(value: f32) -> Self {
+        value.round() as u16
+    }
+
+    fn from_mean(sum: f64, count: u64) -> Self {
+        (sum as u64 / count) as u16
+    }
+
+    fn from_u8(value: u8) -> Self {
+        value as u16 * 257
+    }
+
+    fn premultiply(self, alpha: Self) -> Self {
+        ((self as u64 * alpha as u64 + 32767) / 65535) as u16
+    }
+
+    fn unpremultiply(self, alpha: Self) -> Self {
+        match alpha as u64 {
+            0 => 0,
+            alpha => ((self as u64 * 65535 + alpha / 2) / alpha).min(65535) as u16,
+        }
+    }
+
+    fn total_cmp(&self, other: &Self) -> Ordering {
+        self.cmp(other)
+    }
+}
+
+impl Sample for f32 {
+    const SCALE: f32 = 1.0 / 255.0;
+    const LEVELS: Option<usize> = None;
+
+    fn to_f32(self) -> f32 {
+        self
+    }
+
+    fn from_f32(value: f32) -> Self {
+        value
+    }
+
+    fn from_mean(sum: f64, count: u64) -> Self {
+        (sum / count as f64) as f32
+    }
+
+    fn from_u8(value: u8) -> Self {
+        value as f32 / 255.0
+    }
+
+    fn premultiply(self, alpha: Self) -> Self {
+        self * alpha
+    }
+
+    fn unpremultiply(self, alpha: Self) -> Self {
+        match alpha > 0.0 {
+            true => self / alpha,
+            false => 0.0,
+        }
+    }
+
+    fn total_cmp(&self, other: &Self) -> Ordering {
+        f32::total_cmp(self, other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_u8() {
+        assert_eq!(u16::from_u8(255), u16::MAX);
+        assert_eq!(u16::from_u8(1), 257);
+        assert_eq!(f32::from_u8(255), 1.0);
+        assert_eq!(u8::from_u8(7), 7);
+    }
+
+    #[test]
+    fn test_premultiply_u16() {
+        assert_eq!(40000u16.premultiply(u16::MAX), 40000);
+        assert_eq!(40000u16.premultiply(32768), 20000);
+        assert!(20000u16.unpremultiply(32768).abs_diff(40000) <= 1);
+        assert_eq!(20000u16.unpremultiply(u16::MAX), 20000);
+        assert_eq!(100u16.unpremultiply(0), 0);
+    }
+
+    #[test]
+    fn test_premultiply_f32() {
+        assert_eq!(0.5f32.premultiply(0.5), 0.25);
+        assert_eq!(0.25f32.unpremultiply(0.5), 0.5);
+        // HDR values above 1 are kept
+        assert_eq!(4.0f32.unpremultiply(1.0), 4.0);
+        assert_eq!(0.5f32.unpremultiply(0.0), 0.0);
+    }
+
+    #[test]
+    fn test_from_f32_clamps_integers() {
+        assert_eq!(u8::from_f32(300.0), 255);
+        assert_eq!(u16::from_f32(-3.0), 0);
+        assert_eq!(u16::from_f32(1000.4), 1000);
+        assert_eq!(f32::from_f32(1.5), 1.5);
+    }
+}
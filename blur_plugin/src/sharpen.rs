@@ -0,0 +1,108 @@
+//! Sharpening by unsharp mask built on Gaussian blur
+use plugin_errors::PluginError;
+
+use crate::filters::{FilterOptions, Window, gaussian_blur};
+use crate::sample::Sample;
+use crate::task::Task;
+
+/// Unsharp mask adding difference between image and its Gaussian blur multiplied by `amount`
+///
+/// Channels with absolute difference less than `threshold` given in 8-bit units are left unchanged,
+/// so flat areas are not sharpened together with noise. Result is clamped to range of channel type,
+/// floating point channels are limited from below only to keep HDR highlights
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn unsharp_mask<T: Sample>(
+    width: usize,
+    height: usize,
+    src: &[T],
+    dst: &mut [T],
+    window: Window,
+    amount: f32,
+    threshold: u8,
+    options: &FilterOptions,
+    task: &Task,
+) -> Result<(), PluginError> {
+    gaussian_blur(width, height, src, dst, window, options, task)?;
+
+    let channels = options.channels();
+    let threshold = threshold as f32 * T::SCALE;
+    for (out, original) in dst.chunks_exact_mut(4).zip(src.chunks_exact(4)) {
+        for c in 0..channels {
+            let value = original[c].to_f32();
+            let difference = value - out[c].to_f32();
+            out[c] = match difference.abs() >= threshold {
+                true => T::from_f32((value + amount * difference).max(0.0)),
+                false => original[c],
+            };
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Row of pixels with gray values of `values`
+    fn gray_row(values: &[u8]) -> Vec<u8> {
+        values.iter().flat_map(|v| [*v, *v, *v, 255]).collect()
+    }
+
+    fn sharpen(src: &[u8], amount: f32, threshold: u8) -> Vec<u8> {
+        let width = src.len() / 4;
+        let task = Task::new(None, 1);
+        let mut dst = vec![0; src.len()];
+        unsharp_mask(
+            width,
+            1,
+            src,
+            &mut dst,
+            Window::square(2),
+            amount,
+            threshold,
+            &FilterOptions::default(),
+            &task,
+        )
+        .unwrap();
+        dst
+    }
+
+    #[test]
+    fn test_unsharp_mask_increases_contrast_at_edge() {
+        let src = gray_row(&[50, 50, 50, 50, 200, 200, 200, 200]);
+        let dst = sharpen(&src, 1.0, 0);
+
+        // Dark side of edge becomes darker and light side lighter
+        assert!(dst[3 * 4] < 50, "{dst:?}");
+        assert!(dst[4 * 4] > 200, "{dst:?}");
+        // Alpha is copied
+        assert!(dst.chunks(4).all(|pixel| pixel[3] == 255));
+    }
+
+    #[test]
+    fn test_unsharp_mask_clamps_result() {
+        let src = gray_row(&[0, 0, 0, 255, 255, 255]);
+        let dst = sharpen(&src, 10.0, 0);
+        assert_eq!(dst, src);
+    }
+
+    #[test]
+    fn test_unsharp_mask_keeps_flat_area() {
+        let src = gray_row(&[90; 6]);
+        assert_eq!(sharpen(&src, 3.0, 0), src);
+    }
+
+    #[test]
+    fn test_unsharp_mask_threshold() {
+        let src = gray_row(&[100, 100, 104, 104, 100, 100]);
+        assert_eq!(sharpen(&src, 2.0, 10), src);
+        assert_ne!(sharpen(&src, 2.0, 0), src);
+    }
+
+    #[test]
+    fn test_unsharp_mask_zero_amount_keeps_image() {
+        let src = gray_row(&[10, 200, 30, 170, 90]);
+        assert_eq!(sharpen(&src, 0.0, 0), src);
+    }
+}
@@ -0,0 +1,41 @@
+//! Cancellation and progress state shared by blur filters
+use std::sync::Mutex;
+
+use plugin_abi::{ProcessContext, is_cancelled, report_progress};
+use plugin_errors::PluginError;
+
+/// Cancellation checks and progress reporting of single `process_image` call
+///
+/// Shared by all threads of the call. Progress is reported under lock,
+/// so host receives reports one at a time in increasing order
+pub struct Task<'a> {
+    ctx: Option<&'a ProcessContext>,
+    done_rows: Mutex<u64>,
+    total_rows: u64,
+}
+
+impl<'a> Task<'a> {
+    /// Create task which processes `total_rows` rows in total, `ctx` is context received from host
+    pub fn new(ctx: Option<&'a ProcessContext>, total_rows: u64) -> Self {
+        Task {
+            ctx,
+            done_rows: Mutex::new(0),
+            total_rows,
+        }
+    }
+
+    /// Check cancellation before processing a row
+    pub fn check(&self) -> Result<(), PluginError> {
+        match is_cancelled(self.ctx) {
+            true => Err(PluginError::Cancelled),
+            false => Ok(()),
+        }
+    }
+
+    /// Mark row as processed and report progress to host
+    pub fn row_done(&self) {
+        let mut done = self.done_rows.lock().unwrap_or_else(|e| e.into_inner());
+        *done += 1;
+        report_progress(self.ctx, *done, self.total_rows);
+    }
+}
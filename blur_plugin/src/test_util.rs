@@ -0,0 +1,29 @@
+//! Helpers shared by tests of filters
+use crate::filters::{Edge, FilterOptions};
+
+/// Deterministic pseudo-random RGBA image
+pub(crate) fn noise_image(width: usize, height: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491_u32;
+    (0..width * height * 4)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            (state >> 24) as u8
+        })
+        .collect()
+}
+
+/// Options of filter running on `threads` threads with given edge mode
+pub(crate) fn options(threads: usize, edge: Option<Edge>) -> FilterOptions {
+    FilterOptions {
+        threads,
+        edge,
+        ..FilterOptions::default()
+    }
+}
+
+/// Maximum difference of corresponding channels of two images
+pub(crate) fn max_difference(a: &[u8], b: &[u8]) -> u8 {
+    a.iter().zip(b).map(|(a, b)| a.abs_diff(*b)).max().unwrap()
+}
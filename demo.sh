@@ -39,7 +39,18 @@ run_processor() {
 run_processor "mirror" "demo/mirror_h.json" "out_mirror_h.png" "Applying mirror horizontal"
 run_processor "mirror" "demo/mirror_v.json" "out_mirror_v.png" "Applying mirror vertical"
 run_processor "mirror" "demo/mirror_both.json" "out_mirror_both.png" "Applying mirror both"
+run_processor "mirror" "demo/rotate_90.json" "out_rotate_90.png" "Applying rotation by 90 degrees"
 run_processor "blur" "demo/blur_box.json" "out_blur_box.png" "Applying box blur"
 run_processor "blur" "demo/blur_gauss.json" "out_blur_gauss.png" "Applying Gaussian blur"
+run_processor "resize" "demo/crop.json" "out_crop.png" "Applying crop"
+run_processor "resize" "demo/resize_200.json" "out_resize_200.png" "Applying resize to 200px width"
+
+echo "Applying pipeline: box blur, then mirror horizontal..."
+./target/$TARGET_PROFILE/image_processor \
+    --input "$INPUT_IMAGE" \
+    --output "$OUTPUT_DIR/out_pipeline.png" \
+    --step "blur=demo/blur_box.json" \
+    --step "mirror=demo/mirror_h.json" \
+    $PLUGIN_PATH_ARG
 
 echo "Done! Results are in $OUTPUT_DIR"
\ No newline at end of file
@@ -0,0 +1,231 @@
+//! Eight orientations of image: rotations by multiples of 90 degrees with optional mirroring
+//!
+//! Shared by mirror plugin and app, which normalizes orientation of images with EXIF orientation tag
+#![deny(unreachable_pub)]
+#![warn(missing_docs)]
+
+use serde::Deserialize;
+
+/// Orientation of result relative to source image
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Orientation {
+    /// Image is unchanged
+    #[default]
+    #[serde(alias = "identity")]
+    Normal,
+    /// Mirrored left to right
+    #[serde(alias = "mirror_horizontal")]
+    FlipHorizontal,
+    /// Mirrored upside down
+    #[serde(alias = "mirror_vertical")]
+    FlipVertical,
+    /// Rotated by 90 degrees clockwise
+    #[serde(rename = "rotate_90")]
+    Rotate90,
+    /// Rotated by 180 degrees
+    #[serde(rename = "rotate_180")]
+    Rotate180,
+    /// Rotated by 270 degrees clockwise
+    #[serde(rename = "rotate_270")]
+    Rotate270,
+    /// Mirrored along main diagonal, top left corner stays in place
+    Transpose,
+    /// Mirrored along anti-diagonal, top right corner moves to bottom left
+    Transverse,
+}
+
+/// Mapping of result pixel coordinates to source pixel ones
+///
+/// Result pixel `(x, y)` takes source pixel `(a, b)`, where `(a, b)` is `(y, x)` for `transpose`
+/// and `(x, y)` otherwise, and then `a` and `b` are mirrored if `flip_x` and `flip_y` are set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transform {
+    /// Swap coordinates, so result width is source height
+    pub transpose: bool,
+    /// Mirror source column
+    pub flip_x: bool,
+    /// Mirror source row
+    pub flip_y: bool,
+}
+
+impl Orientation {
+    /// Orientation which should be applied to pixels stored with EXIF orientation tag `value`
+    /// to show image upright, `None` for invalid tag values
+    pub fn from_exif(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(Orientation::Normal),
+            2 => Some(Orientation::FlipHorizontal),
+            3 => Some(Orientation::Rotate180),
+            4 => Some(Orientation::FlipVertical),
+            5 => Some(Orientation::Transpose),
+            6 => Some(Orientation::Rotate90),
+            7 => Some(Orientation::Transverse),
+            8 => Some(Orientation::Rotate270),
+            _ => None,
+        }
+    }
+}
+
+impl From<Orientation> for Transform {
+    fn from(orientation: Orientation) -> Self {
+        let (transpose, flip_x, flip_y) = match orientation {
+            Orientation::Normal => (false, false, false),
+            Orientation::FlipHorizontal => (false, true, false),
+            Orientation::FlipVertical => (false, false, true),
+            Orientation::Rotate180 => (false, true, true),
+            Orientation::Transpose => (true, false, false),
+            Orientation::Rotate90 => (true, false, true),
+            Orientation::Rotate270 => (true, true, false),
+            Orientation::Transverse => (true, true, true),
+        };
+        Transform {
+            transpose,
+            flip_x,
+            flip_y,
+        }
+    }
+}
+
+impl Transform {
+    /// Transform followed by mirroring of its result
+    pub fn then_flip(self, horizontal: bool, vertical: bool) -> Self {
+        // Result columns come from source rows for transposed images and vice versa
+        let (flip_columns, flip_rows) = match self.transpose {
+            false => (horizontal, vertical),
+            true => (vertical, horizontal),
+        };
+        Transform {
+            transpose: self.transpose,
+            flip_x: self.flip_x ^ flip_columns,
+            flip_y: self.flip_y ^ flip_rows,
+        }
+    }
+
+    /// Dimensions of result for source image of `width` x `height`
+    pub fn output_size(&self, width: u32, height: u32) -> (u32, u32) {
+        match self.transpose {
+            true => (height, width),
+            false => (width, height),
+        }
+    }
+
+    /// Check if transform keeps every pixel in place
+    pub fn is_identity(&self) -> bool {
+        !self.transpose && !self.flip_x && !self.flip_y
+    }
+
+    /// Write transformed image `src` of `width` x `height` pixels to `dst`.
+    /// Every pixel is `pixel_size` values of any type, e.g. 4 channels of RGBA image or bytes of pixel
+    pub fn apply<T: Copy>(
+        &self,
+        src: &[T],
+        width: usize,
+        height: usize,
+        pixel_size: usize,
+        dst: &mut [T],
+    ) {
+        let (out_width, _) = self.output_size(width as u32, height as u32);
+        for (idx, pixel) in dst.chunks_exact_mut(pixel_size).enumerate() {
+            let (x, y) = (idx % out_width as usize, idx / out_width as usize);
+            let (a, b) = match self.transpose {
+                true => (y, x),
+                false => (x, y),
+            };
+            let a = if self.flip_x { width - 1 - a } else { a };
+            let b = if self.flip_y { height - 1 - b } else { b };
+            let src_idx = (b * width + a) * pixel_size;
+            pixel.copy_from_slice(&src[src_idx..src_idx + pixel_size]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Red channel of pixels of 3x2 image
+    const SRC: [[u8; 3]; 2] = [[1, 2, 3], [4, 5, 6]];
+
+    fn transform(orientation: Orientation) -> (u32, u32, Vec<u8>) {
+        let src: Vec<u8> = SRC.iter().flatten().flat_map(|v| [*v, 0, 0, 255]).collect();
+        let transform = Transform::from(orientation);
+        let (width, height) = transform.output_size(3, 2);
+        let mut dst = vec![0; src.len()];
+        transform.apply(&src, 3, 2, 4, &mut dst);
+        (width, height, dst.chunks(4).map(|pixel| pixel[0]).collect())
+    }
+
+    #[test]
+    fn test_pixel_size() {
+        // Single channel pixels and 2-byte pixels are moved as a whole
+        let transform = Transform::from(Orientation::Rotate90);
+        let mut dst = [0u8; 6];
+        transform.apply(&[1, 2, 3, 4, 5, 6], 3, 2, 1, &mut dst);
+        assert_eq!(dst, [4, 1, 5, 2, 6, 3]);
+
+        let mut dst = [0u8; 4];
+        transform.apply(&[1, 2, 3, 4], 2, 1, 2, &mut dst);
+        assert_eq!(dst, [1, 2, 3, 4]);
+        Transform::from(Orientation::FlipHorizontal).apply(&[1, 2, 3, 4], 2, 1, 2, &mut dst);
+        assert_eq!(dst, [3, 4, 1, 2]);
+    }
+
+    #[test]
+    fn test_all_orientations() {
+        for (orientation, expected) in [
+            (Orientation::Normal, (3, 2, vec![1, 2, 3, 4, 5, 6])),
+            (Orientation::FlipHorizontal, (3, 2, vec![3, 2, 1, 6, 5, 4])),
+            (Orientation::FlipVertical, (3, 2, vec![4, 5, 6, 1, 2, 3])),
+            (Orientation::Rotate180, (3, 2, vec![6, 5, 4, 3, 2, 1])),
+            (Orientation::Rotate90, (2, 3, vec![4, 1, 5, 2, 6, 3])),
+            (Orientation::Rotate270, (2, 3, vec![3, 6, 2, 5, 1, 4])),
+            (Orientation::Transpose, (2, 3, vec![1, 4, 2, 5, 3, 6])),
+            (Orientation::Transverse, (2, 3, vec![6, 3, 5, 2, 4, 1])),
+        ] {
+            assert_eq!(transform(orientation), expected, "{orientation:?}");
+        }
+    }
+
+    #[test]
+    fn test_then_flip() {
+        let flipped = |orientation: Orientation, horizontal, vertical| {
+            Transform::from(orientation).then_flip(horizontal, vertical)
+        };
+
+        assert_eq!(
+            flipped(Orientation::Normal, true, true),
+            Orientation::Rotate180.into()
+        );
+        assert_eq!(
+            flipped(Orientation::Rotate90, true, false),
+            Orientation::Transpose.into()
+        );
+        assert_eq!(
+            flipped(Orientation::Rotate90, false, true),
+            Orientation::Transverse.into()
+        );
+        assert_eq!(
+            flipped(Orientation::Transpose, false, false),
+            Orientation::Transpose.into()
+        );
+    }
+
+    #[test]
+    fn test_from_exif() {
+        assert_eq!(Orientation::from_exif(1), Some(Orientation::Normal));
+        assert_eq!(Orientation::from_exif(6), Some(Orientation::Rotate90));
+        assert_eq!(Orientation::from_exif(8), Some(Orientation::Rotate270));
+        assert_eq!(Orientation::from_exif(0), None);
+        assert_eq!(Orientation::from_exif(9), None);
+        assert!(Transform::from(Orientation::Normal).is_identity());
+    }
+
+    #[test]
+    fn test_params_names() {
+        let orientation: Orientation = serde_json::from_str(r#""rotate_90""#).unwrap();
+        assert_eq!(orientation, Orientation::Rotate90);
+        let orientation: Orientation = serde_json::from_str(r#""mirror_horizontal""#).unwrap();
+        assert_eq!(orientation, Orientation::FlipHorizontal);
+    }
+}
@@ -1,71 +1,684 @@
 //! CLI arguments of app
-use std::path::PathBuf;
+use std::{
+    env,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
 
-use clap::Parser;
+use clap::{
+    ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, error::ErrorKind, value_parser,
+};
+use image::{
+    ImageFormat,
+    codecs::png::{CompressionType, FilterType},
+};
+use serde_json::Value;
 
-use crate::error::AppError;
+use crate::{
+    batch, discovery,
+    error::AppError,
+    image_io::{self, BitDepth, EncodeOptions},
+    mask::{self, Region},
+    metadata::MetadataKind,
+    params,
+};
+
+/// Environment variable with additional plugins directories
+pub const PLUGIN_PATH_ENV: &str = "IMAGE_PROCESSOR_PLUGIN_PATH";
 
 /// CLI arguments struct
 #[derive(Parser, Debug)]
-#[command(about = "Image Converter with Plugin System")]
+#[command(
+    about = "Image Converter with Plugin System",
+    subcommand_negates_reqs = true
+)]
 pub struct Args {
-    /// Path to input image
-    #[arg(long, value_name = "FILE")]
-    pub input: PathBuf,
+    /// Additional command. Image is processed if no command is given
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Path to input image, directory with images or glob pattern like `photos/*.jpg`
+    #[arg(long, value_name = "FILE|DIR|PATTERN", required = true)]
+    pub input: Option<PathBuf>,
+
+    /// Path to save result. For directory or pattern input it is a directory to save results to
+    #[arg(long, value_name = "FILE|DIR", required = true)]
+    pub output: Option<PathBuf>,
+
+    /// Output file name template for directory or pattern input.
+    /// Supports `{name}`, `{stem}`, `{ext}` and `{index}` placeholders,
+    /// with `--format` `{name}` and `{ext}` take extension of output format
+    #[arg(long, default_value = "{name}", value_name = "TEMPLATE")]
+    pub output_template: String,
 
-    /// Path to save result
-    #[arg(long, value_name = "FILE")]
-    pub output: PathBuf,
+    /// Number of images processed in parallel for directory or pattern input, 0 means number of CPUs
+    #[arg(long, default_value_t = 0, value_name = "N")]
+    pub jobs: usize,
 
     /// Name of image conversion plugin
-    #[arg(long, value_name = "PLUGIN_NAME")]
+    #[arg(
+        long,
+        value_name = "PLUGIN_NAME",
+        required_unless_present = "step",
+        conflicts_with = "step"
+    )]
+    pub plugin: Option<String>,
+
+    /// Path to file with params of conversion plugin. Plugin defaults are used for omitted params
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "plugin",
+        conflicts_with = "step"
+    )]
+    pub params: Option<PathBuf>,
+
+    /// JSON object with params of conversion plugin, merged over params file. Can be repeated.
+    /// With `--step` it applies to the step given before it
+    #[arg(
+        long,
+        value_name = "JSON",
+        value_parser = params::parse_params_json
+    )]
+    pub params_json: Vec<Value>,
+
+    /// Single param of conversion plugin, merged over params file and `--params-json`.
+    /// Can be repeated. Nested params are set with dots like `crop.x=10`.
+    /// With `--step` it applies to the step given before it
+    #[arg(
+        long,
+        value_name = "KEY=VALUE",
+        value_parser = params::parse_param
+    )]
+    pub param: Vec<Value>,
+
+    /// Pipeline step as `PLUGIN_NAME` or `PLUGIN_NAME=PARAMS_FILE`, plugin defaults are used for omitted params.
+    /// Can be repeated, steps are applied in given order
+    #[arg(long, value_name = "PLUGIN_NAME[=FILE]", value_parser = parse_step)]
+    pub step: Vec<StepArgs>,
+
+    /// Process only part of image inside rectangle, original image is kept outside. Can be repeated
+    #[arg(long, value_name = "X,Y,WIDTH,HEIGHT", value_parser = mask::parse_region)]
+    pub region: Vec<Region>,
+
+    /// Grayscale image of input size: pipeline result is used where mask is white,
+    /// original image is kept where it is black, other values blend them
+    #[arg(long, value_name = "FILE", conflicts_with = "region")]
+    pub mask: Option<PathBuf>,
+
+    /// Keep pixels as they are stored in input file instead of rotating image
+    /// according to its EXIF orientation tag
+    #[arg(long)]
+    pub no_auto_orient: bool,
+
+    /// Do not copy metadata of input image (EXIF, ICC color profile, XMP) to result
+    #[arg(long, conflicts_with = "keep_metadata")]
+    pub strip_metadata: bool,
+
+    /// Copy only listed kinds of input image metadata to result, comma separated.
+    /// PNG, JPEG and WebP store all kinds, TIFF stores only `icc`.
+    /// Saving fails if output format can not store listed kind, other metadata is dropped with warning
+    #[arg(long, value_name = "KINDS", value_delimiter = ',')]
+    pub keep_metadata: Option<Vec<MetadataKind>>,
+
+    /// Output image format like `png` or `jpg`. Output file extension must match it if present.
+    /// By default format is chosen by output file extension
+    #[arg(long, value_name = "FORMAT", value_parser = image_io::parse_format)]
+    pub format: Option<ImageFormat>,
+
+    /// Quality of JPEG result from 1 to 100
+    #[arg(long, default_value_t = 75, value_name = "1-100", value_parser = value_parser!(u8).range(1..=100))]
+    pub jpeg_quality: u8,
+
+    /// Compression of PNG result: `fast`, `default`, `best`, `none` or level from 1 to 9
+    #[arg(long, default_value = "fast", value_name = "LEVEL", value_parser = image_io::parse_png_compression)]
+    pub png_compression: CompressionType,
+
+    /// Filter of PNG rows before compression: `none`, `sub`, `up`, `avg`, `paeth` or `adaptive`
+    #[arg(long, default_value = "adaptive", value_name = "FILTER", value_parser = image_io::parse_png_filter)]
+    pub png_filter: FilterType,
+
+    /// Quality of lossy WebP result from 1 to 100. WebP is saved lossless by default,
+    /// lossy WebP encoding is not supported yet, so saving WebP with this option fails
+    #[arg(long, value_name = "1-100", value_parser = value_parser!(u8).range(1..=100))]
+    pub webp_quality: Option<u8>,
+
+    /// Bits per channel of result, 16 is supported by PNG and TIFF. Bit depth of processed image by default
+    #[arg(long, value_name = "BITS")]
+    pub bit_depth: Option<BitDepth>,
+
+    /// Plugins directories separated by `:` (`;` on Windows).
+    /// Directories from `IMAGE_PROCESSOR_PLUGIN_PATH` environment variable are searched after them
+    #[arg(
+        long,
+        default_value = "target/debug",
+        value_name = "DIRS",
+        global = true
+    )]
+    pub plugin_path: OsString,
+
+    /// Number of threads every plugin call is recommended to use. By default plugins use all CPUs,
+    /// or their share if several images are processed in parallel
+    #[arg(long, value_name = "N")]
+    pub plugin_threads: Option<u32>,
+
+    /// Run every plugin call in a separate worker process, so plugin crash does not break the app
+    #[arg(long)]
+    pub isolate: bool,
+
+    /// Time limit for every plugin call in seconds. Plugin is asked to stop when limit is exceeded,
+    /// worker process of isolated plugin is killed
+    #[arg(long, value_name = "SECONDS", value_parser = parse_timeout)]
+    pub timeout: Option<Duration>,
+}
+
+/// Commands which do not process images
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// List plugins found in plugins directories
+    ListPlugins,
+
+    /// Show detailed information about plugin
+    PluginInfo {
+        /// Name of plugin
+        #[arg(value_name = "PLUGIN_NAME")]
+        plugin: String,
+
+        /// Print only JSON Schema of plugin params
+        #[arg(long)]
+        schema: bool,
+    },
+}
+
+/// Single pipeline step: plugin name and its params
+#[derive(Debug, Clone, PartialEq)]
+pub struct StepArgs {
+    /// Name of image conversion plugin
     pub plugin: String,
 
     /// Path to file with params of conversion plugin
-    #[arg(long, value_name = "FILE")]
-    pub params: PathBuf,
+    pub params: Option<PathBuf>,
 
-    /// Path to plugins directory
-    #[arg(long, default_value = "target/debug", value_name = "DIR")]
-    pub plugin_path: PathBuf,
+    /// JSON objects merged over params file in given order
+    pub inline_params: Vec<Value>,
 }
 
 impl Args {
+    /// Parse CLI arguments of app, exit with usage message if they are invalid
+    pub fn parse_args() -> Self {
+        Self::try_parse_args(env::args_os()).unwrap_or_else(|error| error.exit())
+    }
+
+    /// Parse CLI arguments like `Parser::try_parse_from`.
+    /// `--params-json` and `--param` given after `--step` are moved to that step
+    pub fn try_parse_args<I, T>(args: I) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let matches = Self::command().try_get_matches_from(args)?;
+        let mut args = Self::from_arg_matches(&matches)?;
+        args.attach_inline_params_to_steps(&matches)?;
+        Ok(args)
+    }
+
+    fn attach_inline_params_to_steps(&mut self, matches: &ArgMatches) -> Result<(), clap::Error> {
+        if self.step.is_empty() {
+            return Ok(());
+        }
+
+        let step_indices: Vec<usize> = matches.indices_of("step").into_iter().flatten().collect();
+        let mut inline: Vec<(usize, Value)> = ["params_json", "param"]
+            .into_iter()
+            .flat_map(|id| matches.indices_of(id).into_iter().flatten())
+            .zip(self.params_json.drain(..).chain(self.param.drain(..)))
+            .collect();
+        inline.sort_by_key(|(idx, _)| *idx);
+
+        for (idx, value) in inline {
+            let step = step_indices.partition_point(|step_idx| *step_idx < idx);
+            if step == 0 {
+                return Err(Self::command().error(
+                    ErrorKind::ArgumentConflict,
+                    "'--params-json' and '--param' must follow the '--step' they apply to",
+                ));
+            }
+            self.step[step - 1].inline_params.push(value);
+        }
+
+        Ok(())
+    }
+
+    /// Path to input image. Required by CLI parser when no command is given
+    pub fn input(&self) -> &Path {
+        self.input
+            .as_deref()
+            .expect("input is required when no command is given")
+    }
+
+    /// Path to save result. Required by CLI parser when no command is given
+    pub fn output(&self) -> &Path {
+        self.output
+            .as_deref()
+            .expect("output is required when no command is given")
+    }
+
+    /// Directories to search plugins in: `--plugin-path` ones followed by ones from environment variable
+    pub fn plugin_dirs(&self) -> Vec<PathBuf> {
+        let mut dirs: Vec<PathBuf> = env::split_paths(&self.plugin_path).collect();
+        if let Some(env_path) = env::var_os(PLUGIN_PATH_ENV) {
+            dirs.extend(env::split_paths(&env_path));
+        }
+        dirs.retain(|dir| !dir.as_os_str().is_empty());
+        dirs
+    }
+
+    /// List of steps to run: either `--step` values or single `--plugin` with its params
+    pub fn steps(&self) -> Vec<StepArgs> {
+        match &self.plugin {
+            Some(plugin) => vec![StepArgs {
+                plugin: plugin.clone(),
+                params: self.params.clone(),
+                inline_params: self
+                    .params_json
+                    .iter()
+                    .chain(&self.param)
+                    .cloned()
+                    .collect(),
+            }],
+            None => self.step.clone(),
+        }
+    }
+
+    /// Kinds of metadata copied from input image to result, all by default
+    pub fn metadata_kinds(&self) -> Vec<MetadataKind> {
+        match (&self.keep_metadata, self.strip_metadata) {
+            (_, true) => Vec::new(),
+            (Some(kinds), false) => kinds.clone(),
+            (None, false) => MetadataKind::ALL.to_vec(),
+        }
+    }
+
+    /// Format and encoder settings of result
+    pub fn encode_options(&self) -> EncodeOptions {
+        EncodeOptions {
+            format: self.format,
+            jpeg_quality: self.jpeg_quality,
+            png_compression: self.png_compression,
+            png_filter: self.png_filter,
+            webp_quality: self.webp_quality,
+            require_metadata: self.keep_metadata.is_some(),
+            bit_depth: self.bit_depth,
+        }
+    }
+
+    /// Check if input is a directory or a glob pattern to be processed in batch mode
+    pub fn is_batch(&self) -> bool {
+        batch::is_batch_input(self.input())
+    }
+
+    /// Number of parallel workers for batch mode
+    pub fn worker_count(&self) -> usize {
+        match self.jobs {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            jobs => jobs,
+        }
+    }
+
+    /// Number of threads recommended to plugins, 0 lets plugins decide
+    pub fn plugin_thread_count(&self) -> u32 {
+        if let Some(threads) = self.plugin_threads {
+            return threads;
+        }
+
+        let workers = match self.is_batch() {
+            true => self.worker_count(),
+            false => 1,
+        };
+        match workers {
+            1 => 0,
+            workers => {
+                let cpus = thread::available_parallelism().map_or(1, |n| n.get());
+                (cpus / workers).max(1) as u32
+            }
+        }
+    }
+
     /// Verify all required files and directories exist
     /// return AppError if something does not exist
     pub fn check_basic_paths_exists(&self) -> Result<(), AppError> {
-        if !self.input.exists() {
+        // Glob patterns are checked when matching files are collected
+        if !self.is_batch() && !self.input().exists() {
             return Err(AppError::InputFileNotFound(
-                self.input.to_string_lossy().to_string(),
+                self.input().to_string_lossy().to_string(),
             ));
         }
 
-        if !self.params.exists() {
-            return Err(AppError::ParamsFileNotFound(
-                self.params.to_string_lossy().to_string(),
-            ));
+        for params in self.steps().iter().filter_map(|step| step.params.as_ref()) {
+            if !params.exists() {
+                return Err(AppError::ParamsFileNotFound(
+                    params.to_string_lossy().to_string(),
+                ));
+            }
         }
 
-        if !self.plugin_path.exists() {
-            return Err(AppError::PluginDirectoryNotFound(
-                self.plugin_path.to_string_lossy().to_string(),
+        if let Some(mask) = &self.mask
+            && !mask.exists()
+        {
+            return Err(AppError::MaskFileNotFound(
+                mask.to_string_lossy().to_string(),
             ));
         }
 
+        self.check_plugin_dirs_exist()
+    }
+
+    /// Verify all directories given in `--plugin-path` exist.
+    /// Missing directories from environment variable are ignored
+    pub fn check_plugin_dirs_exist(&self) -> Result<(), AppError> {
+        for dir in env::split_paths(&self.plugin_path) {
+            if !dir.exists() {
+                return Err(AppError::PluginDirectoryNotFound(
+                    dir.to_string_lossy().to_string(),
+                ));
+            }
+        }
+
         Ok(())
     }
 
-    /// Verify that plugin exists in plugins directory and return `PathBuf` to it or `AppError` otherwise
-    pub fn plugin_file(&self) -> Result<PathBuf, AppError> {
-        let plugin_filename = libloading::library_filename(&self.plugin);
-        let plugin_file = self.plugin_path.join(plugin_filename);
+    /// Find plugin in plugins directories and return `PathBuf` to it or `AppError` otherwise
+    pub fn plugin_file(&self, plugin: &str) -> Result<PathBuf, AppError> {
+        discovery::find_plugin(&self.plugin_dirs(), plugin)
+            .ok_or_else(|| AppError::PluginNotFound(plugin.to_string()))
+    }
+}
 
-        if !plugin_file.exists() {
-            return Err(AppError::PluginNotFound(
-                plugin_file.to_string_lossy().to_string(),
-            ));
-        }
+/// Parse `PLUGIN_NAME` or `PLUGIN_NAME=PARAMS_FILE` pipeline step
+fn parse_step(value: &str) -> Result<StepArgs, AppError> {
+    let (plugin, params) = match value.split_once('=') {
+        Some((plugin, params)) => (plugin, Some(params)),
+        None => (value, None),
+    };
+    if plugin.is_empty() || params.is_some_and(str::is_empty) {
+        return Err(AppError::InvalidStep(value.to_string()));
+    }
+
+    Ok(StepArgs {
+        plugin: plugin.to_string(),
+        params: params.map(PathBuf::from),
+        inline_params: Vec::new(),
+    })
+}
+
+/// Parse positive number of seconds
+fn parse_timeout(value: &str) -> Result<Duration, AppError> {
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|seconds| *seconds > 0.0)
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        .ok_or_else(|| AppError::InvalidTimeout(value.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_step() {
+        let step = parse_step("blur=demo/blur_box.json").unwrap();
+        assert_eq!(
+            step,
+            StepArgs {
+                plugin: "blur".to_string(),
+                params: Some(PathBuf::from("demo/blur_box.json")),
+                inline_params: Vec::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_step_without_params_file() {
+        let step = parse_step("blur").unwrap();
+        assert_eq!(step.plugin, "blur");
+        assert_eq!(step.params, None);
+    }
+
+    #[test]
+    fn test_parse_step_invalid() {
+        assert!(matches!(parse_step(""), Err(AppError::InvalidStep(_))));
+        assert!(matches!(
+            parse_step("=a.json"),
+            Err(AppError::InvalidStep(_))
+        ));
+        assert!(matches!(parse_step("blur="), Err(AppError::InvalidStep(_))));
+    }
+
+    #[test]
+    fn test_parse_timeout() {
+        assert_eq!(parse_timeout("1.5").unwrap(), Duration::from_millis(1500));
+        assert!(matches!(
+            parse_timeout("0"),
+            Err(AppError::InvalidTimeout(_))
+        ));
+        assert!(matches!(
+            parse_timeout("-1"),
+            Err(AppError::InvalidTimeout(_))
+        ));
+        assert!(matches!(
+            parse_timeout("abc"),
+            Err(AppError::InvalidTimeout(_))
+        ));
+    }
+
+    #[test]
+    fn test_steps_order() {
+        let args = Args::parse_from([
+            "image_processor",
+            "--input",
+            "in.png",
+            "--output",
+            "out.png",
+            "--step",
+            "blur=blur.json",
+            "--step",
+            "mirror=mirror.json",
+        ]);
+        let plugins: Vec<_> = args.steps().into_iter().map(|s| s.plugin).collect();
+        assert_eq!(plugins, ["blur", "mirror"]);
+    }
+
+    #[test]
+    fn test_plugin_inline_params() {
+        let args = Args::parse_from([
+            "image_processor",
+            "--input",
+            "in.png",
+            "--output",
+            "out.png",
+            "--plugin",
+            "mirror",
+            "--param",
+            "vertical=true",
+            "--params-json",
+            r#"{ "horizontal": true }"#,
+        ]);
+        let steps = args.steps();
+        assert_eq!(steps.len(), 1);
+        assert_eq!(steps[0].params, None);
+        assert_eq!(
+            steps[0].inline_params,
+            [
+                serde_json::json!({ "horizontal": true }),
+                serde_json::json!({ "vertical": true })
+            ]
+        );
+    }
+
+    #[test]
+    fn test_step_inline_params() {
+        let args = Args::try_parse_args([
+            "image_processor",
+            "--input",
+            "in.png",
+            "--output",
+            "out.png",
+            "--step",
+            "blur=blur.json",
+            "--param",
+            "radius=1",
+            "--params-json",
+            r#"{ "radius": 2 }"#,
+            "--step",
+            "mirror",
+            "--param",
+            "vertical=true",
+            "--step",
+            "resize=resize.json",
+        ])
+        .unwrap();
+        let steps = args.steps();
+        assert_eq!(steps.len(), 3);
+        assert_eq!(
+            steps[0].inline_params,
+            [
+                serde_json::json!({ "radius": 1 }),
+                serde_json::json!({ "radius": 2 })
+            ]
+        );
+        assert_eq!(steps[1].params, None);
+        assert_eq!(
+            steps[1].inline_params,
+            [serde_json::json!({ "vertical": true })]
+        );
+        assert!(steps[2].inline_params.is_empty());
+    }
+
+    #[test]
+    fn test_inline_params_before_step() {
+        let result = Args::try_parse_args([
+            "image_processor",
+            "--input",
+            "in.png",
+            "--output",
+            "out.png",
+            "--param",
+            "radius=1",
+            "--step",
+            "blur=blur.json",
+        ]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_command_does_not_require_input() {
+        let args = Args::try_parse_from([
+            "image_processor",
+            "plugin-info",
+            "blur",
+            "--plugin-path",
+            "a:b",
+        ])
+        .unwrap();
+        assert!(
+            matches!(args.command, Some(Command::PluginInfo { plugin, schema: false }) if plugin == "blur")
+        );
+        assert_eq!(args.plugin_path, "a:b");
+    }
+
+    #[test]
+    fn test_input_required_without_command() {
+        let result = Args::try_parse_from(["image_processor", "--plugin", "blur"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_plugin_conflicts_with_step() {
+        let result = Args::try_parse_from([
+            "image_processor",
+            "--input",
+            "in.png",
+            "--output",
+            "out.png",
+            "--plugin",
+            "blur",
+            "--params",
+            "blur.json",
+            "--step",
+            "mirror=mirror.json",
+        ]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_mask_conflicts_with_region() {
+        let result = Args::try_parse_from([
+            "image_processor",
+            "--input",
+            "in.png",
+            "--output",
+            "out.png",
+            "--plugin",
+            "blur",
+            "--region",
+            "0,0,10,10",
+            "--mask",
+            "mask.png",
+        ]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_regions() {
+        let args = Args::parse_from([
+            "image_processor",
+            "--input",
+            "in.png",
+            "--output",
+            "out.png",
+            "--plugin",
+            "blur",
+            "--region",
+            "0,0,10,10",
+            "--region",
+            "5,6,7,8",
+        ]);
+        assert_eq!(args.region.len(), 2);
+        assert_eq!(args.region[1].width, 7);
+    }
+
+    #[test]
+    fn test_metadata_kinds() {
+        let args = |extra: &[&str]| {
+            let base = [
+                "image_processor",
+                "--input",
+                "in.png",
+                "--output",
+                "out.png",
+                "--plugin",
+                "blur",
+            ];
+            Args::try_parse_from(base.iter().chain(extra))
+        };
 
-        Ok(plugin_file)
+        assert_eq!(args(&[]).unwrap().metadata_kinds(), MetadataKind::ALL);
+        assert!(
+            args(&["--strip-metadata"])
+                .unwrap()
+                .metadata_kinds()
+                .is_empty()
+        );
+        assert_eq!(
+            args(&["--keep-metadata", "icc,xmp"])
+                .unwrap()
+                .metadata_kinds(),
+            [MetadataKind::Icc, MetadataKind::Xmp]
+        );
+        assert!(args(&["--keep-metadata", "gps"]).is_err());
+        assert!(args(&["--strip-metadata", "--keep-metadata", "icc"]).is_err());
     }
 }
@@ -0,0 +1,297 @@
+//! Batch processing of directories and glob patterns
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+};
+
+use image::ImageFormat;
+
+use crate::{error::AppError, pipeline::Pipeline};
+
+/// Single image of batch: where to read it from and where to save result
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchJob {
+    /// Path to input image
+    pub input: PathBuf,
+
+    /// Path to save result
+    pub output: PathBuf,
+}
+
+/// Check if input should be processed as a batch: it is a directory or a glob pattern
+///
+/// Existing file is never a pattern, even if its name contains pattern characters
+pub fn is_batch_input(input: &Path) -> bool {
+    input.is_dir() || (!input.exists() && input.to_string_lossy().contains(['*', '?', '[']))
+}
+
+/// Find all images for directory or glob pattern input and build output path for each of them
+///
+/// Directories are scanned non-recursively, only files with known image extensions are taken.
+/// Output file names are built from `template` with `output_file_name`, template giving the same name
+/// to several images or name of input image is an error
+pub fn collect_jobs(
+    input: &Path,
+    output_dir: &Path,
+    template: &str,
+    format: Option<ImageFormat>,
+) -> Result<Vec<BatchJob>, AppError> {
+    let input_str = input.to_string_lossy().to_string();
+
+    let mut inputs: Vec<PathBuf> = if input.is_dir() {
+        fs::read_dir(input)
+            .map_err(|source| AppError::Directory {
+                path: input_str.clone(),
+                source,
+            })?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .collect()
+    } else {
+        glob::glob(&input_str)
+            .map_err(|_| AppError::InvalidInputPattern(input_str.clone()))?
+            .filter_map(Result::ok)
+            .collect()
+    };
+
+    inputs.retain(|path| path.is_file() && ImageFormat::from_path(path).is_ok());
+    inputs.sort();
+
+    if inputs.is_empty() {
+        return Err(AppError::NoInputImages(input_str));
+    }
+
+    let jobs: Vec<BatchJob> = inputs
+        .into_iter()
+        .enumerate()
+        .map(|(index, input)| {
+            let output = output_dir.join(output_file_name(template, &input, index + 1, format));
+            BatchJob { input, output }
+        })
+        .collect();
+
+    // Results must not overwrite each other or input images, which may be written to other names
+    let inputs: HashMap<PathBuf, &Path> = jobs
+        .iter()
+        .filter_map(|job| Some((fs::canonicalize(&job.input).ok()?, job.input.as_path())))
+        .collect();
+    let mut outputs: HashMap<&Path, &Path> = HashMap::new();
+    for job in &jobs {
+        if let Some(first) = outputs.insert(&job.output, &job.input) {
+            return Err(AppError::DuplicateOutput {
+                output: job.output.to_string_lossy().to_string(),
+                first: first.to_string_lossy().to_string(),
+                second: job.input.to_string_lossy().to_string(),
+            });
+        }
+        if let Some(input) = fs::canonicalize(&job.output)
+            .ok()
+            .and_then(|output| inputs.get(&output))
+        {
+            return Err(AppError::OutputOverwritesInput {
+                output: job.output.to_string_lossy().to_string(),
+                input: input.to_string_lossy().to_string(),
+            });
+        }
+    }
+
+    Ok(jobs)
+}
+
+/// Build output file name from template
+///
+/// Supported placeholders:
+/// * `{name}` - input file name with extension
+/// * `{stem}` - input file name without extension
+/// * `{ext}` - input file extension
+/// * `{index}` - number of file in batch starting from 1
+///
+/// If output `format` is given, `{name}` and `{ext}` take its extension instead of input file extension
+pub fn output_file_name(
+    template: &str,
+    input: &Path,
+    index: usize,
+    format: Option<ImageFormat>,
+) -> String {
+    let part = |value: Option<&std::ffi::OsStr>| {
+        value
+            .map(|v| v.to_string_lossy().to_string())
+            .unwrap_or_default()
+    };
+
+    let stem = part(input.file_stem());
+    let (name, ext) = match format.and_then(|format| format.extensions_str().first()) {
+        Some(ext) => (format!("{stem}.{ext}"), ext.to_string()),
+        None => (part(input.file_name()), part(input.extension())),
+    };
+
+    template
+        .replace("{name}", &name)
+        .replace("{stem}", &stem)
+        .replace("{ext}", &ext)
+        .replace("{index}", &index.to_string())
+}
+
+/// Process all jobs with `workers` threads. Failed images do not stop processing of other ones.
+/// Plugins progress is not shown in batch mode
+///
+/// `report` is called on the calling thread after each image is finished, in order of completion
+pub fn run(
+    pipeline: &Pipeline,
+    jobs: &[BatchJob],
+    workers: usize,
+    mut report: impl FnMut(&BatchJob, Result<(), AppError>),
+) {
+    let next_job = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..workers.clamp(1, jobs.len().max(1)) {
+            let sender = sender.clone();
+            let next_job = &next_job;
+            scope.spawn(move || {
+                loop {
+                    let idx = next_job.fetch_add(1, Ordering::Relaxed);
+                    let Some(job) = jobs.get(idx) else {
+                        break;
+                    };
+                    let result = pipeline.process_file(&job.input, &job.output, None);
+                    if sender.send((idx, result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        for (idx, result) in receiver {
+            report(&jobs[idx], result);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_file_name() {
+        let input = Path::new("photos/cat.jpg");
+        assert_eq!(output_file_name("{name}", input, 1, None), "cat.jpg");
+        assert_eq!(
+            output_file_name("{stem}_blur.{ext}", input, 1, None),
+            "cat_blur.jpg"
+        );
+        assert_eq!(
+            output_file_name("{index}_{stem}.png", input, 12, None),
+            "12_cat.png"
+        );
+    }
+
+    #[test]
+    fn test_output_file_name_with_format() {
+        let input = Path::new("photos/cat.jpg");
+        let png = Some(ImageFormat::Png);
+        assert_eq!(output_file_name("{name}", input, 1, png), "cat.png");
+        assert_eq!(
+            output_file_name("{stem}_blur.{ext}", input, 1, png),
+            "cat_blur.png"
+        );
+        assert_eq!(
+            output_file_name("{name}", Path::new("photos/README"), 1, png),
+            "README.png"
+        );
+    }
+
+    #[test]
+    fn test_is_batch_input() {
+        assert!(is_batch_input(Path::new("photos/*.jpg")));
+        assert!(is_batch_input(Path::new("photos/img_?.png")));
+        assert!(is_batch_input(Path::new(".")));
+        assert!(!is_batch_input(Path::new("photos/cat.jpg")));
+
+        let file = std::env::temp_dir().join(format!("photo[{}].png", std::process::id()));
+        fs::write(&file, b"").unwrap();
+        assert!(!is_batch_input(&file));
+        fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn test_collect_jobs_from_glob() {
+        let jobs = collect_jobs(
+            Path::new("../demo/*.png"),
+            Path::new("out"),
+            "{stem}_1.png",
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            jobs,
+            [BatchJob {
+                input: PathBuf::from("../demo/weather.png"),
+                output: PathBuf::from("out/weather_1.png"),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_collect_jobs_skips_non_images() {
+        let jobs = collect_jobs(Path::new("../demo"), Path::new("out"), "{name}", None).unwrap();
+        let inputs: Vec<_> = jobs.into_iter().map(|job| job.input).collect();
+        assert_eq!(inputs, [PathBuf::from("../demo/weather.png")]);
+    }
+
+    #[test]
+    fn test_collect_jobs_no_images() {
+        let result = collect_jobs(Path::new("../demo/*.gif"), Path::new("out"), "{name}", None);
+        assert!(matches!(result, Err(AppError::NoInputImages(_))));
+    }
+
+    #[test]
+    fn test_collect_jobs_with_format() {
+        let jobs = collect_jobs(
+            Path::new("../demo/*.png"),
+            Path::new("out"),
+            "{name}",
+            Some(ImageFormat::Jpeg),
+        )
+        .unwrap();
+        assert_eq!(jobs[0].output, PathBuf::from("out/weather.jpg"));
+    }
+
+    #[test]
+    fn test_collect_jobs_duplicate_output() {
+        let dir = std::env::temp_dir().join(format!("batch_duplicate_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["a.png", "a.jpg", "b.png"] {
+            fs::copy("../demo/weather.png", dir.join(name)).unwrap();
+        }
+
+        let result = collect_jobs(&dir, Path::new("out"), "result.png", None);
+        assert!(matches!(result, Err(AppError::DuplicateOutput { .. })));
+        assert_eq!(
+            collect_jobs(&dir, Path::new("out"), "{name}", None)
+                .unwrap()
+                .len(),
+            3
+        );
+        // Output format gives the same extension to images with the same stem
+        let result = collect_jobs(&dir, Path::new("out"), "{name}", Some(ImageFormat::Png));
+        assert!(matches!(result, Err(AppError::DuplicateOutput { .. })));
+
+        // Results saved next to inputs must not replace them
+        let result = collect_jobs(&dir, &dir, "{name}", None);
+        assert!(matches!(
+            result,
+            Err(AppError::OutputOverwritesInput { .. })
+        ));
+        assert!(collect_jobs(&dir, &dir, "{stem}_blur.{ext}", None).is_ok());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,112 @@
+//! Search of plugins in plugins directories
+use std::{
+    collections::HashSet,
+    env::consts::{DLL_PREFIX, DLL_SUFFIX},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::AppError,
+    plugin::{Plugin, PluginMetadata},
+};
+
+/// Plugin found in one of plugins directories
+#[derive(Debug)]
+pub struct PluginEntry {
+    /// Path to plugin library
+    pub path: PathBuf,
+
+    /// Description read from plugin
+    pub metadata: PluginMetadata,
+}
+
+/// Result of plugins directories scan
+#[derive(Debug, Default)]
+pub struct Discovery {
+    /// Plugins in search order. Plugin shadowed by library with the same name in earlier directory is not listed
+    pub plugins: Vec<PluginEntry>,
+
+    /// Libraries which could not be loaded as plugins with the reason
+    pub skipped: Vec<(PathBuf, AppError)>,
+}
+
+/// Find library of plugin with given name in first directory containing it
+pub fn find_plugin(dirs: &[PathBuf], plugin: &str) -> Option<PathBuf> {
+    let plugin_filename = libloading::library_filename(plugin);
+    dirs.iter()
+        .map(|dir| dir.join(&plugin_filename))
+        .find(|plugin_file| plugin_file.is_file())
+}
+
+/// Load every dynamic library found in `dirs` and read its plugin description
+///
+/// Missing directories are ignored. Libraries which are not compatible plugins are reported in `Discovery::skipped`
+pub fn discover(dirs: &[PathBuf]) -> Discovery {
+    let mut discovery = Discovery::default();
+    let mut seen = HashSet::new();
+
+    for dir in dirs {
+        for (name, path) in libraries(dir) {
+            if !seen.insert(name) {
+                continue;
+            }
+            match Plugin::new(path.clone()) {
+                Ok(plugin) => discovery.plugins.push(PluginEntry {
+                    path,
+                    metadata: plugin.metadata().clone(),
+                }),
+                Err(e) => discovery.skipped.push((path, e)),
+            }
+        }
+    }
+
+    discovery
+}
+
+/// Dynamic libraries in `dir` with plugin names derived from file names, sorted by name
+fn libraries(dir: &Path) -> Vec<(String, PathBuf)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut libraries: Vec<_> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .filter_map(|path| {
+            let name = plugin_name(path.file_name()?.to_str()?)?.to_string();
+            Some((name, path))
+        })
+        .collect();
+    libraries.sort();
+    libraries
+}
+
+/// Plugin name from library file name, e.g. `blur` for `libblur.so`
+fn plugin_name(file_name: &str) -> Option<&str> {
+    file_name
+        .strip_prefix(DLL_PREFIX)?
+        .strip_suffix(DLL_SUFFIX)
+        .filter(|name| !name.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plugin_name() {
+        let file_name = format!("{DLL_PREFIX}blur{DLL_SUFFIX}");
+        assert_eq!(plugin_name(&file_name), Some("blur"));
+        assert_eq!(plugin_name(&format!("{DLL_PREFIX}{DLL_SUFFIX}")), None);
+        assert_eq!(plugin_name("blur.txt"), None);
+    }
+
+    #[test]
+    fn test_missing_directory_is_ignored() {
+        let discovery = discover(&[PathBuf::from("no/such/directory")]);
+        assert!(discovery.plugins.is_empty());
+        assert!(discovery.skipped.is_empty());
+    }
+}
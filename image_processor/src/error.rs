@@ -1,7 +1,11 @@
 //! App errors list and logic
+use std::time::Duration;
+
 use plugin_errors::PluginError;
 use thiserror::Error;
 
+use crate::metadata::MetadataKind;
+
 /// Checked app errors
 #[derive(Debug, Error)]
 pub enum AppError {
@@ -40,6 +44,306 @@ pub enum AppError {
     /// Plugin unable to convert image with given dimensions
     #[error("Plugin unable to convert image with given dimensions")]
     SizeIsTooBig,
+
+    /// Plugin stopped processing because it was cancelled by app
+    #[error("Plugin processing was cancelled")]
+    PluginCancelled,
+
+    /// Plugin reported invalid output dimensions or got output buffer of unexpected size
+    #[error("Plugin reported invalid output image dimensions")]
+    InvalidOutputSize,
+
+    /// Plugin does not accept pixel format of image data passed to it
+    #[error("Plugin does not accept pixel format of image")]
+    PluginUnsupportedPixelFormat,
+
+    /// Plugin can not read image data in given pixel format
+    #[error("Plugin received invalid image data")]
+    PluginInvalidData,
+
+    /// Plugin error with detailed description provided by plugin
+    #[error("{error}: {message}")]
+    PluginErrorMessage {
+        /// Error mapped from plugin return code
+        error: Box<AppError>,
+        /// Error description provided by plugin
+        message: String,
+    },
+
+    /// Pipeline step argument has wrong format
+    #[error("Invalid pipeline step '{0}', expected PLUGIN_NAME or PLUGIN_NAME=PARAMS_FILE")]
+    InvalidStep(String),
+
+    /// Unable to load plugin library or find its exported functions
+    #[error("Unable to load plugin")]
+    PluginLoad(#[from] libloading::Error),
+
+    /// Library does not export `plugin_info` function or it returned null
+    #[error("Library '{0}' is not an image processor plugin: plugin info is missing")]
+    PluginInfoMissing(String),
+
+    /// Plugin is built against another ABI version
+    #[error(
+        "Plugin '{plugin}' is built for ABI version {found}, but version {expected} is required"
+    )]
+    IncompatiblePlugin {
+        /// Path to plugin library
+        plugin: String,
+        /// ABI version supported by app
+        expected: u32,
+        /// ABI version declared by plugin
+        found: u32,
+    },
+
+    /// Image is passed to plugin in pixel format not declared in plugin info
+    #[error("Plugin '{plugin}' does not accept {format} pixel format")]
+    UnsupportedPixelFormat {
+        /// Plugin name
+        plugin: String,
+        /// Name of pixel format
+        format: &'static str,
+    },
+
+    /// Plugin does not declare capability required for requested operation
+    #[error("Plugin '{plugin}' does not support {capability}")]
+    PluginCapabilityMissing {
+        /// Plugin name
+        plugin: String,
+        /// Description of missing capability
+        capability: &'static str,
+    },
+
+    /// Unable to read params file
+    #[error("Unable to read params file '{path}'")]
+    ParamsFileRead {
+        /// Path to params file
+        path: String,
+        /// Read error
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// Inline param is not in `KEY=VALUE` format
+    #[error("Invalid param '{0}', expected KEY=VALUE")]
+    InvalidParam(String),
+
+    /// Params which should be merged with inline params are not a JSON object
+    #[error("Params from {origin} are not a valid JSON object: {message}")]
+    InvalidParamsJson {
+        /// Params file path or CLI argument name
+        origin: String,
+        /// JSON parse error
+        message: String,
+    },
+
+    /// Params do not match JSON Schema exported by plugin
+    #[error("Invalid params for plugin '{plugin}' at '{path}': {message}")]
+    ParamsValidation {
+        /// Plugin name
+        plugin: String,
+        /// Path to invalid value, like `$.crop.width`
+        path: String,
+        /// Description of mismatch
+        message: String,
+    },
+
+    /// Plugin exported params schema which is not a valid JSON
+    #[error("Plugin '{plugin}' exported invalid params schema: {message}")]
+    InvalidParamsSchema {
+        /// Plugin name
+        plugin: String,
+        /// JSON parse error
+        message: String,
+    },
+
+    /// Params file contains nul byte and can not be passed to plugin
+    #[error("Params file '{0}' contains nul byte")]
+    ParamsContainNul(String),
+
+    /// Unable to decode input image or encode result
+    #[error("Unable to read or write image")]
+    Image(#[from] image::ImageError),
+
+    /// Output format is unknown or can not be written
+    #[error("Unknown output format '{0}'")]
+    InvalidFormat(String),
+
+    /// Encoder option has invalid value
+    #[error("Invalid {option} '{value}'")]
+    InvalidEncoderOption {
+        /// Option description
+        option: &'static str,
+        /// Given value
+        value: String,
+    },
+
+    /// Output file extension does not match output format given explicitly
+    #[error("Output file '{path}' extension does not match output format {format}")]
+    FormatMismatch {
+        /// Path to output file
+        path: String,
+        /// Output format
+        format: String,
+    },
+
+    /// Output format does not support requested bit depth
+    #[error("Output format {format} does not support {bits}-bit images")]
+    UnsupportedBitDepth {
+        /// Output format
+        format: String,
+        /// Bits per channel
+        bits: u8,
+    },
+
+    /// WebP quality is requested, but only lossless WebP encoding is supported
+    #[error("Lossy WebP encoding is not supported, omit --webp-quality to save lossless WebP")]
+    LossyWebPUnsupported,
+
+    /// Output format can not store metadata kept from input image
+    #[error("Output format {format} can not store {kind} metadata")]
+    UnsupportedMetadata {
+        /// Kind of metadata
+        kind: MetadataKind,
+        /// Output format
+        format: String,
+    },
+
+    /// Region argument is not in `X,Y,WIDTH,HEIGHT` format
+    #[error("Invalid region '{0}', expected X,Y,WIDTH,HEIGHT with positive size")]
+    InvalidRegion(String),
+
+    /// Mask file not found
+    #[error("Mask file '{0}' not found")]
+    MaskFileNotFound(String),
+
+    /// Mask file is not a readable image
+    #[error("Unable to read mask file '{path}'")]
+    MaskLoad {
+        /// Path to mask file
+        path: String,
+        /// Decoding error
+        #[source]
+        source: image::ImageError,
+    },
+
+    /// Mask image size differs from size of processed image
+    #[error(
+        "Mask size {}x{} does not match image size {}x{}",
+        mask.0,
+        mask.1,
+        image.0,
+        image.1
+    )]
+    MaskSizeMismatch {
+        /// Mask width and height
+        mask: (u32, u32),
+        /// Image width and height
+        image: (u32, u32),
+    },
+
+    /// Pipeline changed image size, so result can not be blended with original image by mask
+    #[error(
+        "Mask can not be applied because pipeline changed image size from {}x{} to {}x{}",
+        before.0,
+        before.1,
+        after.0,
+        after.1
+    )]
+    MaskedSizeChanged {
+        /// Size of input image
+        before: (u32, u32),
+        /// Size of pipeline result
+        after: (u32, u32),
+    },
+
+    /// Glob pattern given as input is malformed
+    #[error("Invalid input pattern '{0}'")]
+    InvalidInputPattern(String),
+
+    /// Input directory or glob pattern does not contain any image
+    #[error("No images found for input '{0}'")]
+    NoInputImages(String),
+
+    /// Output file name template gives the same path to several images of batch
+    #[error(
+        "Output file '{output}' is the same for '{first}' and '{second}', use {{stem}} or {{index}} in output template"
+    )]
+    DuplicateOutput {
+        /// Path to output file
+        output: String,
+        /// First input image saved to this path
+        first: String,
+        /// Second input image saved to this path
+        second: String,
+    },
+
+    /// Output file name template gives path of input image of batch
+    #[error("Output file '{output}' would overwrite input image '{input}'")]
+    OutputOverwritesInput {
+        /// Path to output file
+        output: String,
+        /// Path to input image
+        input: String,
+    },
+
+    /// Unable to read input directory or create output directory
+    #[error("Unable to access directory '{path}'")]
+    Directory {
+        /// Path to directory
+        path: String,
+        /// IO error
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// Some of images failed in batch mode
+    #[error("{failed} of {total} images failed")]
+    BatchFailed {
+        /// Number of failed images
+        failed: usize,
+        /// Total number of images
+        total: usize,
+    },
+
+    /// Timeout value is not a positive number of seconds
+    #[error("Invalid timeout '{0}', expected positive number of seconds")]
+    InvalidTimeout(String),
+
+    /// Unable to start or wait for plugin worker process
+    #[error("Unable to run plugin worker process")]
+    WorkerSpawn(#[source] std::io::Error),
+
+    /// Plugin worker process was terminated by signal, e.g. because of segmentation fault
+    #[error("Plugin worker process crashed with signal {0}")]
+    WorkerCrashed(i32),
+
+    /// Plugin worker process exited with error code without sending result
+    #[error("Plugin worker process exited with code {0:?}")]
+    WorkerExited(Option<i32>),
+
+    /// Plugin worker process sent malformed message
+    #[error("Plugin worker protocol error: {0}")]
+    WorkerProtocol(&'static str),
+
+    /// Plugin worker process failed before or after calling plugin
+    #[error("Plugin worker failed: {0}")]
+    WorkerError(String),
+
+    /// Plugin did not finish in time
+    #[error("Plugin did not finish in {0:?}")]
+    Timeout(Duration),
+
+    /// One of pipeline steps failed
+    #[error("Pipeline step {step} (plugin '{plugin}') failed")]
+    PipelineStepFailed {
+        /// Number of failed step starting from 1
+        step: usize,
+        /// Name of plugin used in failed step
+        plugin: String,
+        /// Error returned by step
+        #[source]
+        source: Box<AppError>,
+    },
 }
 
 impl AppError {
@@ -53,7 +357,31 @@ impl AppError {
             Some(PluginError::NullPointer) => Some(AppError::NullPointer),
             Some(PluginError::Panic) => Some(AppError::PluginPanic),
             Some(PluginError::SizeIsTooBig) => Some(AppError::SizeIsTooBig),
+            Some(PluginError::InvalidOutputSize) => Some(AppError::InvalidOutputSize),
+            Some(PluginError::Cancelled) => Some(AppError::PluginCancelled),
+            Some(PluginError::UnsupportedPixelFormat) => {
+                Some(AppError::PluginUnsupportedPixelFormat)
+            }
+            Some(PluginError::InvalidData) => Some(AppError::PluginInvalidData),
             None => Some(AppError::PluginUnknownErrorCode(code)),
         }
     }
+
+    /// Convert error created from plugin return code back to the code, None for other errors
+    pub fn plugin_error_code(&self) -> Option<i32> {
+        let plugin_error = match self {
+            AppError::PluginInvalidParams => PluginError::InvalidParams,
+            AppError::NullPointer => PluginError::NullPointer,
+            AppError::PluginPanic => PluginError::Panic,
+            AppError::SizeIsTooBig => PluginError::SizeIsTooBig,
+            AppError::InvalidOutputSize => PluginError::InvalidOutputSize,
+            AppError::PluginCancelled => PluginError::Cancelled,
+            AppError::PluginUnsupportedPixelFormat => PluginError::UnsupportedPixelFormat,
+            AppError::PluginInvalidData => PluginError::InvalidData,
+            AppError::PluginUnknownErrorCode(code) => return Some(*code),
+            _ => return None,
+        };
+
+        Some(plugin_error as i32)
+    }
 }
@@ -0,0 +1,710 @@
+//! Reading input images and saving results
+use std::{
+    fs,
+    io::{BufRead, Cursor, Seek},
+    path::Path,
+};
+
+use clap::ValueEnum;
+use image::{
+    DynamicImage, ImageBuffer, ImageDecoder, ImageEncoder, ImageError, ImageFormat, ImageReader,
+    Pixel,
+    codecs::{
+        jpeg::JpegEncoder,
+        png::{CompressionType, FilterType, PngEncoder},
+        tiff::TiffEncoder,
+        webp::WebPEncoder,
+    },
+};
+use image_orientation::{Orientation, Transform};
+
+use crate::{
+    error::AppError,
+    metadata::{Metadata, MetadataKind},
+};
+
+/// Read image from `path` together with its metadata of given `kinds`
+///
+/// Image keeps its decoded pixel type, so 16-bit and floating point images are not rounded to 8 bits.
+/// If `auto_orient` is set, image is rotated and mirrored according to its EXIF orientation tag,
+/// so plugins get it upright as image viewers show it, and the tag is reset in returned EXIF
+pub fn read_image(
+    path: &Path,
+    auto_orient: bool,
+    kinds: &[MetadataKind],
+) -> Result<(DynamicImage, Metadata), AppError> {
+    let reader = ImageReader::open(path).map_err(ImageError::IoError)?;
+    decode(reader, auto_orient, kinds)
+}
+
+fn decode<R: BufRead + Seek>(
+    reader: ImageReader<R>,
+    auto_orient: bool,
+    kinds: &[MetadataKind],
+) -> Result<(DynamicImage, Metadata), AppError> {
+    let mut decoder = reader.into_decoder()?;
+    let mut metadata = Metadata::read(&mut decoder, kinds)?;
+    let orientation = match auto_orient {
+        true => {
+            metadata.reset_orientation();
+            Orientation::from_exif(decoder.orientation()?.to_exif()).unwrap_or_default()
+        }
+        false => Orientation::Normal,
+    };
+    let image = DynamicImage::from_decoder(decoder)?;
+
+    Ok((orient(image, orientation), metadata))
+}
+
+/// Bits per channel of saved result
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BitDepth {
+    /// 8 bits, supported by all formats
+    #[value(name = "8")]
+    Eight,
+    /// 16 bits, supported by PNG and TIFF
+    #[value(name = "16")]
+    Sixteen,
+}
+
+/// Format and encoder settings of saved result
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EncodeOptions {
+    /// Output format, chosen by output file extension if not set
+    pub format: Option<ImageFormat>,
+    /// JPEG quality from 1 to 100
+    pub jpeg_quality: u8,
+    /// PNG compression level
+    pub png_compression: CompressionType,
+    /// PNG filter applied to rows before compression
+    pub png_filter: FilterType,
+    /// Requested quality of lossy WebP. WebP is always encoded lossless, so saving WebP with quality fails
+    pub webp_quality: Option<u8>,
+    /// Bits per channel, depth of processed image is kept if not set
+    pub bit_depth: Option<BitDepth>,
+    /// Fail if output format can not store metadata of input image instead of dropping it with warning
+    pub require_metadata: bool,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        EncodeOptions {
+            format: None,
+            jpeg_quality: 75,
+            png_compression: CompressionType::Fast,
+            png_filter: FilterType::Adaptive,
+            webp_quality: None,
+            bit_depth: None,
+            require_metadata: false,
+        }
+    }
+}
+
+/// Parse `--format` value given as file extension like `png` or `jpg`
+pub fn parse_format(value: &str) -> Result<ImageFormat, AppError> {
+    ImageFormat::from_extension(value)
+        .filter(|format| format.writing_enabled())
+        .ok_or_else(|| AppError::InvalidFormat(value.to_string()))
+}
+
+/// Parse `--png-compression`: `fast`, `default`, `best`, `none` or level from 1 to 9
+pub fn parse_png_compression(value: &str) -> Result<CompressionType, AppError> {
+    match value {
+        "fast" => Ok(CompressionType::Fast),
+        "default" => Ok(CompressionType::Default),
+        "best" => Ok(CompressionType::Best),
+        "none" => Ok(CompressionType::Uncompressed),
+        level => match level.parse() {
+            Ok(level @ 1..=9) => Ok(CompressionType::Level(level)),
+            _ => Err(AppError::InvalidEncoderOption {
+                option: "PNG compression",
+                value: value.to_string(),
+            }),
+        },
+    }
+}
+
+/// Parse `--png-filter`: `none`, `sub`, `up`, `avg`, `paeth` or `adaptive`
+pub fn parse_png_filter(value: &str) -> Result<FilterType, AppError> {
+    match value {
+        "none" => Ok(FilterType::NoFilter),
+        "sub" => Ok(FilterType::Sub),
+        "up" => Ok(FilterType::Up),
+        "avg" => Ok(FilterType::Avg),
+        "paeth" => Ok(FilterType::Paeth),
+        "adaptive" => Ok(FilterType::Adaptive),
+        _ => Err(AppError::InvalidEncoderOption {
+            option: "PNG filter",
+            value: value.to_string(),
+        }),
+    }
+}
+
+/// Save image to `path` in format given by options or chosen by file extension
+///
+/// PNG, JPEG and WebP results keep all given metadata, TIFF keeps only ICC profile.
+/// Metadata which format can not store is dropped with warning or fails saving if `require_metadata` is set
+pub fn write_image(
+    path: &Path,
+    image: &DynamicImage,
+    metadata: &Metadata,
+    options: &EncodeOptions,
+) -> Result<(), AppError> {
+    let format = output_format(path, options.format)?;
+    let encoded = encode(image, format, metadata, options)?;
+    fs::write(path, encoded).map_err(ImageError::IoError)?;
+
+    Ok(())
+}
+
+/// Format of output file, which extension must match format given explicitly
+fn output_format(path: &Path, format: Option<ImageFormat>) -> Result<ImageFormat, AppError> {
+    let Some(format) = format else {
+        return Ok(ImageFormat::from_path(path)?);
+    };
+    match path.extension() {
+        Some(extension) if ImageFormat::from_extension(extension) != Some(format) => {
+            Err(AppError::FormatMismatch {
+                path: path.to_string_lossy().to_string(),
+                format: format!("{format:?}"),
+            })
+        }
+        _ => Ok(format),
+    }
+}
+
+fn encode(
+    image: &DynamicImage,
+    format: ImageFormat,
+    metadata: &Metadata,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, AppError> {
+    let converted = match options.bit_depth {
+        Some(BitDepth::Sixteen) if matches!(format, ImageFormat::Png | ImageFormat::Tiff) => {
+            Some(DynamicImage::ImageRgba16(image.to_rgba16()))
+        }
+        Some(BitDepth::Sixteen) => {
+            return Err(AppError::UnsupportedBitDepth {
+                format: format!("{format:?}"),
+                bits: 16,
+            });
+        }
+        Some(BitDepth::Eight) => Some(DynamicImage::ImageRgba8(image.to_rgba8())),
+        None => encodable(image, format),
+    };
+    let image = converted.as_ref().unwrap_or(image);
+
+    let mut encoded = Vec::new();
+    let writer = MetadataWriter {
+        metadata,
+        format,
+        options,
+    };
+    match format {
+        ImageFormat::Png => writer.write(
+            image,
+            PngEncoder::new_with_quality(&mut encoded, options.png_compression, options.png_filter),
+        )?,
+        ImageFormat::Jpeg => writer.write(
+            image,
+            JpegEncoder::new_with_quality(&mut encoded, options.jpeg_quality),
+        )?,
+        ImageFormat::WebP if options.webp_quality.is_some() => {
+            return Err(AppError::LossyWebPUnsupported);
+        }
+        ImageFormat::WebP => writer.write(image, WebPEncoder::new_lossless(&mut encoded))?,
+        ImageFormat::Tiff => writer.write(image, TiffEncoder::new(Cursor::new(&mut encoded)))?,
+        _ => {
+            for (kind, chunk) in [
+                (MetadataKind::Icc, &metadata.icc),
+                (MetadataKind::Exif, &metadata.exif),
+            ] {
+                if chunk.is_some() {
+                    writer.drop(kind)?;
+                }
+            }
+            image.write_to(Cursor::new(&mut encoded), format)?
+        }
+    }
+
+    match metadata.embed_xmp(format, encoded) {
+        Ok(encoded) => Ok(encoded),
+        Err(encoded) => {
+            writer.drop(MetadataKind::Xmp)?;
+            Ok(encoded)
+        }
+    }
+}
+
+/// Image converted to pixel type which encoder of `format` supports, None if image can be saved as is
+///
+/// Precision of image is kept as far as format allows: PNG, TIFF and PNM keep 16 bits,
+/// TIFF and OpenEXR keep floating point channels, other formats are saved with 8 bits
+fn encodable(image: &DynamicImage, format: ImageFormat) -> Option<DynamicImage> {
+    let color = image.color();
+    let channel_size = color.bytes_per_pixel() / color.channel_count();
+
+    match (format, channel_size) {
+        (ImageFormat::OpenExr, 4) => None,
+        (ImageFormat::Hdr, 4) if !color.has_alpha() => None,
+        (ImageFormat::OpenExr, _) if color.has_alpha() => {
+            Some(DynamicImage::ImageRgba32F(image.to_rgba32f()))
+        }
+        (ImageFormat::OpenExr | ImageFormat::Hdr, _) => {
+            Some(DynamicImage::ImageRgb32F(image.to_rgb32f()))
+        }
+        (_, 1) | (ImageFormat::Tiff, 4) => None,
+        (ImageFormat::Png | ImageFormat::Tiff | ImageFormat::Pnm, 2) => None,
+        (ImageFormat::Png | ImageFormat::Tiff | ImageFormat::Pnm, _) => {
+            Some(with_depth(image, BitDepth::Sixteen))
+        }
+        _ => Some(with_depth(image, BitDepth::Eight)),
+    }
+}
+
+/// Image converted to integer channels of given depth keeping its color and alpha channels
+fn with_depth(image: &DynamicImage, depth: BitDepth) -> DynamicImage {
+    let color = image.color();
+    match (depth, color.has_color(), color.has_alpha()) {
+        (BitDepth::Eight, false, false) => DynamicImage::ImageLuma8(image.to_luma8()),
+        (BitDepth::Eight, false, true) => DynamicImage::ImageLumaA8(image.to_luma_alpha8()),
+        (BitDepth::Eight, true, false) => DynamicImage::ImageRgb8(image.to_rgb8()),
+        (BitDepth::Eight, true, true) => DynamicImage::ImageRgba8(image.to_rgba8()),
+        (BitDepth::Sixteen, false, false) => DynamicImage::ImageLuma16(image.to_luma16()),
+        (BitDepth::Sixteen, false, true) => DynamicImage::ImageLumaA16(image.to_luma_alpha16()),
+        (BitDepth::Sixteen, true, false) => DynamicImage::ImageRgb16(image.to_rgb16()),
+        (BitDepth::Sixteen, true, true) => DynamicImage::ImageRgba16(image.to_rgba16()),
+    }
+}
+
+/// Metadata saved with image encoded in `format`
+struct MetadataWriter<'a> {
+    metadata: &'a Metadata,
+    format: ImageFormat,
+    options: &'a EncodeOptions,
+}
+
+impl MetadataWriter<'_> {
+    /// Encode image passing metadata chunks supported by encoder to it
+    fn write(&self, image: &DynamicImage, mut encoder: impl ImageEncoder) -> Result<(), AppError> {
+        if let Some(icc) = &self.metadata.icc
+            && encoder.set_icc_profile(icc.clone()).is_err()
+        {
+            self.drop(MetadataKind::Icc)?;
+        }
+        if let Some(exif) = &self.metadata.exif
+            && encoder.set_exif_metadata(exif.clone()).is_err()
+        {
+            self.drop(MetadataKind::Exif)?;
+        }
+        Ok(image.write_with_encoder(encoder)?)
+    }
+
+    /// Report metadata which output format can not store: it is an error if metadata kinds
+    /// to keep were listed explicitly, otherwise metadata is dropped with warning
+    fn drop(&self, kind: MetadataKind) -> Result<(), AppError> {
+        let error = AppError::UnsupportedMetadata {
+            kind,
+            format: format!("{:?}", self.format),
+        };
+        match self.options.require_metadata {
+            true => Err(error),
+            false => {
+                eprintln!("Warning: {error}, it is not saved");
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Rotate and mirror image the same way as mirror plugin does for `orientation` param.
+/// Pixel type of image is kept
+pub fn orient(image: DynamicImage, orientation: Orientation) -> DynamicImage {
+    let transform = Transform::from(orientation);
+    if transform.is_identity() {
+        return image;
+    }
+
+    match image {
+        DynamicImage::ImageLuma8(image) => transform_buffer(&image, transform).into(),
+        DynamicImage::ImageLumaA8(image) => transform_buffer(&image, transform).into(),
+        DynamicImage::ImageRgb8(image) => transform_buffer(&image, transform).into(),
+        DynamicImage::ImageRgba8(image) => transform_buffer(&image, transform).into(),
+        DynamicImage::ImageLuma16(image) => transform_buffer(&image, transform).into(),
+        DynamicImage::ImageLumaA16(image) => transform_buffer(&image, transform).into(),
+        DynamicImage::ImageRgb16(image) => transform_buffer(&image, transform).into(),
+        DynamicImage::ImageRgba16(image) => transform_buffer(&image, transform).into(),
+        DynamicImage::ImageRgb32F(image) => transform_buffer(&image, transform).into(),
+        DynamicImage::ImageRgba32F(image) => transform_buffer(&image, transform).into(),
+        other => transform_buffer(&other.to_rgba32f(), transform).into(),
+    }
+}
+
+fn transform_buffer<P: Pixel>(
+    image: &ImageBuffer<P, Vec<P::Subpixel>>,
+    transform: Transform,
+) -> ImageBuffer<P, Vec<P::Subpixel>> {
+    let (width, height) = image.dimensions();
+    let (out_width, out_height) = transform.output_size(width, height);
+    let mut result = ImageBuffer::new(out_width, out_height);
+    transform.apply(
+        image.as_raw(),
+        width as usize,
+        height as usize,
+        P::CHANNEL_COUNT as usize,
+        &mut result,
+    );
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{ColorType, Rgba, RgbaImage, codecs::tiff::TiffDecoder};
+
+    use super::*;
+
+    /// Big-endian EXIF block with single orientation entry
+    fn exif_orientation(value: u8) -> Vec<u8> {
+        let mut exif = b"MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01".to_vec();
+        exif.extend([0, value, 0, 0, 0, 0, 0, 0]);
+        exif
+    }
+
+    /// 2x1 PNG with red left pixel and blue right one
+    fn png_with_exif(exif: Option<Vec<u8>>) -> Vec<u8> {
+        let pixels = [255, 0, 0, 255, 0, 0, 255, 255];
+        let mut png = Vec::new();
+        let mut encoder = PngEncoder::new(&mut png);
+        if let Some(exif) = exif {
+            encoder.set_exif_metadata(exif).unwrap();
+        }
+        encoder
+            .write_image(&pixels, 2, 1, image::ExtendedColorType::Rgba8)
+            .unwrap();
+        png
+    }
+
+    fn decode_png(png: Vec<u8>, auto_orient: bool) -> RgbaImage {
+        let reader = ImageReader::with_format(Cursor::new(png), ImageFormat::Png);
+        decode(reader, auto_orient, &[]).unwrap().0.to_rgba8()
+    }
+
+    fn read_metadata(encoded: Vec<u8>, format: ImageFormat) -> Metadata {
+        let reader = ImageReader::with_format(Cursor::new(encoded), format);
+        decode(reader, false, &MetadataKind::ALL).unwrap().1
+    }
+
+    fn test_metadata() -> Metadata {
+        Metadata {
+            exif: Some(exif_orientation(1)),
+            icc: Some(b"test icc profile".to_vec()),
+            xmp: Some(b"<x:xmpmeta xmlns:x='adobe:ns:meta/'></x:xmpmeta>".to_vec()),
+        }
+    }
+
+    #[test]
+    fn test_metadata_roundtrip() {
+        let image = DynamicImage::ImageRgba8(decode_png(png_with_exif(None), false));
+        let metadata = test_metadata();
+        for format in [ImageFormat::Png, ImageFormat::Jpeg, ImageFormat::WebP] {
+            let encoded = encode(&image, format, &metadata, &EncodeOptions::default()).unwrap();
+            assert_eq!(read_metadata(encoded, format), metadata, "{format:?}");
+        }
+
+        let encoded = encode(
+            &image,
+            ImageFormat::Tiff,
+            &metadata,
+            &EncodeOptions::default(),
+        )
+        .unwrap();
+        let mut decoder = TiffDecoder::new(Cursor::new(encoded)).unwrap();
+        assert_eq!(decoder.icc_profile().unwrap(), metadata.icc);
+    }
+
+    #[test]
+    fn test_unsupported_metadata() {
+        let image = DynamicImage::ImageRgba8(decode_png(png_with_exif(None), false));
+        let metadata = test_metadata();
+        let required = EncodeOptions {
+            require_metadata: true,
+            ..EncodeOptions::default()
+        };
+
+        let result = encode(&image, ImageFormat::Tiff, &metadata, &required);
+        assert!(matches!(
+            result,
+            Err(AppError::UnsupportedMetadata {
+                kind: MetadataKind::Exif,
+                ..
+            })
+        ));
+        let icc_only = Metadata {
+            icc: metadata.icc.clone(),
+            ..Metadata::default()
+        };
+        assert!(encode(&image, ImageFormat::Tiff, &icc_only, &required).is_ok());
+
+        let xmp_only = Metadata {
+            xmp: metadata.xmp.clone(),
+            ..Metadata::default()
+        };
+        let result = encode(&image, ImageFormat::Bmp, &xmp_only, &required);
+        assert!(matches!(
+            result,
+            Err(AppError::UnsupportedMetadata {
+                kind: MetadataKind::Xmp,
+                ..
+            })
+        ));
+        // Without explicit request metadata is dropped
+        let encoded = encode(
+            &image,
+            ImageFormat::Bmp,
+            &metadata,
+            &EncodeOptions::default(),
+        );
+        assert!(encoded.is_ok());
+    }
+
+    #[test]
+    fn test_xmp_only_webp() {
+        let image = DynamicImage::ImageRgba8(decode_png(png_with_exif(None), false));
+        let metadata = Metadata {
+            xmp: Some(b"<odd />".to_vec()),
+            ..Metadata::default()
+        };
+        let encoded = encode(
+            &image,
+            ImageFormat::WebP,
+            &metadata,
+            &EncodeOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(read_metadata(encoded.clone(), ImageFormat::WebP), metadata);
+
+        let decoded = image::load_from_memory_with_format(&encoded, ImageFormat::WebP).unwrap();
+        assert_eq!(decoded, image);
+    }
+
+    #[test]
+    fn test_orientation_reset() {
+        let png = png_with_exif(Some(exif_orientation(6)));
+        let reader = ImageReader::with_format(Cursor::new(png.clone()), ImageFormat::Png);
+        let (_, metadata) = decode(reader, true, &[MetadataKind::Exif]).unwrap();
+        assert_eq!(metadata.exif, Some(exif_orientation(1)));
+
+        // Tag is kept if image is not rotated
+        let reader = ImageReader::with_format(Cursor::new(png), ImageFormat::Png);
+        let (_, metadata) = decode(reader, false, &[MetadataKind::Exif]).unwrap();
+        assert_eq!(metadata.exif, Some(exif_orientation(6)));
+    }
+
+    #[test]
+    fn test_exif_orientation_is_applied() {
+        let image = decode_png(png_with_exif(Some(exif_orientation(6))), true);
+        assert_eq!(image.dimensions(), (1, 2));
+        assert_eq!(image.get_pixel(0, 0), &Rgba([255, 0, 0, 255]));
+        assert_eq!(image.get_pixel(0, 1), &Rgba([0, 0, 255, 255]));
+
+        let image = decode_png(png_with_exif(Some(exif_orientation(2))), true);
+        assert_eq!(image.get_pixel(0, 0), &Rgba([0, 0, 255, 255]));
+    }
+
+    #[test]
+    fn test_exif_orientation_is_ignored() {
+        let image = decode_png(png_with_exif(Some(exif_orientation(6))), false);
+        assert_eq!(image.dimensions(), (2, 1));
+        assert_eq!(image.get_pixel(0, 0), &Rgba([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn test_image_without_exif() {
+        let image = decode_png(png_with_exif(None), true);
+        assert_eq!(image.dimensions(), (2, 1));
+        assert_eq!(image.get_pixel(1, 0), &Rgba([0, 0, 255, 255]));
+    }
+
+    #[test]
+    fn test_high_precision_image_is_kept() {
+        let pixels: Vec<u8> = [1000u16, 2000, 3000, 4000, 5000, 6000]
+            .iter()
+            .flat_map(|value| value.to_ne_bytes())
+            .collect();
+        let mut png = Vec::new();
+        PngEncoder::new(&mut png)
+            .write_image(&pixels, 2, 1, image::ExtendedColorType::Rgb16)
+            .unwrap();
+        let reader = ImageReader::with_format(Cursor::new(png), ImageFormat::Png);
+        let (image, _) = decode(reader, false, &[]).unwrap();
+        let DynamicImage::ImageRgb16(image) = image else {
+            panic!("unexpected image type {:?}", image.color());
+        };
+        assert_eq!(image.as_raw(), &[1000, 2000, 3000, 4000, 5000, 6000]);
+
+        let rotated = orient(DynamicImage::ImageRgb16(image), Orientation::Rotate90);
+        assert_eq!(rotated.color(), ColorType::Rgb16);
+        assert_eq!(
+            rotated.into_rgb16().into_raw(),
+            [1000, 2000, 3000, 4000, 5000, 6000]
+        );
+    }
+
+    #[test]
+    fn test_encodable() {
+        let hdr = DynamicImage::ImageRgba32F(image::Rgba32FImage::from_pixel(
+            2,
+            2,
+            Rgba([0.5, 2.0, 0.0, 1.0]),
+        ));
+        assert!(encodable(&hdr, ImageFormat::Tiff).is_none());
+        assert!(encodable(&hdr, ImageFormat::OpenExr).is_none());
+        assert_eq!(
+            encodable(&hdr, ImageFormat::Png).map(|image| image.color()),
+            Some(ColorType::Rgba16)
+        );
+        assert_eq!(
+            encodable(&hdr, ImageFormat::Jpeg).map(|image| image.color()),
+            Some(ColorType::Rgba8)
+        );
+        assert_eq!(
+            encodable(&gradient(), ImageFormat::OpenExr).map(|image| image.color()),
+            Some(ColorType::Rgba32F)
+        );
+        assert!(encodable(&gradient(), ImageFormat::Png).is_none());
+
+        // Every format gets image its encoder supports
+        for format in [
+            ImageFormat::Png,
+            ImageFormat::Jpeg,
+            ImageFormat::WebP,
+            ImageFormat::Tiff,
+            ImageFormat::OpenExr,
+            ImageFormat::Bmp,
+        ] {
+            let encoded = encode(
+                &hdr,
+                format,
+                &Metadata::default(),
+                &EncodeOptions::default(),
+            );
+            assert!(encoded.is_ok(), "{format:?}");
+        }
+    }
+
+    fn gradient() -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 64, |x, y| {
+            Rgba([(x * 4) as u8, (y * 4) as u8, ((x + y) * 2) as u8, 255])
+        }))
+    }
+
+    #[test]
+    fn test_output_format() {
+        let png = Some(ImageFormat::Png);
+        assert_eq!(
+            output_format(Path::new("out.jpg"), None).unwrap(),
+            ImageFormat::Jpeg
+        );
+        assert_eq!(
+            output_format(Path::new("out.png"), png).unwrap(),
+            ImageFormat::Png
+        );
+        assert_eq!(
+            output_format(Path::new("out"), png).unwrap(),
+            ImageFormat::Png
+        );
+        assert!(matches!(
+            output_format(Path::new("out.jpg"), png),
+            Err(AppError::FormatMismatch { .. })
+        ));
+        assert!(output_format(Path::new("out"), None).is_err());
+    }
+
+    #[test]
+    fn test_parse_options() {
+        assert_eq!(parse_format("jpg").unwrap(), ImageFormat::Jpeg);
+        assert!(parse_format("txt").is_err());
+        assert_eq!(
+            parse_png_compression("best").unwrap(),
+            CompressionType::Best
+        );
+        assert_eq!(
+            parse_png_compression("6").unwrap(),
+            CompressionType::Level(6)
+        );
+        assert!(parse_png_compression("10").is_err());
+        assert_eq!(parse_png_filter("paeth").unwrap(), FilterType::Paeth);
+        assert!(parse_png_filter("median").is_err());
+    }
+
+    #[test]
+    fn test_jpeg_quality() {
+        let encoded_size = |jpeg_quality| {
+            let options = EncodeOptions {
+                jpeg_quality,
+                ..EncodeOptions::default()
+            };
+            let encoded = encode(
+                &gradient(),
+                ImageFormat::Jpeg,
+                &Metadata::default(),
+                &options,
+            );
+            encoded.unwrap().len()
+        };
+        assert!(encoded_size(20) < encoded_size(95));
+    }
+
+    #[test]
+    fn test_bit_depth() {
+        let options = EncodeOptions {
+            bit_depth: Some(BitDepth::Sixteen),
+            ..EncodeOptions::default()
+        };
+        let encoded = encode(
+            &gradient(),
+            ImageFormat::Png,
+            &Metadata::default(),
+            &options,
+        )
+        .unwrap();
+        let decoded = image::load_from_memory_with_format(&encoded, ImageFormat::Png).unwrap();
+        assert_eq!(decoded.color(), image::ColorType::Rgba16);
+        assert_eq!(decoded.to_rgba8(), gradient().to_rgba8());
+
+        let result = encode(
+            &gradient(),
+            ImageFormat::Jpeg,
+            &Metadata::default(),
+            &options,
+        );
+        assert!(matches!(
+            result,
+            Err(AppError::UnsupportedBitDepth { bits: 16, .. })
+        ));
+    }
+
+    #[test]
+    fn test_webp_lossless() {
+        let image = DynamicImage::ImageRgb8(gradient().to_rgb8());
+        let encoded = encode(
+            &image,
+            ImageFormat::WebP,
+            &Metadata::default(),
+            &EncodeOptions::default(),
+        )
+        .unwrap();
+        let decoded = image::load_from_memory_with_format(&encoded, ImageFormat::WebP).unwrap();
+        assert_eq!(decoded.color(), ColorType::Rgb8);
+        assert_eq!(decoded.to_rgb8(), image.to_rgb8());
+
+        let options = EncodeOptions {
+            webp_quality: Some(80),
+            ..EncodeOptions::default()
+        };
+        let result = encode(&image, ImageFormat::WebP, &Metadata::default(), &options);
+        assert!(matches!(result, Err(AppError::LossyWebPUnsupported)));
+    }
+}
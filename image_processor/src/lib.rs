@@ -3,5 +3,16 @@
 #![deny(unreachable_pub)]
 #![warn(missing_docs)]
 pub mod args;
+pub mod batch;
+pub mod discovery;
 pub mod error;
+pub mod image_io;
+pub mod mask;
+pub mod metadata;
+pub mod params;
+pub mod pipeline;
+pub mod pixels;
 pub mod plugin;
+pub mod progress;
+pub mod sandbox;
+pub mod schema;
@@ -1,36 +1,147 @@
-use std::{ffi::CString, fs};
+use std::{env, fs, path::PathBuf};
 
-use clap::Parser;
-use image::GenericImageView;
-use image_processor::{args::Args, error::AppError, plugin::Plugin};
+use image_processor::{
+    args::{Args, Command},
+    batch, discovery,
+    error::AppError,
+    pipeline::Pipeline,
+    plugin::Plugin,
+    progress::ProgressBar,
+    sandbox,
+};
 
 fn main() -> Result<(), anyhow::Error> {
-    let args = Args::parse();
+    let mut raw_args = env::args_os().skip(1);
+    if raw_args
+        .next()
+        .is_some_and(|arg| arg == sandbox::WORKER_ARG)
+    {
+        let plugin_file = PathBuf::from(raw_args.next().unwrap_or_default());
+        return Ok(sandbox::run_worker(&plugin_file)?);
+    }
+
+    let args = Args::parse_args();
+
+    match &args.command {
+        Some(Command::ListPlugins) => return list_plugins(&args),
+        Some(Command::PluginInfo { plugin, schema }) => return plugin_info(&args, plugin, *schema),
+        None => {}
+    }
 
     args.check_basic_paths_exists()?;
 
-    let plugin_lib = Plugin::new(args.plugin_file()?)?;
-    let interface = plugin_lib.interface()?;
+    let pipeline = Pipeline::load(&args)?;
 
-    let img = image::open(&args.input)?;
-    let (width, height) = img.dimensions();
-    let mut rgba_data = img.to_rgba8();
+    if args.is_batch() {
+        return run_batch(&args, &pipeline);
+    }
 
-    let params_content = fs::read_to_string(&args.params)?;
-    let c_params = CString::new(params_content)?;
+    let progress_bar = ProgressBar::for_terminal();
+    pipeline.process_file(args.input(), args.output(), progress_bar.as_ref())?;
 
-    let raw_data_ptr = rgba_data.as_mut_ptr();
+    println!("Image saved successfully");
 
-    let error_code =
-        unsafe { (interface.process_image_fn)(width, height, raw_data_ptr, c_params.as_ptr()) };
+    Ok(())
+}
 
-    if let Some(error) = AppError::from_plugin_error_code(error_code) {
-        return Err(error.into());
+fn run_batch(args: &Args, pipeline: &Pipeline) -> Result<(), anyhow::Error> {
+    let jobs = batch::collect_jobs(
+        args.input(),
+        args.output(),
+        &args.output_template,
+        args.format,
+    )?;
+
+    fs::create_dir_all(args.output()).map_err(|source| AppError::Directory {
+        path: args.output().to_string_lossy().to_string(),
+        source,
+    })?;
+
+    let mut failed = 0;
+    batch::run(
+        pipeline,
+        &jobs,
+        args.worker_count(),
+        |job, result| match result {
+            Ok(()) => println!("{} -> {}", job.input.display(), job.output.display()),
+            Err(e) => {
+                failed += 1;
+                eprintln!("{}: {:#}", job.input.display(), anyhow::Error::from(e));
+            }
+        },
+    );
+
+    println!(
+        "Processed {} images: {} succeeded, {failed} failed",
+        jobs.len(),
+        jobs.len() - failed
+    );
+
+    if failed > 0 {
+        return Err(AppError::BatchFailed {
+            failed,
+            total: jobs.len(),
+        }
+        .into());
     }
 
-    rgba_data.save(&args.output)?;
+    Ok(())
+}
 
-    println!("Image saved successfully");
+fn list_plugins(args: &Args) -> Result<(), anyhow::Error> {
+    args.check_plugin_dirs_exist()?;
+
+    let found = discovery::discover(&args.plugin_dirs());
+    for entry in &found.plugins {
+        let metadata = &entry.metadata;
+        println!(
+            "{} {} - {} ({})",
+            metadata.name,
+            metadata.version,
+            metadata.description,
+            entry.path.display()
+        );
+    }
+    if found.plugins.is_empty() {
+        println!("No plugins found");
+    }
+
+    for (path, e) in found.skipped {
+        eprintln!("Skipped '{}': {:#}", path.display(), anyhow::Error::from(e));
+    }
+
+    Ok(())
+}
+
+fn plugin_info(args: &Args, plugin: &str, schema_only: bool) -> Result<(), anyhow::Error> {
+    args.check_plugin_dirs_exist()?;
+
+    let plugin_file = args.plugin_file(plugin)?;
+    let plugin = Plugin::new(plugin_file.clone())?;
+    let metadata = plugin.metadata();
+    let schema = plugin
+        .params_schema()
+        .map(|schema| serde_json::to_string_pretty(schema.as_value()))
+        .transpose()?;
+
+    if schema_only {
+        println!("{}", schema.as_deref().unwrap_or("{}"));
+        return Ok(());
+    }
+
+    println!("Name:          {}", metadata.name);
+    println!("Version:       {}", metadata.version);
+    println!("Description:   {}", metadata.description);
+    println!("ABI version:   {}", metadata.abi_version);
+    println!("Capabilities:  {}", metadata.capability_names().join(", "));
+    println!(
+        "Pixel formats: {}",
+        metadata.pixel_format_names().join(", ")
+    );
+    println!("Path:          {}", plugin_file.display());
+    if let Some(schema) = schema {
+        println!("Params schema:\n{schema}");
+    }
 
     Ok(())
 }
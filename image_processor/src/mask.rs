@@ -0,0 +1,403 @@
+//! Limiting pipeline result to image regions
+//!
+//! Regions are cut out of image and processed separately, while grayscale mask blends result of
+//! processing of the whole image with original image, so plugins do not need to know about masks
+use image::{
+    DynamicImage, GenericImage, GenericImageView, GrayImage, ImageBuffer, Pixel, imageops,
+};
+
+use crate::error::AppError;
+
+/// Rectangle of image in pixels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Region {
+    /// Left column
+    pub x: u32,
+    /// Top row
+    pub y: u32,
+    /// Width in pixels
+    pub width: u32,
+    /// Height in pixels
+    pub height: u32,
+}
+
+impl Region {
+    /// Part of region inside of image of given size, None if region is out of image
+    fn clip(&self, width: u32, height: u32) -> Option<Region> {
+        let right = self.x.saturating_add(self.width).min(width);
+        let bottom = self.y.saturating_add(self.height).min(height);
+        (self.x < right && self.y < bottom).then(|| Region {
+            x: self.x,
+            y: self.y,
+            width: right - self.x,
+            height: bottom - self.y,
+        })
+    }
+}
+
+/// Part of image where pipeline result is used
+#[derive(Debug)]
+pub enum Mask {
+    /// Result is used inside of rectangles, which may overlap or exceed image bounds
+    Regions(Vec<Region>),
+    /// Grayscale image of the same size as processed image: white pixels take result,
+    /// black ones keep original, others blend them proportionally
+    Image(GrayImage),
+}
+
+impl Mask {
+    /// Apply `process` to masked part of `image`, `process` must keep size of image it gets
+    ///
+    /// Every region is cropped, processed and pasted back in place, so plugins see only pixels of region.
+    /// Overlapping regions are processed in given order, so their common part is processed several times.
+    /// Grayscale mask blends result of processing of the whole image with original image.
+    ///
+    /// Image is converted to pixel type of result. Result of type without own blending
+    /// is converted to floating point RGBA
+    pub fn apply<F>(&self, image: DynamicImage, mut process: F) -> Result<DynamicImage, AppError>
+    where
+        F: FnMut(DynamicImage) -> Result<DynamicImage, AppError>,
+    {
+        match self {
+            Mask::Regions(regions) => {
+                let mut image = image;
+                for region in regions {
+                    let Some(Region {
+                        x,
+                        y,
+                        width,
+                        height,
+                    }) = region.clip(image.width(), image.height())
+                    else {
+                        continue;
+                    };
+                    let part = process(image.crop_imm(x, y, width, height))?;
+                    check_size((width, height), part.dimensions())?;
+                    image = paste(image, part, x, y);
+                }
+                Ok(image)
+            }
+            Mask::Image(mask) => {
+                if mask.dimensions() != image.dimensions() {
+                    return Err(AppError::MaskSizeMismatch {
+                        mask: mask.dimensions(),
+                        image: image.dimensions(),
+                    });
+                }
+                let original = image.clone();
+                let mut result = process(image)?;
+                check_size(original.dimensions(), result.dimensions())?;
+                blend_image(&original, &mut result, mask);
+                Ok(result)
+            }
+        }
+    }
+}
+
+fn check_size(before: (u32, u32), after: (u32, u32)) -> Result<(), AppError> {
+    match before == after {
+        true => Ok(()),
+        false => Err(AppError::MaskedSizeChanged { before, after }),
+    }
+}
+
+/// Replace part of `image` at given position with `part`, converting image to pixel type of part
+fn paste(image: DynamicImage, part: DynamicImage, x: u32, y: u32) -> DynamicImage {
+    match part {
+        DynamicImage::ImageRgba8(part) => {
+            DynamicImage::ImageRgba8(replace(image.into_rgba8(), &part, x, y))
+        }
+        DynamicImage::ImageRgba16(part) => {
+            DynamicImage::ImageRgba16(replace(image.into_rgba16(), &part, x, y))
+        }
+        DynamicImage::ImageRgba32F(part) => {
+            DynamicImage::ImageRgba32F(replace(image.into_rgba32f(), &part, x, y))
+        }
+        DynamicImage::ImageLuma8(part) => {
+            DynamicImage::ImageLuma8(replace(image.into_luma8(), &part, x, y))
+        }
+        DynamicImage::ImageLuma16(part) => {
+            DynamicImage::ImageLuma16(replace(image.into_luma16(), &part, x, y))
+        }
+        other => {
+            DynamicImage::ImageRgba32F(replace(image.into_rgba32f(), &other.into_rgba32f(), x, y))
+        }
+    }
+}
+
+fn replace<I: GenericImage>(
+    mut image: I,
+    part: &impl GenericImageView<Pixel = I::Pixel>,
+    x: u32,
+    y: u32,
+) -> I {
+    imageops::replace(&mut image, part, x as i64, y as i64);
+    image
+}
+
+/// Blend `result` with `original` of the same size by mask
+fn blend_image(original: &DynamicImage, result: &mut DynamicImage, mask: &GrayImage) {
+    match result {
+        DynamicImage::ImageRgba8(result) => blend(&original.to_rgba8(), result, mask),
+        DynamicImage::ImageRgba16(result) => blend(&original.to_rgba16(), result, mask),
+        DynamicImage::ImageRgba32F(result) => blend(&original.to_rgba32f(), result, mask),
+        DynamicImage::ImageLuma8(result) => blend(&original.to_luma8(), result, mask),
+        DynamicImage::ImageLuma16(result) => blend(&original.to_luma16(), result, mask),
+        other => {
+            let mut rgba = other.to_rgba32f();
+            blend(&original.to_rgba32f(), &mut rgba, mask);
+            *other = DynamicImage::ImageRgba32F(rgba);
+        }
+    }
+}
+
+/// Mix every channel of `result` with `original` by mask value
+fn blend<P>(
+    original: &ImageBuffer<P, Vec<P::Subpixel>>,
+    result: &mut ImageBuffer<P, Vec<P::Subpixel>>,
+    mask: &GrayImage,
+) where
+    P: Pixel,
+    P::Subpixel: Blend,
+{
+    for ((result, original), value) in result
+        .pixels_mut()
+        .zip(original.pixels())
+        .zip(mask.pixels())
+    {
+        for (result, original) in result.channels_mut().iter_mut().zip(original.channels()) {
+            *result = result.blend(*original, value.0[0]);
+        }
+    }
+}
+
+/// Channel value which can be mixed with another one by 8-bit mask value
+trait Blend: Copy {
+    /// Mix of `self` taken with weight `value / 255` and `other` taken with the rest
+    fn blend(self, other: Self, value: u8) -> Self;
+}
+
+impl Blend for u8 {
+    fn blend(self, other: Self, value: u8) -> Self {
+        let value = value as u32;
+        ((self as u32 * value + other as u32 * (255 - value) + 127) / 255) as u8
+    }
+}
+
+impl Blend for u16 {
+    fn blend(self, other: Self, value: u8) -> Self {
+        let value = value as u32;
+        ((self as u32 * value + other as u32 * (255 - value) + 127) / 255) as u16
+    }
+}
+
+impl Blend for f32 {
+    fn blend(self, other: Self, value: u8) -> Self {
+        let value = value as f32 / 255.0;
+        self * value + other * (1.0 - value)
+    }
+}
+
+/// Parse `--region X,Y,WIDTH,HEIGHT` with non-empty size
+pub fn parse_region(value: &str) -> Result<Region, AppError> {
+    let numbers: Vec<u32> = value
+        .split(',')
+        .map(|number| number.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| AppError::InvalidRegion(value.to_string()))?;
+
+    match numbers[..] {
+        [x, y, width, height] if width > 0 && height > 0 => Ok(Region {
+            x,
+            y,
+            width,
+            height,
+        }),
+        _ => Err(AppError::InvalidRegion(value.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Luma, Rgba, RgbaImage};
+
+    fn solid(width: u32, height: u32, value: u8) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(width, height, Rgba([value; 4])))
+    }
+
+    #[test]
+    fn test_parse_region() {
+        assert_eq!(
+            parse_region("1, 2,30,40").unwrap(),
+            Region {
+                x: 1,
+                y: 2,
+                width: 30,
+                height: 40
+            }
+        );
+        for value in ["1,2,3", "1,2,3,4,5", "a,2,3,4", "1,2,0,4", "-1,2,3,4"] {
+            assert!(
+                matches!(parse_region(value), Err(AppError::InvalidRegion(_))),
+                "{value}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_regions_mask() {
+        let mask = Mask::Regions(vec![
+            Region {
+                x: 0,
+                y: 0,
+                width: 1,
+                height: 1,
+            },
+            Region {
+                x: 2,
+                y: 1,
+                width: 10,
+                height: 10,
+            },
+            Region {
+                x: 5,
+                y: 0,
+                width: 1,
+                height: 1,
+            },
+        ]);
+        let mut sizes = Vec::new();
+        let result = mask
+            .apply(solid(3, 2, 0), |part| {
+                sizes.push(part.dimensions());
+                Ok(solid(part.width(), part.height(), 200))
+            })
+            .unwrap();
+
+        // Region out of image is skipped, other ones are clipped by image bounds
+        assert_eq!(sizes, [(1, 1), (1, 1)]);
+        let values: Vec<u8> = result.to_rgba8().pixels().map(|pixel| pixel.0[0]).collect();
+        assert_eq!(values, [200, 0, 0, 0, 0, 200]);
+    }
+
+    #[test]
+    fn test_region_is_processed_separately() {
+        let image = DynamicImage::ImageLuma8(GrayImage::from_fn(6, 1, |x, _| Luma([x as u8])));
+        let mask = Mask::Regions(vec![Region {
+            x: 1,
+            y: 0,
+            width: 3,
+            height: 1,
+        }]);
+        let result = mask.apply(image, |part| Ok(part.fliph())).unwrap();
+
+        // Region is mirrored in place instead of taking pixels from the opposite side of image
+        assert_eq!(result.to_luma8().into_raw(), [0, 3, 2, 1, 4, 5]);
+    }
+
+    #[test]
+    fn test_regions_take_result_type() {
+        let mask = Mask::Regions(vec![Region {
+            x: 0,
+            y: 0,
+            width: 1,
+            height: 1,
+        }]);
+        let result = mask
+            .apply(solid(2, 1, 255), |_| {
+                Ok(DynamicImage::ImageLuma16(ImageBuffer::from_pixel(
+                    1,
+                    1,
+                    Luma([1000]),
+                )))
+            })
+            .unwrap();
+        let DynamicImage::ImageLuma16(result) = result else {
+            panic!("image is not converted to result type");
+        };
+        assert_eq!(result.into_raw(), [1000, 65535]);
+    }
+
+    #[test]
+    fn test_image_mask_blends() {
+        let mask = Mask::Image(GrayImage::from_raw(3, 1, vec![0, 128, 255]).unwrap());
+        let result = mask
+            .apply(solid(3, 1, 100), |_| Ok(solid(3, 1, 200)))
+            .unwrap();
+
+        let values: Vec<u8> = result.to_rgba8().pixels().map(|pixel| pixel.0[3]).collect();
+        assert_eq!(values, [100, 150, 200]);
+    }
+
+    #[test]
+    fn test_mask_keeps_result_type() {
+        let mask = Mask::Image(GrayImage::from_raw(3, 1, vec![0, 128, 255]).unwrap());
+
+        let result = mask
+            .apply(solid(3, 1, 100), |_| {
+                Ok(DynamicImage::ImageLuma16(ImageBuffer::from_pixel(
+                    3,
+                    1,
+                    Luma([51400]),
+                )))
+            })
+            .unwrap();
+        let DynamicImage::ImageLuma16(result) = result else {
+            panic!("result type is changed");
+        };
+        let values: Vec<u16> = result.pixels().map(|pixel| pixel.0[0]).collect();
+        assert_eq!(values, [25700, 38600, 51400]);
+
+        // Floating point grayscale returned by plugins is stored as RGB
+        let result = mask
+            .apply(solid(3, 1, 100), |_| {
+                Ok(DynamicImage::ImageRgb32F(ImageBuffer::from_pixel(
+                    3,
+                    1,
+                    image::Rgb([2.0; 3]),
+                )))
+            })
+            .unwrap();
+        let DynamicImage::ImageRgba32F(result) = result else {
+            panic!("result is not converted to RGBA");
+        };
+        assert!((result.get_pixel(0, 0).0[0] - 100.0 / 255.0).abs() < 1e-6);
+        assert_eq!(result.get_pixel(2, 0).0, [2.0, 2.0, 2.0, 1.0]);
+    }
+
+    #[test]
+    fn test_mask_size_mismatch() {
+        let mask = Mask::Image(GrayImage::new(2, 2));
+        let result = mask.apply(solid(3, 2, 0), |_| panic!("image is processed"));
+        assert!(matches!(
+            result,
+            Err(AppError::MaskSizeMismatch {
+                mask: (2, 2),
+                image: (3, 2)
+            })
+        ));
+    }
+
+    #[test]
+    fn test_mask_requires_same_result_size() {
+        let mask = Mask::Image(GrayImage::new(3, 2));
+        let result = mask.apply(solid(3, 2, 0), |_| Ok(solid(2, 3, 0)));
+        assert!(matches!(result, Err(AppError::MaskedSizeChanged { .. })));
+
+        let mask = Mask::Regions(vec![Region {
+            x: 1,
+            y: 1,
+            width: 2,
+            height: 1,
+        }]);
+        let result = mask.apply(solid(3, 2, 0), |_| Ok(solid(1, 1, 0)));
+        assert!(matches!(
+            result,
+            Err(AppError::MaskedSizeChanged {
+                before: (2, 1),
+                after: (1, 1)
+            })
+        ));
+    }
+}
@@ -0,0 +1,197 @@
+//! Metadata of input image carried to result: EXIF, ICC color profile and XMP
+use std::fmt;
+
+use clap::ValueEnum;
+use image::{ImageDecoder, ImageFormat, ImageResult};
+
+/// Kind of metadata chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MetadataKind {
+    /// EXIF: camera settings, capture date, orientation
+    Exif,
+    /// ICC color profile
+    Icc,
+    /// XMP packet: editing history, rating, description
+    Xmp,
+}
+
+impl MetadataKind {
+    /// All kinds of metadata, which are kept by default
+    pub const ALL: [MetadataKind; 3] = [MetadataKind::Exif, MetadataKind::Icc, MetadataKind::Xmp];
+}
+
+impl fmt::Display for MetadataKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MetadataKind::Exif => "EXIF",
+            MetadataKind::Icc => "ICC",
+            MetadataKind::Xmp => "XMP",
+        })
+    }
+}
+
+/// Raw metadata chunks read from input image
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Metadata {
+    /// EXIF block in TIFF format, without `Exif\0\0` prefix
+    pub exif: Option<Vec<u8>>,
+    /// ICC color profile
+    pub icc: Option<Vec<u8>>,
+    /// XMP packet
+    pub xmp: Option<Vec<u8>>,
+}
+
+impl Metadata {
+    /// Read metadata chunks of given `kinds` from decoder of input image
+    pub fn read(decoder: &mut impl ImageDecoder, kinds: &[MetadataKind]) -> ImageResult<Self> {
+        let mut metadata = Metadata::default();
+        for kind in kinds {
+            match kind {
+                MetadataKind::Exif => metadata.exif = decoder.exif_metadata()?,
+                MetadataKind::Icc => metadata.icc = decoder.icc_profile()?,
+                MetadataKind::Xmp => metadata.xmp = decoder.xmp_metadata()?,
+            }
+        }
+        Ok(metadata)
+    }
+
+    /// Reset EXIF orientation tag after image was rotated according to it
+    pub fn reset_orientation(&mut self) {
+        if let Some(exif) = &mut self.exif {
+            let _ = image::metadata::Orientation::remove_from_exif_chunk(exif);
+        }
+    }
+
+    /// Add XMP packet to image encoded in `format`, since image encoders do not support XMP.
+    /// Unchanged image is returned as error for other formats or if packet does not fit into format limits
+    pub(crate) fn embed_xmp(
+        &self,
+        format: ImageFormat,
+        encoded: Vec<u8>,
+    ) -> Result<Vec<u8>, Vec<u8>> {
+        let Some(xmp) = &self.xmp else {
+            return Ok(encoded);
+        };
+        match format {
+            ImageFormat::Png => png_with_xmp(encoded, xmp),
+            ImageFormat::Jpeg => jpeg_with_xmp(encoded, xmp),
+            ImageFormat::WebP => webp_with_xmp(encoded, xmp),
+            _ => Err(encoded),
+        }
+    }
+}
+
+/// Add XMP as `iTXt` chunk before image data, where decoders read text chunks from
+fn png_with_xmp(png: Vec<u8>, xmp: &[u8]) -> Result<Vec<u8>, Vec<u8>> {
+    let mut pos = 8;
+    while pos + 8 <= png.len() && &png[pos + 4..pos + 8] != b"IDAT" {
+        let len = u32::from_be_bytes(png[pos..pos + 4].try_into().unwrap()) as usize;
+        pos += len + 12;
+    }
+    if pos + 8 > png.len() {
+        return Err(png);
+    }
+
+    // Keyword, uncompressed text without language and translated keyword
+    let mut chunk = b"iTXtXML:com.adobe.xmp\0\0\0\0\0".to_vec();
+    chunk.extend_from_slice(xmp);
+    let Ok(len) = u32::try_from(chunk.len() - 4) else {
+        return Err(png);
+    };
+
+    let mut result = Vec::with_capacity(png.len() + chunk.len() + 8);
+    result.extend_from_slice(&png[..pos]);
+    result.extend_from_slice(&len.to_be_bytes());
+    result.extend_from_slice(&chunk);
+    result.extend_from_slice(&crc32fast::hash(&chunk).to_be_bytes());
+    result.extend_from_slice(&png[pos..]);
+    Ok(result)
+}
+
+/// Add XMP as `APP1` segment after other application segments
+fn jpeg_with_xmp(jpeg: Vec<u8>, xmp: &[u8]) -> Result<Vec<u8>, Vec<u8>> {
+    const NAMESPACE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+    // Segment length includes 2 bytes of length itself, bigger packets need extended XMP
+    let Ok(len) = u16::try_from(2 + NAMESPACE.len() + xmp.len()) else {
+        return Err(jpeg);
+    };
+
+    let mut pos = 2;
+    while pos + 4 <= jpeg.len() && jpeg[pos] == 0xff && (0xe0..=0xef).contains(&jpeg[pos + 1]) {
+        pos += 2 + u16::from_be_bytes([jpeg[pos + 2], jpeg[pos + 3]]) as usize;
+    }
+    if pos > jpeg.len() {
+        return Err(jpeg);
+    }
+
+    let mut result = Vec::with_capacity(jpeg.len() + len as usize + 2);
+    result.extend_from_slice(&jpeg[..pos]);
+    result.extend_from_slice(&[0xff, 0xe1]);
+    result.extend_from_slice(&len.to_be_bytes());
+    result.extend_from_slice(NAMESPACE);
+    result.extend_from_slice(xmp);
+    result.extend_from_slice(&jpeg[pos..]);
+    Ok(result)
+}
+
+/// Add XMP as last `XMP ` chunk, which requires extended `VP8X` header with XMP flag
+fn webp_with_xmp(webp: Vec<u8>, xmp: &[u8]) -> Result<Vec<u8>, Vec<u8>> {
+    const XMP_FLAG: u8 = 0x04;
+    const ALPHA_FLAG: u8 = 0x10;
+
+    if webp.len() < 30 || &webp[..4] != b"RIFF" || &webp[8..12] != b"WEBP" {
+        return Err(webp);
+    }
+
+    let mut result = Vec::with_capacity(webp.len() + xmp.len() + 27);
+    result.extend_from_slice(&webp[..12]);
+    match &webp[12..16] {
+        b"VP8X" => {
+            result.extend_from_slice(&webp[12..]);
+            result[20] |= XMP_FLAG;
+        }
+        simple_format => {
+            // Canvas size is stored in simple lossless or lossy header
+            let (width, height, alpha) = match simple_format {
+                b"VP8L" => {
+                    let bits = u32::from_le_bytes(webp[21..25].try_into().unwrap());
+                    (bits & 0x3fff, (bits >> 14) & 0x3fff, bits & (1 << 28) != 0)
+                }
+                b"VP8 " => {
+                    let width = u16::from_le_bytes([webp[26], webp[27]]) & 0x3fff;
+                    let height = u16::from_le_bytes([webp[28], webp[29]]) & 0x3fff;
+                    (
+                        width.saturating_sub(1) as u32,
+                        height.saturating_sub(1) as u32,
+                        false,
+                    )
+                }
+                _ => return Err(webp),
+            };
+            let flags = XMP_FLAG | if alpha { ALPHA_FLAG } else { 0 };
+            result.extend_from_slice(b"VP8X");
+            result.extend_from_slice(&10u32.to_le_bytes());
+            result.extend_from_slice(&[flags, 0, 0, 0]);
+            result.extend_from_slice(&width.to_le_bytes()[..3]);
+            result.extend_from_slice(&height.to_le_bytes()[..3]);
+            result.extend_from_slice(&webp[12..]);
+        }
+    }
+
+    let Ok(len) = u32::try_from(xmp.len()) else {
+        return Err(webp);
+    };
+    result.extend_from_slice(b"XMP ");
+    result.extend_from_slice(&len.to_le_bytes());
+    result.extend_from_slice(xmp);
+    if xmp.len() % 2 == 1 {
+        result.push(0);
+    }
+
+    let Ok(riff_size) = u32::try_from(result.len() - 8) else {
+        return Err(webp);
+    };
+    result[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Ok(result)
+}
@@ -0,0 +1,173 @@
+//! Plugin params built from params file and inline `--params-json` and `--param` values
+use std::{ffi::CString, fs, path::Path};
+
+use serde_json::{Map, Value};
+
+use crate::error::AppError;
+
+/// Parse `--param KEY=VALUE` into JSON object with single property.
+///
+/// Dots in key describe nested objects, so `crop.x=10` gives `{"crop": {"x": 10}}`.
+/// Value is parsed as JSON and is used as a string if it is not a valid JSON, so `filter=nearest` is accepted
+pub fn parse_param(value: &str) -> Result<Value, AppError> {
+    let invalid = || AppError::InvalidParam(value.to_string());
+
+    let (key, raw) = value.split_once('=').ok_or_else(invalid)?;
+    if key.split('.').any(str::is_empty) {
+        return Err(invalid());
+    }
+
+    let mut param = serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string()));
+    for name in key.rsplit('.') {
+        param = Value::Object(Map::from_iter([(name.to_string(), param)]));
+    }
+
+    Ok(param)
+}
+
+/// Parse `--params-json` value, which should be a JSON object
+pub fn parse_params_json(value: &str) -> Result<Value, AppError> {
+    parse_object(value, "--params-json")
+}
+
+/// Build params string passed to plugin: `inline` objects are merged over params `file` in given order.
+/// Missing file is treated as empty object, so plugin uses its defaults
+///
+/// File content is passed unchanged if there are no inline params
+pub fn load(file: Option<&Path>, inline: &[Value]) -> Result<CString, AppError> {
+    let content = match file {
+        Some(file) => {
+            let path = file.to_string_lossy().to_string();
+            let content = fs::read_to_string(file).map_err(|source| AppError::ParamsFileRead {
+                path: path.clone(),
+                source,
+            })?;
+            if inline.is_empty() {
+                return CString::new(content).map_err(|_| AppError::ParamsContainNul(path));
+            }
+            parse_object(&content, &path)?
+        }
+        None => Value::Object(Map::new()),
+    };
+
+    let params = inline.iter().fold(content, |mut params, overlay| {
+        merge(&mut params, overlay.clone());
+        params
+    });
+
+    CString::new(params.to_string()).map_err(|_| AppError::ParamsContainNul("inline params".into()))
+}
+
+/// Merge `overlay` into `base`. Nested objects are merged recursively, other values are replaced
+pub fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+fn parse_object(value: &str, origin: &str) -> Result<Value, AppError> {
+    let invalid = |message: String| AppError::InvalidParamsJson {
+        origin: origin.to_string(),
+        message,
+    };
+
+    match serde_json::from_str(value) {
+        Ok(value @ Value::Object(_)) => Ok(value),
+        Ok(_) => Err(invalid("expected JSON object".to_string())),
+        Err(e) => Err(invalid(e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_param() {
+        assert_eq!(parse_param("radius=3").unwrap(), json!({ "radius": 3 }));
+        assert_eq!(
+            parse_param("weighted=true").unwrap(),
+            json!({ "weighted": true })
+        );
+        assert_eq!(
+            parse_param("filter=nearest").unwrap(),
+            json!({ "filter": "nearest" })
+        );
+        assert_eq!(
+            parse_param("crop.x=10").unwrap(),
+            json!({ "crop": { "x": 10 } })
+        );
+        assert_eq!(parse_param("name=").unwrap(), json!({ "name": "" }));
+    }
+
+    #[test]
+    fn test_parse_param_invalid() {
+        for value in ["radius", "=3", "crop..x=1", "crop.=1"] {
+            assert!(matches!(parse_param(value), Err(AppError::InvalidParam(_))));
+        }
+    }
+
+    #[test]
+    fn test_parse_params_json() {
+        assert_eq!(
+            parse_params_json(r#"{ "radius": 2 }"#).unwrap(),
+            json!({ "radius": 2 })
+        );
+        assert!(matches!(
+            parse_params_json("[1]"),
+            Err(AppError::InvalidParamsJson { .. })
+        ));
+        assert!(matches!(
+            parse_params_json("{ radius: 2 }"),
+            Err(AppError::InvalidParamsJson { .. })
+        ));
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut params = json!({ "radius": 1, "crop": { "x": 1, "y": 2 } });
+        merge(&mut params, json!({ "crop": { "x": 5 }, "weighted": true }));
+        assert_eq!(
+            params,
+            json!({ "radius": 1, "crop": { "x": 5, "y": 2 }, "weighted": true })
+        );
+    }
+
+    #[test]
+    fn test_load_merges_inline_over_file() {
+        let params = load(
+            Some(Path::new("../demo/blur_box.json")),
+            &[
+                json!({ "radius": 7 }),
+                parse_param("weighted=true").unwrap(),
+            ],
+        )
+        .unwrap();
+        let params: Value = serde_json::from_str(params.to_str().unwrap()).unwrap();
+        assert_eq!(params["radius"], 7);
+        assert_eq!(params["weighted"], true);
+    }
+
+    #[test]
+    fn test_load_without_file() {
+        assert_eq!(load(None, &[]).unwrap().as_c_str(), c"{}");
+        assert_eq!(
+            load(None, &[json!({ "horizontal": true })])
+                .unwrap()
+                .as_c_str(),
+            c"{\"horizontal\":true}"
+        );
+    }
+}
@@ -0,0 +1,281 @@
+//! Chain of plugins applied one after another to the same image
+use std::{
+    collections::HashMap,
+    ffi::{CStr, CString},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError},
+    },
+    thread,
+    time::Duration,
+};
+
+use image::DynamicImage;
+
+use crate::{
+    args::Args,
+    error::AppError,
+    image_io::{self, EncodeOptions},
+    mask::Mask,
+    metadata::MetadataKind,
+    params,
+    plugin::{CallContext, Plugin},
+    progress::ProgressBar,
+    sandbox::IsolatedPlugin,
+};
+
+/// Loaded plugins and params of every pipeline step
+pub struct Pipeline {
+    plugins: Vec<StepPlugin>,
+    steps: Vec<Step>,
+    mask: Option<Mask>,
+    auto_orient: bool,
+    metadata_kinds: Vec<MetadataKind>,
+    encode_options: EncodeOptions,
+}
+
+/// Plugin loaded into app process or called in worker process
+enum StepPlugin {
+    InProcess {
+        plugin: Plugin,
+        timeout: Option<Duration>,
+        thread_count: u32,
+    },
+    Isolated(IsolatedPlugin),
+}
+
+impl StepPlugin {
+    /// Apply plugin to image. Progress is reported only by plugins loaded into app process
+    fn apply(
+        &self,
+        image: DynamicImage,
+        params: &CStr,
+        progress: Option<&(dyn Fn(u64, u64) + Sync)>,
+    ) -> Result<DynamicImage, AppError> {
+        match self {
+            StepPlugin::InProcess {
+                plugin,
+                timeout,
+                thread_count,
+            } => run_with_timeout(*timeout, |cancel_flag| {
+                let ctx = CallContext {
+                    cancel_flag,
+                    progress,
+                    thread_count: *thread_count,
+                };
+                plugin.apply(image, params, &ctx)
+            }),
+            StepPlugin::Isolated(plugin) => plugin.apply(image, params),
+        }
+    }
+}
+
+/// Run `process` with cancellation flag which is set once `timeout` is exceeded.
+/// Error of `process` is replaced with `AppError::Timeout` if time limit was exceeded,
+/// while finished result is kept even if timer fired right after processing ended
+fn run_with_timeout<T>(
+    timeout: Option<Duration>,
+    process: impl FnOnce(&AtomicBool) -> Result<T, AppError>,
+) -> Result<T, AppError> {
+    let cancel_flag = AtomicBool::new(false);
+
+    let Some(timeout) = timeout else {
+        return process(&cancel_flag);
+    };
+
+    let (done_sender, done_receiver) = mpsc::channel::<()>();
+
+    thread::scope(|scope| {
+        let cancel_flag = &cancel_flag;
+        scope.spawn(move || {
+            if let Err(RecvTimeoutError::Timeout) = done_receiver.recv_timeout(timeout) {
+                cancel_flag.store(true, Ordering::Relaxed);
+            }
+        });
+
+        let result = process(cancel_flag);
+        drop(done_sender);
+
+        match result {
+            Err(_) if cancel_flag.load(Ordering::Relaxed) => Err(AppError::Timeout(timeout)),
+            result => result,
+        }
+    })
+}
+
+struct Step {
+    plugin_name: String,
+    plugin_idx: usize,
+    params: CString,
+}
+
+impl Pipeline {
+    /// Load every plugin used in pipeline steps once and read params of each step
+    ///
+    /// Params are checked against schemas exported by plugins, so misconfigured step fails before any image is processed.
+    /// With `--isolate` plugins are not loaded into app process, only their paths are checked,
+    /// and params are checked by worker process before calling plugin
+    pub fn load(args: &Args) -> Result<Self, AppError> {
+        let mut plugins = Vec::new();
+        let mut loaded: HashMap<String, usize> = HashMap::new();
+        let mut steps = Vec::new();
+        let thread_count = args.plugin_thread_count();
+
+        for step in args.steps() {
+            let plugin_idx = match loaded.get(&step.plugin) {
+                Some(idx) => *idx,
+                None => {
+                    let plugin_file = args.plugin_file(&step.plugin)?;
+                    plugins.push(match args.isolate {
+                        true => StepPlugin::Isolated(IsolatedPlugin::new(
+                            plugin_file,
+                            args.timeout,
+                            thread_count,
+                        )),
+                        false => StepPlugin::InProcess {
+                            plugin: Plugin::new(plugin_file)?,
+                            timeout: args.timeout,
+                            thread_count,
+                        },
+                    });
+                    loaded.insert(step.plugin.clone(), plugins.len() - 1);
+                    plugins.len() - 1
+                }
+            };
+
+            let params = params::load(step.params.as_deref(), &step.inline_params)?;
+
+            if let StepPlugin::InProcess { plugin, .. } = &plugins[plugin_idx] {
+                plugin.validate_params(&params)?;
+            }
+
+            steps.push(Step {
+                plugin_name: step.plugin,
+                plugin_idx,
+                params,
+            });
+        }
+
+        let mask = match &args.mask {
+            Some(path) => Some(Mask::Image(
+                image::open(path)
+                    .map_err(|source| AppError::MaskLoad {
+                        path: path.to_string_lossy().to_string(),
+                        source,
+                    })?
+                    .to_luma8(),
+            )),
+            None if !args.region.is_empty() => Some(Mask::Regions(args.region.clone())),
+            None => None,
+        };
+
+        Ok(Pipeline {
+            plugins,
+            steps,
+            mask,
+            auto_orient: !args.no_auto_orient,
+            metadata_kinds: args.metadata_kinds(),
+            encode_options: args.encode_options(),
+        })
+    }
+
+    /// Apply all steps to image in order and return resulting image,
+    /// which may have other dimensions and pixel format. Stops on first failed step
+    ///
+    /// If mask is set, only masked part of image is changed, so steps must keep image size
+    ///
+    /// Progress reported by plugins is drawn on `progress_bar` if it is given
+    pub fn run(
+        &self,
+        image: DynamicImage,
+        progress_bar: Option<&ProgressBar>,
+    ) -> Result<DynamicImage, AppError> {
+        match &self.mask {
+            Some(mask) => mask.apply(image, |image| self.run_steps(image, progress_bar)),
+            None => self.run_steps(image, progress_bar),
+        }
+    }
+
+    fn run_steps(
+        &self,
+        mut image: DynamicImage,
+        progress_bar: Option<&ProgressBar>,
+    ) -> Result<DynamicImage, AppError> {
+        for (idx, step) in self.steps.iter().enumerate() {
+            let report = |done, total| {
+                if let Some(bar) = progress_bar {
+                    bar.update(idx + 1, self.steps.len(), &step.plugin_name, done, total);
+                }
+            };
+
+            let result = self.plugins[step.plugin_idx].apply(
+                image,
+                &step.params,
+                progress_bar.map(|_| &report as &(dyn Fn(u64, u64) + Sync)),
+            );
+            if let Some(bar) = progress_bar {
+                bar.finish();
+            }
+
+            image = result.map_err(|source| AppError::PipelineStepFailed {
+                step: idx + 1,
+                plugin: step.plugin_name.clone(),
+                source: Box::new(source),
+            })?;
+        }
+
+        Ok(image)
+    }
+
+    /// Read image from `input`, apply all steps and save result to `output`
+    ///
+    /// Unless disabled, image is rotated according to its EXIF orientation tag before the first step.
+    /// Metadata of input image is copied to result if output format supports it,
+    /// result is encoded with format and settings given in args
+    pub fn process_file(
+        &self,
+        input: &Path,
+        output: &Path,
+        progress_bar: Option<&ProgressBar>,
+    ) -> Result<(), AppError> {
+        let (img, metadata) = image_io::read_image(input, self.auto_orient, &self.metadata_kinds)?;
+        let result = self.run(img, progress_bar)?;
+        image_io::write_image(output, &result, &metadata, &self.encode_options)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_with_timeout_finished_in_time() {
+        let result = run_with_timeout(Some(Duration::from_secs(10)), |_| Ok(42));
+        assert_eq!(result.unwrap(), 42);
+    }
+
+    #[test]
+    fn test_run_with_timeout_sets_cancel_flag() {
+        let result = run_with_timeout(Some(Duration::from_millis(10)), |cancel_flag| {
+            while !cancel_flag.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(1));
+            }
+            Err::<(), _>(AppError::PluginCancelled)
+        });
+        assert!(matches!(result, Err(AppError::Timeout(_))));
+    }
+
+    #[test]
+    fn test_run_with_timeout_keeps_finished_result() {
+        let result = run_with_timeout(Some(Duration::from_millis(1)), |cancel_flag| {
+            while !cancel_flag.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(1));
+            }
+            Ok(42)
+        });
+        assert_eq!(result.unwrap(), 42);
+    }
+}
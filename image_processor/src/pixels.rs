@@ -0,0 +1,336 @@
+//! Image data in pixel formats of plugin ABI and choice of format passed to plugin
+use image::{DynamicImage, GrayImage, ImageBuffer, Limits, Luma, Rgba, Rgba32FImage, RgbaImage};
+use plugin_abi::PixelFormat;
+
+use crate::error::AppError;
+
+type Rgba16Image = ImageBuffer<Rgba<u16>, Vec<u16>>;
+type Gray16Image = ImageBuffer<Luma<u16>, Vec<u16>>;
+type Gray32FImage = ImageBuffer<Luma<f32>, Vec<f32>>;
+
+/// Image stored in one of `PixelFormat`s, so its data can be passed to plugin as is
+///
+/// Channels are stored in vectors of channel type, so data is aligned to channel size as ABI requires
+#[derive(Debug, Clone, PartialEq)]
+pub struct PixelBuffer(Buffer);
+
+#[derive(Debug, Clone, PartialEq)]
+enum Buffer {
+    Rgba8(RgbaImage),
+    Rgba16(Rgba16Image),
+    Rgba32F(Rgba32FImage),
+    Gray8(GrayImage),
+    Gray16(Gray16Image),
+    Gray32F(Gray32FImage),
+}
+
+impl PixelBuffer {
+    /// Convert image to given format. Image already stored in this format is moved without copying
+    pub fn from_image(image: DynamicImage, format: PixelFormat) -> Self {
+        PixelBuffer(match format {
+            PixelFormat::Rgba8 => Buffer::Rgba8(image.into_rgba8()),
+            PixelFormat::Rgba16 => Buffer::Rgba16(image.into_rgba16()),
+            PixelFormat::Rgba32F => Buffer::Rgba32F(image.into_rgba32f()),
+            PixelFormat::Gray8 => Buffer::Gray8(image.into_luma8()),
+            PixelFormat::Gray16 => Buffer::Gray16(image.into_luma16()),
+            PixelFormat::Gray32F => Buffer::Gray32F(image.to_luma32f()),
+        })
+    }
+
+    /// Zero-filled image of given format and dimensions
+    ///
+    /// Dimensions reported by plugins are limited by the same allocation limit as decoded images,
+    /// so huge output size is an error rather than out of memory abort
+    pub fn new(format: PixelFormat, width: u32, height: u32) -> Result<Self, AppError> {
+        let max_size = Limits::default().max_alloc.unwrap_or(u64::MAX);
+        match format.data_size(width, height) {
+            Some(size) if size as u64 <= max_size => {}
+            _ => return Err(AppError::SizeIsTooBig),
+        }
+
+        Ok(PixelBuffer(match format {
+            PixelFormat::Rgba8 => Buffer::Rgba8(ImageBuffer::new(width, height)),
+            PixelFormat::Rgba16 => Buffer::Rgba16(ImageBuffer::new(width, height)),
+            PixelFormat::Rgba32F => Buffer::Rgba32F(ImageBuffer::new(width, height)),
+            PixelFormat::Gray8 => Buffer::Gray8(ImageBuffer::new(width, height)),
+            PixelFormat::Gray16 => Buffer::Gray16(ImageBuffer::new(width, height)),
+            PixelFormat::Gray32F => Buffer::Gray32F(ImageBuffer::new(width, height)),
+        }))
+    }
+
+    /// Read image from channel values in native byte order, None if data size does not match dimensions
+    pub fn from_bytes(format: PixelFormat, width: u32, height: u32, bytes: &[u8]) -> Option<Self> {
+        if format.data_size(width, height) != Some(bytes.len()) {
+            return None;
+        }
+
+        Some(PixelBuffer(match format {
+            PixelFormat::Rgba8 => {
+                Buffer::Rgba8(ImageBuffer::from_raw(width, height, bytes.to_vec())?)
+            }
+            PixelFormat::Rgba16 => Buffer::Rgba16(ImageBuffer::from_raw(
+                width,
+                height,
+                values(bytes, u16::from_ne_bytes),
+            )?),
+            PixelFormat::Rgba32F => Buffer::Rgba32F(ImageBuffer::from_raw(
+                width,
+                height,
+                values(bytes, f32::from_ne_bytes),
+            )?),
+            PixelFormat::Gray8 => {
+                Buffer::Gray8(ImageBuffer::from_raw(width, height, bytes.to_vec())?)
+            }
+            PixelFormat::Gray16 => Buffer::Gray16(ImageBuffer::from_raw(
+                width,
+                height,
+                values(bytes, u16::from_ne_bytes),
+            )?),
+            PixelFormat::Gray32F => Buffer::Gray32F(ImageBuffer::from_raw(
+                width,
+                height,
+                values(bytes, f32::from_ne_bytes),
+            )?),
+        }))
+    }
+
+    /// Channel values in native byte order, the layout passed to plugins
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match &self.0 {
+            Buffer::Rgba8(image) => image.to_vec(),
+            Buffer::Rgba16(image) => bytes(image, u16::to_ne_bytes),
+            Buffer::Rgba32F(image) => bytes(image, f32::to_ne_bytes),
+            Buffer::Gray8(image) => image.to_vec(),
+            Buffer::Gray16(image) => bytes(image, u16::to_ne_bytes),
+            Buffer::Gray32F(image) => bytes(image, f32::to_ne_bytes),
+        }
+    }
+
+    /// Format of image data
+    pub fn format(&self) -> PixelFormat {
+        match &self.0 {
+            Buffer::Rgba8(_) => PixelFormat::Rgba8,
+            Buffer::Rgba16(_) => PixelFormat::Rgba16,
+            Buffer::Rgba32F(_) => PixelFormat::Rgba32F,
+            Buffer::Gray8(_) => PixelFormat::Gray8,
+            Buffer::Gray16(_) => PixelFormat::Gray16,
+            Buffer::Gray32F(_) => PixelFormat::Gray32F,
+        }
+    }
+
+    /// Image width and height in pixels
+    pub fn dimensions(&self) -> (u32, u32) {
+        match &self.0 {
+            Buffer::Rgba8(image) => image.dimensions(),
+            Buffer::Rgba16(image) => image.dimensions(),
+            Buffer::Rgba32F(image) => image.dimensions(),
+            Buffer::Gray8(image) => image.dimensions(),
+            Buffer::Gray16(image) => image.dimensions(),
+            Buffer::Gray32F(image) => image.dimensions(),
+        }
+    }
+
+    /// Pointer to image data passed to plugin
+    pub fn as_ptr(&self) -> *const u8 {
+        match &self.0 {
+            Buffer::Rgba8(image) => image.as_ptr(),
+            Buffer::Rgba16(image) => image.as_ptr().cast(),
+            Buffer::Rgba32F(image) => image.as_ptr().cast(),
+            Buffer::Gray8(image) => image.as_ptr(),
+            Buffer::Gray16(image) => image.as_ptr().cast(),
+            Buffer::Gray32F(image) => image.as_ptr().cast(),
+        }
+    }
+
+    /// Pointer to image data passed to plugin for writing
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        match &mut self.0 {
+            Buffer::Rgba8(image) => image.as_mut_ptr(),
+            Buffer::Rgba16(image) => image.as_mut_ptr().cast(),
+            Buffer::Rgba32F(image) => image.as_mut_ptr().cast(),
+            Buffer::Gray8(image) => image.as_mut_ptr(),
+            Buffer::Gray16(image) => image.as_mut_ptr().cast(),
+            Buffer::Gray32F(image) => image.as_mut_ptr().cast(),
+        }
+    }
+
+    /// Convert to image without copying. Floating point grayscale has no own image type,
+    /// so it becomes RGB image
+    pub fn into_image(self) -> DynamicImage {
+        match self.0 {
+            Buffer::Rgba8(image) => DynamicImage::ImageRgba8(image),
+            Buffer::Rgba16(image) => DynamicImage::ImageRgba16(image),
+            Buffer::Rgba32F(image) => DynamicImage::ImageRgba32F(image),
+            Buffer::Gray8(image) => DynamicImage::ImageLuma8(image),
+            Buffer::Gray16(image) => DynamicImage::ImageLuma16(image),
+            Buffer::Gray32F(image) => DynamicImage::from(image),
+        }
+    }
+}
+
+/// Pixel format keeping all data of image: its channel type and color or alpha if image has them
+pub fn closest_format(image: &DynamicImage) -> PixelFormat {
+    match image {
+        DynamicImage::ImageLuma8(_) => PixelFormat::Gray8,
+        DynamicImage::ImageLumaA8(_) | DynamicImage::ImageRgb8(_) | DynamicImage::ImageRgba8(_) => {
+            PixelFormat::Rgba8
+        }
+        DynamicImage::ImageLuma16(_) => PixelFormat::Gray16,
+        DynamicImage::ImageLumaA16(_)
+        | DynamicImage::ImageRgb16(_)
+        | DynamicImage::ImageRgba16(_) => PixelFormat::Rgba16,
+        _ => PixelFormat::Rgba32F,
+    }
+}
+
+/// Format from bit set of `PixelFormat::flag` which loses least of image data,
+/// None if set does not contain known formats
+///
+/// Loss of color is avoided first, then loss of precision, then change of channel type
+/// and finally adding of color channels to grayscale image
+pub fn best_format(image: &DynamicImage, accepted: u64) -> Option<PixelFormat> {
+    let source = closest_format(image);
+    PixelFormat::ALL
+        .into_iter()
+        .filter(|format| accepted & format.flag() != 0)
+        .min_by_key(|format| {
+            (
+                is_color(source) && !is_color(*format),
+                depth(source).saturating_sub(depth(*format)),
+                depth(*format).saturating_sub(depth(source)),
+                is_color(*format) && !is_color(source),
+            )
+        })
+}
+
+fn is_color(format: PixelFormat) -> bool {
+    format.channels() > 1
+}
+
+/// Rank of channel precision: 8-bit, 16-bit, floating point
+fn depth(format: PixelFormat) -> usize {
+    match format {
+        PixelFormat::Rgba8 | PixelFormat::Gray8 => 0,
+        PixelFormat::Rgba16 | PixelFormat::Gray16 => 1,
+        PixelFormat::Rgba32F | PixelFormat::Gray32F => 2,
+    }
+}
+
+fn values<T, const N: usize>(bytes: &[u8], from_bytes: fn([u8; N]) -> T) -> Vec<T> {
+    bytes
+        .as_chunks()
+        .0
+        .iter()
+        .map(|chunk| from_bytes(*chunk))
+        .collect()
+}
+
+fn bytes<T: Copy, const N: usize>(values: &[T], to_bytes: fn(T) -> [u8; N]) -> Vec<u8> {
+    values.iter().flat_map(|value| to_bytes(*value)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{LumaA, Rgb32FImage};
+
+    fn accepted(formats: &[PixelFormat]) -> u64 {
+        formats.iter().fold(0, |set, format| set | format.flag())
+    }
+
+    #[test]
+    fn test_closest_format() {
+        let gray = DynamicImage::ImageLuma8(GrayImage::new(1, 1));
+        assert_eq!(closest_format(&gray), PixelFormat::Gray8);
+        let gray_alpha = DynamicImage::ImageLumaA16(ImageBuffer::from_pixel(1, 1, LumaA([1, 2])));
+        assert_eq!(closest_format(&gray_alpha), PixelFormat::Rgba16);
+        let hdr = DynamicImage::ImageRgb32F(Rgb32FImage::new(1, 1));
+        assert_eq!(closest_format(&hdr), PixelFormat::Rgba32F);
+    }
+
+    #[test]
+    fn test_best_format_keeps_source_format() {
+        let image = DynamicImage::ImageRgba16(Rgba16Image::new(1, 1));
+        let all = accepted(&PixelFormat::ALL);
+        assert_eq!(best_format(&image, all), Some(PixelFormat::Rgba16));
+    }
+
+    #[test]
+    fn test_best_format_prefers_least_loss() {
+        let rgba16 = DynamicImage::ImageRgba16(Rgba16Image::new(1, 1));
+        // Precision is kept by converting to floating point rather than to 8 bits
+        let formats = accepted(&[PixelFormat::Rgba8, PixelFormat::Rgba32F]);
+        assert_eq!(best_format(&rgba16, formats), Some(PixelFormat::Rgba32F));
+        // Color is more important than precision
+        let formats = accepted(&[PixelFormat::Rgba8, PixelFormat::Gray16]);
+        assert_eq!(best_format(&rgba16, formats), Some(PixelFormat::Rgba8));
+
+        let gray8 = DynamicImage::ImageLuma8(GrayImage::new(1, 1));
+        // Channel type is kept when data is not lost
+        let formats = accepted(&[
+            PixelFormat::Rgba32F,
+            PixelFormat::Rgba8,
+            PixelFormat::Gray16,
+        ]);
+        assert_eq!(best_format(&gray8, formats), Some(PixelFormat::Rgba8));
+        let formats = accepted(&[PixelFormat::Rgba32F, PixelFormat::Gray16]);
+        assert_eq!(best_format(&gray8, formats), Some(PixelFormat::Gray16));
+
+        assert_eq!(best_format(&gray8, 1 << 40), None);
+    }
+
+    #[test]
+    fn test_from_image_keeps_values() {
+        let image =
+            Rgba16Image::from_fn(3, 2, |x, y| Rgba([x as u16 * 1000 + 1, y as u16, 7, 65535]));
+        let buffer = PixelBuffer::from_image(
+            DynamicImage::ImageRgba16(image.clone()),
+            PixelFormat::Rgba16,
+        );
+        assert_eq!(buffer.format(), PixelFormat::Rgba16);
+        assert_eq!(buffer.dimensions(), (3, 2));
+        assert_eq!(buffer.into_image(), DynamicImage::ImageRgba16(image));
+    }
+
+    #[test]
+    fn test_bytes_roundtrip() {
+        for format in PixelFormat::ALL {
+            let image = DynamicImage::ImageRgba16(Rgba16Image::from_fn(3, 2, |x, y| {
+                Rgba([x as u16 * 20000, y as u16 * 30000, 1234, 40000])
+            }));
+            let buffer = PixelBuffer::from_image(image, format);
+            let bytes = buffer.to_bytes();
+            assert_eq!(Some(bytes.len()), format.data_size(3, 2));
+            assert_eq!(PixelBuffer::from_bytes(format, 3, 2, &bytes), Some(buffer));
+            assert_eq!(PixelBuffer::from_bytes(format, 2, 2, &bytes), None);
+        }
+    }
+
+    #[test]
+    fn test_new_size_is_too_big() {
+        assert!(matches!(
+            PixelBuffer::new(PixelFormat::Rgba32F, u32::MAX, u32::MAX),
+            Err(AppError::SizeIsTooBig)
+        ));
+        // Size fits into address space, but exceeds allocation limit
+        assert!(matches!(
+            PixelBuffer::new(PixelFormat::Rgba8, 200_000, 200_000),
+            Err(AppError::SizeIsTooBig)
+        ));
+        let buffer = PixelBuffer::new(PixelFormat::Gray16, 2, 3).unwrap();
+        assert_eq!(buffer.to_bytes(), [0; 12]);
+    }
+
+    #[test]
+    fn test_gray32f_into_image() {
+        let image = DynamicImage::ImageRgb32F(Rgb32FImage::from_pixel(1, 1, image::Rgb([0.5; 3])));
+        let buffer = PixelBuffer::from_image(image, PixelFormat::Gray32F);
+        match buffer.into_image() {
+            DynamicImage::ImageRgb32F(image) => {
+                let [r, g, b] = image.get_pixel(0, 0).0;
+                assert!((r - 0.5).abs() < 1e-6 && r == g && g == b);
+            }
+            other => panic!("unexpected image {:?}", other.color()),
+        }
+    }
+}
@@ -1,14 +1,121 @@
 //! Plugin initialization and interface
 use std::{
+    ffi::{CStr, c_void},
     os::raw::{c_char, c_uchar},
+    panic::{self, AssertUnwindSafe},
     path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
 };
 
+use image::DynamicImage;
 use libloading::{Library, Symbol};
+use plugin_abi::{ABI_VERSION, PixelFormat, PluginInfo, ProcessContext, capabilities};
+
+use crate::{
+    error::AppError,
+    pixels::{PixelBuffer, best_format},
+    schema::ParamsSchema,
+};
 
 /// Struct contatining plugin library
 pub struct Plugin {
     plugin: Library,
+    metadata: PluginMetadata,
+    params_schema: Option<ParamsSchema>,
+}
+
+/// Plugin description read from `PluginInfo` exported by plugin
+#[derive(Debug, Clone)]
+pub struct PluginMetadata {
+    /// ABI version plugin was built against
+    pub abi_version: u32,
+
+    /// Plugin name
+    pub name: String,
+
+    /// Plugin version
+    pub version: String,
+
+    /// Short human readable description, empty if plugin does not provide it
+    pub description: String,
+
+    /// Bit set of `plugin_abi::capabilities` flags
+    pub capabilities: u64,
+
+    /// Bit set of `PixelFormat::flag` of formats accepted by plugin
+    pub pixel_formats: u64,
+}
+
+impl PluginMetadata {
+    /// Check if plugin declares given capability
+    pub fn has_capability(&self, capability: u64) -> bool {
+        self.capabilities & capability == capability
+    }
+
+    /// Names of known capabilities declared by plugin
+    pub fn capability_names(&self) -> Vec<&'static str> {
+        [
+            (capabilities::IN_PLACE, "in-place"),
+            (capabilities::TRANSFORM, "transform"),
+            (capabilities::ERROR_MESSAGE, "error-message"),
+            (capabilities::PARAMS_SCHEMA, "params-schema"),
+        ]
+        .into_iter()
+        .filter(|(capability, _)| self.has_capability(*capability))
+        .map(|(_, name)| name)
+        .collect()
+    }
+
+    /// Check if plugin accepts image data in given format
+    pub fn accepts(&self, format: PixelFormat) -> bool {
+        self.pixel_formats & format.flag() != 0
+    }
+
+    /// Names of known pixel formats accepted by plugin
+    pub fn pixel_format_names(&self) -> Vec<&'static str> {
+        PixelFormat::ALL
+            .into_iter()
+            .filter(|format| self.accepts(*format))
+            .map(PixelFormat::name)
+            .collect()
+    }
+}
+
+/// App side state shared with plugin during a single call
+pub struct CallContext<'a> {
+    /// Flag asking plugin to stop processing
+    pub cancel_flag: &'a AtomicBool,
+
+    /// Receives number of done and total work units reported by plugin. May be called from any thread
+    pub progress: Option<&'a (dyn Fn(u64, u64) + Sync)>,
+
+    /// Number of threads plugin is recommended to use, 0 lets plugin decide
+    pub thread_count: u32,
+}
+
+impl CallContext<'_> {
+    /// Build ABI context pointing to this struct. Result must not outlive `self`
+    fn process_context(&self) -> ProcessContext {
+        ProcessContext {
+            cancel_flag: self.cancel_flag,
+            progress: self.progress.map(|_| progress_trampoline as _),
+            progress_user_data: (self as *const CallContext).cast_mut().cast(),
+            thread_count: self.thread_count,
+        }
+    }
+}
+
+/// Progress callback given to plugins, forwards calls to `CallContext::progress`
+///
+/// Panic of callback must not unwind into plugin, so it is caught and plugin is asked to stop instead
+unsafe extern "C" fn progress_trampoline(user_data: *mut c_void, done: u64, total: u64) {
+    // SAFETY: user_data points to `CallContext` which outlives plugin call
+    let ctx = unsafe { &*(user_data as *const CallContext) };
+    if let Some(progress) = ctx.progress
+        && panic::catch_unwind(AssertUnwindSafe(|| progress(done, total))).is_err()
+    {
+        ctx.cancel_flag.store(true, Ordering::Relaxed);
+    }
 }
 
 /// Struct to hold pointer for image process function from plugin
@@ -19,39 +126,175 @@ pub struct PluginInterface<'a> {
     ///
     /// * `width` - image width in pixels
     /// * `height` - image height in pixels
-    /// * `rgba_data` - pointer to image data. Image conversion runs in place so it will contain result data in case of successful conversion
+    /// * `pixel_format` - `PixelFormat` of image data, one of formats accepted by plugin
+    /// * `data` - pointer to image data. Image conversion runs in place so it will contain result data in case of successful conversion
     /// * `params` - pointer to params string
+    /// * `ctx` - pointer to processing context with cancellation flag, may be null
     ///
     /// # Safety
     ///
     /// Pointers are checked for being non-null before usage
     /// `params` should point to a valid UTF-8 string ending with nul-terminator
-    /// `rgba_data` must have at least data_size bytes
+    /// `data` must have at least `width * height` pixels of `pixel_format`
+    /// `ctx` should be null or point to `ProcessContext` valid for the whole call
     ///
     pub process_image_fn: Symbol<
         'a,
         unsafe extern "C" fn(
             width: u32,
             height: u32,
-            rgba_data: *mut c_uchar,
+            pixel_format: u32,
+            data: *mut c_uchar,
+            params: *const c_char,
+            ctx: *const ProcessContext,
+        ) -> i32,
+    >,
+}
+
+/// Struct to hold pointers for functions of plugins able to change image dimensions
+pub struct TransformInterface<'a> {
+    /// Calculates dimensions of output image
+    ///
+    /// # Arguments
+    ///
+    /// * `width` - input image width in pixels
+    /// * `height` - input image height in pixels
+    /// * `params` - pointer to params string
+    /// * `out_width` - pointer to write output image width to
+    /// * `out_height` - pointer to write output image height to
+    ///
+    /// # Safety
+    ///
+    /// `params` should point to a valid UTF-8 string ending with nul-terminator
+    /// `out_width` and `out_height` should point to writable `u32` values
+    pub output_size_fn: Symbol<
+        'a,
+        unsafe extern "C" fn(
+            width: u32,
+            height: u32,
+            params: *const c_char,
+            out_width: *mut u32,
+            out_height: *mut u32,
+        ) -> i32,
+    >,
+
+    /// Image conversion function writing result into separate output buffer
+    ///
+    /// # Arguments
+    ///
+    /// * `width` - input image width in pixels
+    /// * `height` - input image height in pixels
+    /// * `pixel_format` - `PixelFormat` of input and output image data, one of formats accepted by plugin
+    /// * `data` - pointer to input image data
+    /// * `out_width` - output image width returned by `output_size`
+    /// * `out_height` - output image height returned by `output_size`
+    /// * `out_data` - pointer to output image data allocated by host
+    /// * `params` - pointer to params string
+    /// * `ctx` - pointer to processing context with cancellation flag, may be null
+    ///
+    /// # Safety
+    ///
+    /// `params` should point to a valid UTF-8 string ending with nul-terminator
+    /// `data` must have at least `width * height` pixels of `pixel_format`
+    /// `out_data` must have at least `out_width * out_height` pixels of `pixel_format`
+    /// `ctx` should be null or point to `ProcessContext` valid for the whole call
+    pub transform_image_fn: Symbol<
+        'a,
+        unsafe extern "C" fn(
+            width: u32,
+            height: u32,
+            pixel_format: u32,
+            data: *const c_uchar,
+            out_width: u32,
+            out_height: u32,
+            out_data: *mut c_uchar,
             params: *const c_char,
+            ctx: *const ProcessContext,
         ) -> i32,
     >,
 }
 
 impl Plugin {
-    /// Find and load a dynamic library
+    /// Find and load a dynamic library and check its ABI version
     ///
     /// `plugin_file` should point to existing dynamic library
     ///
-    /// Safety: it is expected for plugin to export `process_image` function,
+    /// Safety: it is expected for plugin to export `plugin_info` and `process_image` functions,
     /// not trying to complete any harmful operations and not use any pointers after image conversion is finished
-    pub fn new(plugin_file: PathBuf) -> Result<Self, libloading::Error> {
+    pub fn new(plugin_file: PathBuf) -> Result<Self, AppError> {
+        let plugin = unsafe { Library::new(&plugin_file) }?;
+        let plugin_name = plugin_file.to_string_lossy().to_string();
+
+        let metadata = {
+            let info_fn: Symbol<unsafe extern "C" fn() -> *const PluginInfo> =
+                unsafe { plugin.get("plugin_info") }
+                    .map_err(|_| AppError::PluginInfoMissing(plugin_name.clone()))?;
+
+            // SAFETY: `plugin_info` returns pointer to static `PluginInfo` or null
+            let info = unsafe { info_fn() };
+            if info.is_null() {
+                return Err(AppError::PluginInfoMissing(plugin_name));
+            }
+
+            // SAFETY: `abi_version` is the first field in every ABI version
+            let abi_version = unsafe { (*info).abi_version };
+            if abi_version != ABI_VERSION {
+                return Err(AppError::IncompatiblePlugin {
+                    plugin: plugin_name,
+                    expected: ABI_VERSION,
+                    found: abi_version,
+                });
+            }
+
+            // SAFETY: ABI version matches, so pointer refers to `PluginInfo` of the same layout
+            let info = unsafe { &*info };
+            PluginMetadata {
+                abi_version,
+                name: unsafe { c_string_or_empty(info.name) },
+                version: unsafe { c_string_or_empty(info.version) },
+                description: unsafe { c_string_or_empty(info.description) },
+                capabilities: info.capabilities,
+                pixel_formats: info.pixel_formats,
+            }
+        };
+
+        let params_schema = match metadata.has_capability(capabilities::PARAMS_SCHEMA) {
+            true => Some(read_params_schema(&plugin, &metadata.name)?),
+            false => None,
+        };
+
         Ok(Plugin {
-            plugin: unsafe { Library::new(plugin_file) }?,
+            plugin,
+            metadata,
+            params_schema,
         })
     }
 
+    /// Plugin description
+    pub fn metadata(&self) -> &PluginMetadata {
+        &self.metadata
+    }
+
+    /// JSON Schema of plugin params, None if plugin does not export it
+    pub fn params_schema(&self) -> Option<&ParamsSchema> {
+        self.params_schema.as_ref()
+    }
+
+    /// Check params against schema exported by plugin. Params of plugins without schema are not checked
+    pub fn validate_params(&self, params: &CStr) -> Result<(), AppError> {
+        let Some(schema) = &self.params_schema else {
+            return Ok(());
+        };
+
+        schema
+            .validate(&params.to_string_lossy())
+            .map_err(|e| AppError::ParamsValidation {
+                plugin: self.metadata.name.clone(),
+                path: e.path,
+                message: e.message,
+            })
+    }
+
     /// Gets a pointer to PluginInterface struct
     ///
     /// Safety: it is expected for plugin to export `process_image` function,
@@ -61,4 +304,251 @@ impl Plugin {
             process_image_fn: unsafe { self.plugin.get("process_image") }?,
         })
     }
+
+    /// Gets pointers to functions of plugins with `TRANSFORM` capability
+    ///
+    /// Safety: it is expected for plugin to export `output_size` and `transform_image` functions,
+    /// not trying to complete any harmful operations and not use any pointers after image conversion is finished
+    pub fn transform_interface(&self) -> Result<TransformInterface<'_>, libloading::Error> {
+        Ok(TransformInterface {
+            output_size_fn: unsafe { self.plugin.get("output_size") }?,
+            transform_image_fn: unsafe { self.plugin.get("transform_image") }?,
+        })
+    }
+
+    /// Apply plugin to image. Plugins with `TRANSFORM` capability may return image of other size,
+    /// other plugins convert image in-place
+    ///
+    /// Image is converted to pixel format accepted by plugin which loses least of its data,
+    /// so result may have other type than input image.
+    /// Plugin stops processing with `AppError::PluginCancelled` once `ctx.cancel_flag` is set
+    pub fn apply(
+        &self,
+        image: DynamicImage,
+        params: &CStr,
+        ctx: &CallContext,
+    ) -> Result<DynamicImage, AppError> {
+        let format = best_format(&image, self.metadata.pixel_formats).ok_or_else(|| {
+            AppError::PluginCapabilityMissing {
+                plugin: self.metadata.name.clone(),
+                capability: "any known pixel format",
+            }
+        })?;
+        let mut pixels = PixelBuffer::from_image(image, format);
+
+        if self.metadata.has_capability(capabilities::TRANSFORM) {
+            return Ok(self.transform(&pixels, params, ctx)?.into_image());
+        }
+
+        self.process(&mut pixels, params, ctx)?;
+        Ok(pixels.into_image())
+    }
+
+    /// Run conversion producing new image which dimensions are reported by plugin.
+    /// Result has the same pixel format as input
+    pub fn transform(
+        &self,
+        pixels: &PixelBuffer,
+        params: &CStr,
+        ctx: &CallContext,
+    ) -> Result<PixelBuffer, AppError> {
+        if !self.metadata.has_capability(capabilities::TRANSFORM) {
+            return Err(AppError::PluginCapabilityMissing {
+                plugin: self.metadata.name.clone(),
+                capability: "image transformation",
+            });
+        }
+        self.check_pixel_format(pixels.format())?;
+
+        let interface = self.transform_interface()?;
+        let (width, height) = pixels.dimensions();
+
+        let mut out_width = 0u32;
+        let mut out_height = 0u32;
+        // SAFETY: params is a nul-terminated string, output pointers refer to local variables
+        let error_code = unsafe {
+            (interface.output_size_fn)(
+                width,
+                height,
+                params.as_ptr(),
+                &mut out_width,
+                &mut out_height,
+            )
+        };
+        if let Some(error) = self.error_from_code(error_code) {
+            return Err(error);
+        }
+
+        if out_width == 0 || out_height == 0 {
+            return Err(AppError::InvalidOutputSize);
+        }
+        let mut out_pixels = PixelBuffer::new(pixels.format(), out_width, out_height)?;
+        let ctx = ctx.process_context();
+
+        // SAFETY: both buffers match their dimensions and format, params is a nul-terminated string
+        // and ctx lives until the call is finished
+        let error_code = unsafe {
+            (interface.transform_image_fn)(
+                width,
+                height,
+                pixels.format() as u32,
+                pixels.as_ptr(),
+                out_width,
+                out_height,
+                out_pixels.as_mut_ptr(),
+                params.as_ptr(),
+                &ctx,
+            )
+        };
+        match self.error_from_code(error_code) {
+            Some(error) => Err(error),
+            None => Ok(out_pixels),
+        }
+    }
+
+    /// Run in-place conversion of image data with given params
+    pub fn process(
+        &self,
+        pixels: &mut PixelBuffer,
+        params: &CStr,
+        ctx: &CallContext,
+    ) -> Result<(), AppError> {
+        if !self.metadata.has_capability(capabilities::IN_PLACE) {
+            return Err(AppError::PluginCapabilityMissing {
+                plugin: self.metadata.name.clone(),
+                capability: "in-place processing",
+            });
+        }
+        self.check_pixel_format(pixels.format())?;
+
+        let interface = self.interface()?;
+        let (width, height) = pixels.dimensions();
+        let ctx = ctx.process_context();
+
+        // SAFETY: buffer matches its dimensions and format, params is a nul-terminated string
+        // and ctx lives until the call is finished
+        let error_code = unsafe {
+            (interface.process_image_fn)(
+                width,
+                height,
+                pixels.format() as u32,
+                pixels.as_mut_ptr(),
+                params.as_ptr(),
+                &ctx,
+            )
+        };
+
+        match self.error_from_code(error_code) {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    fn check_pixel_format(&self, format: PixelFormat) -> Result<(), AppError> {
+        match self.metadata.accepts(format) {
+            true => Ok(()),
+            false => Err(AppError::UnsupportedPixelFormat {
+                plugin: self.metadata.name.clone(),
+                format: format.name(),
+            }),
+        }
+    }
+
+    /// Description of last error happened in plugin on current thread,
+    /// None if plugin does not support error messages or did not provide one
+    pub fn last_error_message(&self) -> Option<String> {
+        if !self.metadata.has_capability(capabilities::ERROR_MESSAGE) {
+            return None;
+        }
+
+        let message_fn: Symbol<unsafe extern "C" fn() -> *const c_char> =
+            unsafe { self.plugin.get("last_error_message") }.ok()?;
+
+        // SAFETY: plugin returns null or pointer to nul-terminated string valid until next call
+        let message = unsafe { message_fn() };
+        (!message.is_null()).then(|| unsafe { c_string_or_empty(message) })
+    }
+
+    /// Convert plugin return code to AppError with attached plugin error message
+    /// or None if plugin finished without error
+    fn error_from_code(&self, code: i32) -> Option<AppError> {
+        let error = AppError::from_plugin_error_code(code)?;
+
+        match self.last_error_message() {
+            Some(message) => Some(AppError::PluginErrorMessage {
+                error: Box::new(error),
+                message,
+            }),
+            None => Some(error),
+        }
+    }
+}
+
+/// Read and parse schema returned by `params_schema` function of plugin with `PARAMS_SCHEMA` capability
+fn read_params_schema(plugin: &Library, plugin_name: &str) -> Result<ParamsSchema, AppError> {
+    let schema_fn: Symbol<unsafe extern "C" fn() -> *const c_char> =
+        unsafe { plugin.get("params_schema") }?;
+
+    // SAFETY: plugin returns null or pointer to static nul-terminated string
+    let schema = unsafe { c_string_or_empty(schema_fn()) };
+    ParamsSchema::parse(&schema).map_err(|e| AppError::InvalidParamsSchema {
+        plugin: plugin_name.to_string(),
+        message: e.to_string(),
+    })
+}
+
+/// Copy nul-terminated string from plugin, null pointer is read as empty string
+///
+/// # Safety
+///
+/// `ptr` should be null or point to a nul-terminated string
+unsafe fn c_string_or_empty(ptr: *const c_char) -> String {
+    if ptr.is_null() {
+        return String::new();
+    }
+    unsafe { CStr::from_ptr(ptr) }.to_string_lossy().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_panic_cancels_processing() {
+        let cancel_flag = AtomicBool::new(false);
+        let reports = std::sync::Mutex::new(Vec::new());
+        let progress = |done: u64, total: u64| {
+            reports.lock().unwrap().push((done, total));
+            assert!(done < total, "progress is finished");
+        };
+        let ctx = CallContext {
+            cancel_flag: &cancel_flag,
+            progress: Some(&progress),
+            thread_count: 0,
+        };
+        let process_ctx = ctx.process_context();
+
+        process_ctx.report_progress(1, 2);
+        assert!(!process_ctx.is_cancelled());
+        process_ctx.report_progress(2, 2);
+        assert!(process_ctx.is_cancelled());
+        assert_eq!(*reports.lock().unwrap(), [(1, 2), (2, 2)]);
+    }
+
+    #[test]
+    fn test_huge_output_size() {
+        // Plugins are built to target directory by `cargo build --workspace`
+        let plugin_file = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/../target/debug"))
+            .join(libloading::library_filename("resize"));
+        let plugin = Plugin::new(plugin_file).expect("resize plugin is not built");
+        let cancel_flag = AtomicBool::new(false);
+        let ctx = CallContext {
+            cancel_flag: &cancel_flag,
+            progress: None,
+            thread_count: 0,
+        };
+        let image = DynamicImage::ImageRgba8(image::RgbaImage::new(2, 2));
+        let result = plugin.apply(image, c"{\"width\":200000,\"height\":200000}", &ctx);
+        assert!(matches!(result, Err(AppError::SizeIsTooBig)));
+    }
 }
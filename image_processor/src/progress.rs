@@ -0,0 +1,59 @@
+//! Progress bar for long plugin calls
+use std::{
+    io::{self, IsTerminal, Write},
+    sync::Mutex,
+};
+
+const BAR_WIDTH: usize = 30;
+
+/// Progress bar printed to stderr. Shown only when stderr is a terminal
+pub struct ProgressBar {
+    last_drawn: Mutex<Option<(usize, u64)>>,
+}
+
+impl ProgressBar {
+    /// Create progress bar if stderr is a terminal
+    pub fn for_terminal() -> Option<Self> {
+        io::stderr().is_terminal().then(|| ProgressBar {
+            last_drawn: Mutex::new(None),
+        })
+    }
+
+    /// Draw progress of pipeline step. Redraws only when percentage changes
+    pub fn update(&self, step: usize, steps: usize, plugin: &str, done: u64, total: u64) {
+        let percent = match total {
+            0 => 100,
+            total => done.min(total) * 100 / total,
+        };
+
+        let Ok(mut last_drawn) = self.last_drawn.lock() else {
+            return;
+        };
+        if *last_drawn == Some((step, percent)) {
+            return;
+        }
+        *last_drawn = Some((step, percent));
+
+        let filled = percent as usize * BAR_WIDTH / 100;
+        let mut stderr = io::stderr().lock();
+        let _ = write!(
+            stderr,
+            "\r[{}/{}] {plugin} [{}{}] {percent:>3}%",
+            step,
+            steps,
+            "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled)
+        );
+        let _ = stderr.flush();
+    }
+
+    /// Move to the next line if progress bar was drawn
+    pub fn finish(&self) {
+        let Ok(mut last_drawn) = self.last_drawn.lock() else {
+            return;
+        };
+        if last_drawn.take().is_some() {
+            eprintln!();
+        }
+    }
+}
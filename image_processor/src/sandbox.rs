@@ -0,0 +1,423 @@
+//! Running plugins in a separate worker process
+//!
+//! Host starts current executable with `WORKER_ARG` and path to plugin library,
+//! writes request with image and params to worker stdin and reads result from worker stdout.
+//! Worker redirects its own stdout to stderr before loading plugin, so output printed by plugin
+//! does not mix with response. Crash of plugin kills only the worker process and is reported as `AppError`
+use std::{
+    error::Error,
+    ffi::{CStr, CString},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::atomic::AtomicBool,
+    thread,
+    time::{Duration, Instant},
+};
+
+use image::DynamicImage;
+use plugin_abi::PixelFormat;
+
+use crate::{
+    error::AppError,
+    pixels::{PixelBuffer, closest_format},
+    plugin::{CallContext, Plugin},
+};
+
+/// First CLI argument switching app into plugin worker mode
+pub const WORKER_ARG: &str = "--plugin-worker";
+
+const RESPONSE_OK: u8 = 0;
+const RESPONSE_PLUGIN_ERROR: u8 = 1;
+const RESPONSE_WORKER_ERROR: u8 = 2;
+
+/// Plugin which is loaded and called in a separate worker process for every image
+pub struct IsolatedPlugin {
+    plugin_file: PathBuf,
+    timeout: Option<Duration>,
+    thread_count: u32,
+}
+
+impl IsolatedPlugin {
+    /// Create isolated plugin. Library is not loaded into the host process
+    ///
+    /// Worker process is killed if plugin does not finish in `timeout`.
+    /// `thread_count` is passed to plugin as recommended number of threads
+    pub fn new(plugin_file: PathBuf, timeout: Option<Duration>, thread_count: u32) -> Self {
+        IsolatedPlugin {
+            plugin_file,
+            timeout,
+            thread_count,
+        }
+    }
+
+    /// Apply plugin to image in worker process
+    pub fn apply(&self, image: DynamicImage, params: &CStr) -> Result<DynamicImage, AppError> {
+        let executable = std::env::current_exe().map_err(AppError::WorkerSpawn)?;
+        let mut child = Command::new(executable)
+            .arg(WORKER_ARG)
+            .arg(&self.plugin_file)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(AppError::WorkerSpawn)?;
+
+        let request = encode_request(image, params, self.thread_count);
+
+        let (mut stdin, mut stdout) = match (child.stdin.take(), child.stdout.take()) {
+            (Some(stdin), Some(stdout)) => (stdin, stdout),
+            _ => {
+                let _ = child.kill();
+                return Err(AppError::WorkerProtocol("worker pipes are not available"));
+            }
+        };
+
+        let (status, response) = thread::scope(|scope| {
+            // Worker may exit without reading the whole request, so write errors are ignored
+            // and the reason is detected by exit status
+            scope.spawn(move || stdin.write_all(&request));
+            let reader = scope.spawn(move || {
+                let mut response = Vec::new();
+                stdout.read_to_end(&mut response).map(|_| response)
+            });
+
+            let status = wait_with_timeout(&mut child, self.timeout);
+            let response = reader.join().unwrap_or_else(|_| Ok(Vec::new()));
+            (status, response)
+        });
+
+        let status = status?;
+        if !status.success() {
+            return Err(match exit_signal(&status) {
+                Some(signal) => AppError::WorkerCrashed(signal),
+                None => AppError::WorkerExited(status.code()),
+            });
+        }
+
+        let response = response.map_err(|_| AppError::WorkerProtocol("unable to read response"))?;
+        decode_response(&response)
+    }
+}
+
+/// Entry point of worker process: read request from stdin, apply plugin and write response to stdout
+pub fn run_worker(plugin_file: &Path) -> Result<(), AppError> {
+    let mut response_writer =
+        response_writer().map_err(|_| AppError::WorkerProtocol("unable to redirect stdout"))?;
+
+    let mut request = Vec::new();
+    io::stdin()
+        .read_to_end(&mut request)
+        .map_err(|_| AppError::WorkerProtocol("unable to read request"))?;
+    let (image, params, thread_count) = decode_request(&request)?;
+
+    // Host kills worker on timeout, so cancellation flag is never set inside worker
+    let cancel_flag = AtomicBool::new(false);
+    let ctx = CallContext {
+        cancel_flag: &cancel_flag,
+        progress: None,
+        thread_count,
+    };
+    let result = Plugin::new(plugin_file.to_path_buf()).and_then(|plugin| {
+        plugin.validate_params(&params)?;
+        plugin.apply(image, &params, &ctx)
+    });
+
+    let response = encode_response(result);
+    response_writer
+        .write_all(&response)
+        .and_then(|_| response_writer.flush())
+        .map_err(|_| AppError::WorkerProtocol("unable to write response"))
+}
+
+/// Duplicate of stdout used only for response, while stdout itself is redirected to stderr
+#[cfg(unix)]
+fn response_writer() -> io::Result<impl Write> {
+    use std::os::fd::AsFd;
+
+    io::stdout().flush()?;
+    let response = io::stdout().as_fd().try_clone_to_owned()?;
+    // SAFETY: dup2 only replaces descriptor 1 with a copy of descriptor 2, both are owned by process
+    if unsafe { libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(std::fs::File::from(response))
+}
+
+/// Stdout can not be redirected without unix descriptors, so response shares it with plugin output
+#[cfg(not(unix))]
+fn response_writer() -> io::Result<impl Write> {
+    Ok(io::stdout())
+}
+
+fn wait_with_timeout(child: &mut Child, timeout: Option<Duration>) -> Result<ExitStatus, AppError> {
+    let kill = |child: &mut Child| {
+        let _ = child.kill();
+        let _ = child.wait();
+    };
+
+    let Some(timeout) = timeout else {
+        return child.wait().map_err(AppError::WorkerSpawn);
+    };
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => return Ok(status),
+            Ok(None) if Instant::now() >= deadline => {
+                kill(child);
+                return Err(AppError::Timeout(timeout));
+            }
+            Ok(None) => thread::sleep(Duration::from_millis(5)),
+            Err(e) => {
+                kill(child);
+                return Err(AppError::WorkerSpawn(e));
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn exit_signal(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn exit_signal(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+/// Request layout: thread count, params length, params, image. Numbers are little-endian `u32`
+///
+/// Image is sent in pixel format keeping all its data, worker converts it to format accepted by plugin
+fn encode_request(image: DynamicImage, params: &CStr, thread_count: u32) -> Vec<u8> {
+    let params = params.to_bytes();
+    let mut request = Vec::with_capacity(8 + params.len());
+    request.extend_from_slice(&thread_count.to_le_bytes());
+    request.extend_from_slice(&(params.len() as u32).to_le_bytes());
+    request.extend_from_slice(params);
+    put_image(&mut request, image);
+    request
+}
+
+fn decode_request(request: &[u8]) -> Result<(DynamicImage, CString, u32), AppError> {
+    let mut reader = Reader(request);
+    let thread_count = reader.u32()?;
+    let params_len = reader.u32()? as usize;
+    let params = CString::new(reader.bytes(params_len)?)
+        .map_err(|_| AppError::WorkerProtocol("params contain nul byte"))?;
+    let image = reader.image()?;
+    Ok((image, params, thread_count))
+}
+
+/// Image layout: pixel format, width, height, channel values in native byte order as passed to plugins
+fn put_image(message: &mut Vec<u8>, image: DynamicImage) {
+    let format = closest_format(&image);
+    let pixels = PixelBuffer::from_image(image, format);
+    let (width, height) = pixels.dimensions();
+    message.extend_from_slice(&(format as u32).to_le_bytes());
+    message.extend_from_slice(&width.to_le_bytes());
+    message.extend_from_slice(&height.to_le_bytes());
+    message.extend_from_slice(&pixels.to_bytes());
+}
+
+/// Response layout: status byte followed by
+/// * `RESPONSE_OK` - image
+/// * `RESPONSE_PLUGIN_ERROR` - plugin error code, message length, message (empty if plugin provided no message)
+/// * `RESPONSE_WORKER_ERROR` - message length, message
+fn encode_response(result: Result<DynamicImage, AppError>) -> Vec<u8> {
+    let mut response = Vec::new();
+    let put_message = |response: &mut Vec<u8>, message: &str| {
+        response.extend_from_slice(&(message.len() as u32).to_le_bytes());
+        response.extend_from_slice(message.as_bytes());
+    };
+
+    match result {
+        Ok(image) => {
+            response.push(RESPONSE_OK);
+            put_image(&mut response, image);
+        }
+        Err(error) => {
+            let (error, message) = match &error {
+                AppError::PluginErrorMessage { error, message } => {
+                    (error.as_ref(), message.as_str())
+                }
+                error => (error, ""),
+            };
+
+            match error.plugin_error_code() {
+                Some(code) => {
+                    response.push(RESPONSE_PLUGIN_ERROR);
+                    response.extend_from_slice(&code.to_le_bytes());
+                    put_message(&mut response, message);
+                }
+                None => {
+                    response.push(RESPONSE_WORKER_ERROR);
+                    put_message(&mut response, &error_chain(error));
+                }
+            }
+        }
+    }
+
+    response
+}
+
+fn decode_response(response: &[u8]) -> Result<DynamicImage, AppError> {
+    let mut reader = Reader(response);
+
+    match reader.u8()? {
+        RESPONSE_OK => reader.image(),
+        RESPONSE_PLUGIN_ERROR => {
+            let code = reader.u32()? as i32;
+            let message = reader.string()?;
+            let error = AppError::from_plugin_error_code(code)
+                .ok_or(AppError::WorkerProtocol("error response with success code"))?;
+
+            Err(match message.is_empty() {
+                true => error,
+                false => AppError::PluginErrorMessage {
+                    error: Box::new(error),
+                    message,
+                },
+            })
+        }
+        RESPONSE_WORKER_ERROR => Err(AppError::WorkerError(reader.string()?)),
+        _ => Err(AppError::WorkerProtocol("unknown response status")),
+    }
+}
+
+/// Error message with all its sources separated by colon
+fn error_chain(error: &AppError) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        message.push_str(&format!(": {error}"));
+        source = error.source();
+    }
+    message
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], AppError> {
+        if self.0.len() < len {
+            return Err(AppError::WorkerProtocol("message is truncated"));
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, AppError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, AppError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn string(&mut self) -> Result<String, AppError> {
+        let len = self.u32()? as usize;
+        Ok(String::from_utf8_lossy(self.bytes(len)?).to_string())
+    }
+
+    /// Read image written by `put_image`, it takes the rest of message
+    fn image(&mut self) -> Result<DynamicImage, AppError> {
+        let format = PixelFormat::from_u32(self.u32()?)
+            .ok_or(AppError::WorkerProtocol("unknown pixel format"))?;
+        let width = self.u32()?;
+        let height = self.u32()?;
+        let pixels = PixelBuffer::from_bytes(format, width, height, self.0)
+            .ok_or(AppError::WorkerProtocol("image data size mismatch"))?;
+        self.0 = &[];
+        Ok(pixels.into_image())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_image() -> DynamicImage {
+        DynamicImage::ImageRgba8(image::RgbaImage::from_fn(3, 2, |x, y| {
+            image::Rgba([x as u8, y as u8, 0, 255])
+        }))
+    }
+
+    #[test]
+    fn test_request_roundtrip() {
+        let request = encode_request(test_image(), c"{\"a\": 1}", 3);
+        let (image, params, thread_count) = decode_request(&request).unwrap();
+        assert_eq!(image, test_image());
+        assert_eq!(params.as_c_str(), c"{\"a\": 1}");
+        assert_eq!(thread_count, 3);
+    }
+
+    #[test]
+    fn test_truncated_request() {
+        let request = encode_request(test_image(), c"{}", 0);
+        let result = decode_request(&request[..request.len() - 1]);
+        assert!(matches!(result, Err(AppError::WorkerProtocol(_))));
+    }
+
+    #[test]
+    fn test_ok_response_roundtrip() {
+        let response = encode_response(Ok(test_image()));
+        assert_eq!(decode_response(&response).unwrap(), test_image());
+    }
+
+    #[test]
+    fn test_high_precision_image_roundtrip() {
+        let image = DynamicImage::ImageLuma16(image::ImageBuffer::from_fn(3, 2, |x, y| {
+            image::Luma([x as u16 * 10000 + y as u16])
+        }));
+        let response = encode_response(Ok(image.clone()));
+        assert_eq!(decode_response(&response).unwrap(), image);
+
+        let image = DynamicImage::ImageRgba32F(image::Rgba32FImage::from_pixel(
+            2,
+            2,
+            image::Rgba([0.25, 1.5, 0.0, 1.0]),
+        ));
+        let request = encode_request(image.clone(), c"{}", 0);
+        assert_eq!(decode_request(&request).unwrap().0, image);
+    }
+
+    #[test]
+    fn test_plugin_error_response_roundtrip() {
+        let response = encode_response(Err(AppError::PluginErrorMessage {
+            error: Box::new(AppError::PluginInvalidParams),
+            message: "missing field".to_string(),
+        }));
+        match decode_response(&response) {
+            Err(AppError::PluginErrorMessage { error, message }) => {
+                assert!(matches!(*error, AppError::PluginInvalidParams));
+                assert_eq!(message, "missing field");
+            }
+            other => panic!("unexpected result {other:?}"),
+        }
+
+        let response = encode_response(Err(AppError::PluginPanic));
+        assert!(matches!(
+            decode_response(&response),
+            Err(AppError::PluginPanic)
+        ));
+    }
+
+    #[test]
+    fn test_worker_error_response_roundtrip() {
+        let response = encode_response(Err(AppError::PluginInfoMissing("libz.so".to_string())));
+        match decode_response(&response) {
+            Err(AppError::WorkerError(message)) => {
+                assert_eq!(
+                    message,
+                    AppError::PluginInfoMissing("libz.so".to_string()).to_string()
+                )
+            }
+            other => panic!("unexpected result {other:?}"),
+        }
+    }
+}
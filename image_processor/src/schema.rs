@@ -0,0 +1,335 @@
+//! Validation of plugin params against JSON Schema exported by plugin
+//!
+//! Only the subset of JSON Schema used by plugins is supported: `type`, `enum`,
+//! `minimum`, `maximum`, `exclusiveMinimum`, `exclusiveMaximum`, `properties`, `required`,
+//! `additionalProperties`, `items`, `minItems` and `maxItems`. Other keywords are ignored
+use serde_json::{Map, Value};
+
+/// JSON Schema of plugin params
+#[derive(Debug, Clone)]
+pub struct ParamsSchema {
+    schema: Value,
+}
+
+/// Params mismatch found by `ParamsSchema::validate`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaError {
+    /// Path to invalid value, like `$.crop.width`
+    pub path: String,
+
+    /// Description of mismatch
+    pub message: String,
+}
+
+impl ParamsSchema {
+    /// Parse schema JSON exported by plugin
+    pub fn parse(schema: &str) -> Result<Self, serde_json::Error> {
+        Ok(ParamsSchema {
+            schema: serde_json::from_str(schema)?,
+        })
+    }
+
+    /// Schema as JSON value
+    pub fn as_value(&self) -> &Value {
+        &self.schema
+    }
+
+    /// Check params JSON against schema. Returns the first mismatch found
+    pub fn validate(&self, params: &str) -> Result<(), SchemaError> {
+        let params: Value = serde_json::from_str(params).map_err(|e| SchemaError {
+            path: "$".to_string(),
+            message: format!("params are not a valid JSON: {e}"),
+        })?;
+        check(&self.schema, &params, "$")
+    }
+}
+
+fn check(schema: &Value, value: &Value, path: &str) -> Result<(), SchemaError> {
+    let error = |message: String| SchemaError {
+        path: path.to_string(),
+        message,
+    };
+
+    let schema = match schema {
+        Value::Object(schema) => schema,
+        Value::Bool(false) => return Err(error("value is not allowed".to_string())),
+        _ => return Ok(()),
+    };
+
+    if let Some(types) = schema.get("type") {
+        let types: Vec<&str> = match types {
+            Value::String(name) => vec![name.as_str()],
+            Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !types.is_empty() && !types.iter().any(|name| has_type(value, name)) {
+            return Err(error(format!(
+                "expected {}, found {}",
+                types.join(" or "),
+                type_name(value)
+            )));
+        }
+    }
+
+    if let Some(Value::Array(variants)) = schema.get("enum")
+        && !variants.contains(value)
+    {
+        let variants: Vec<String> = variants.iter().map(Value::to_string).collect();
+        return Err(error(format!(
+            "expected one of {}, found {value}",
+            variants.join(", ")
+        )));
+    }
+
+    if let Some(number) = value.as_f64() {
+        for (keyword, relation) in [
+            ("minimum", ">="),
+            ("maximum", "<="),
+            ("exclusiveMinimum", ">"),
+            ("exclusiveMaximum", "<"),
+        ] {
+            let Some(bound) = schema.get(keyword).and_then(Value::as_f64) else {
+                continue;
+            };
+            let holds = match relation {
+                ">=" => number >= bound,
+                "<=" => number <= bound,
+                ">" => number > bound,
+                _ => number < bound,
+            };
+            if !holds {
+                return Err(error(format!(
+                    "expected value {relation} {}, found {value}",
+                    schema[keyword]
+                )));
+            }
+        }
+    }
+
+    match value {
+        Value::Object(object) => check_object(schema, object, path),
+        Value::Array(items) => check_array(schema, items, path),
+        _ => Ok(()),
+    }
+}
+
+fn check_object(
+    schema: &Map<String, Value>,
+    object: &Map<String, Value>,
+    path: &str,
+) -> Result<(), SchemaError> {
+    if let Some(Value::Array(required)) = schema.get("required") {
+        for name in required.iter().filter_map(Value::as_str) {
+            if !object.contains_key(name) {
+                return Err(SchemaError {
+                    path: format!("{path}.{name}"),
+                    message: "missing required property".to_string(),
+                });
+            }
+        }
+    }
+
+    let properties = schema.get("properties").and_then(Value::as_object);
+    let additional = schema.get("additionalProperties");
+
+    for (name, value) in object {
+        let property_path = format!("{path}.{name}");
+        match (properties.and_then(|p| p.get(name)), additional) {
+            (Some(property), _) => check(property, value, &property_path)?,
+            (None, Some(Value::Bool(false))) => {
+                let known: Vec<&str> = properties
+                    .map(|p| p.keys().map(String::as_str).collect())
+                    .unwrap_or_default();
+                return Err(SchemaError {
+                    path: property_path,
+                    message: format!("unknown property, expected one of: {}", known.join(", ")),
+                });
+            }
+            (None, Some(additional)) => check(additional, value, &property_path)?,
+            (None, None) => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn check_array(
+    schema: &Map<String, Value>,
+    items: &[Value],
+    path: &str,
+) -> Result<(), SchemaError> {
+    let len = items.len() as u64;
+    if let Some(min) = schema.get("minItems").and_then(Value::as_u64)
+        && len < min
+    {
+        return Err(SchemaError {
+            path: path.to_string(),
+            message: format!("expected at least {min} items, found {len}"),
+        });
+    }
+    if let Some(max) = schema.get("maxItems").and_then(Value::as_u64)
+        && len > max
+    {
+        return Err(SchemaError {
+            path: path.to_string(),
+            message: format!("expected at most {max} items, found {len}"),
+        });
+    }
+
+    if let Some(item_schema) = schema.get("items") {
+        for (idx, item) in items.iter().enumerate() {
+            check(item_schema, item, &format!("{path}[{idx}]"))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn has_type(value: &Value, name: &str) -> bool {
+    match name {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "string" => value.is_string(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        "number" => value.is_number(),
+        // Plugins parse integers with serde, which rejects numbers written with fraction like `3.0`
+        "integer" => value.is_i64() || value.is_u64(),
+        _ => false,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEMA: &str = r#"{
+        "type": "object",
+        "properties": {
+            "radius": { "type": "integer", "minimum": 0 },
+            "mode": { "type": "string", "enum": ["box", "gauss"] },
+            "crop": {
+                "type": ["object", "null"],
+                "properties": { "width": { "type": "integer", "exclusiveMinimum": 0 } },
+                "required": ["width"]
+            },
+            "weights": { "type": "array", "items": { "type": "number" }, "maxItems": 2 }
+        },
+        "required": ["radius"],
+        "additionalProperties": false
+    }"#;
+
+    fn validate(params: &str) -> Result<(), SchemaError> {
+        ParamsSchema::parse(SCHEMA).unwrap().validate(params)
+    }
+
+    fn error(path: &str, message: &str) -> Result<(), SchemaError> {
+        Err(SchemaError {
+            path: path.to_string(),
+            message: message.to_string(),
+        })
+    }
+
+    #[test]
+    fn test_valid_params() {
+        assert_eq!(validate(r#"{ "radius": 3 }"#), Ok(()));
+        assert_eq!(
+            validate(r#"{ "radius": 3, "mode": "box", "crop": null, "weights": [0.5, 1] }"#),
+            Ok(())
+        );
+        assert_eq!(
+            validate(r#"{ "radius": 3, "crop": { "width": 1 } }"#),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_type_mismatch() {
+        assert_eq!(
+            validate(r#"{ "radius": "3" }"#),
+            error("$.radius", "expected integer, found string")
+        );
+        assert_eq!(
+            validate(r#"{ "radius": 1.5 }"#),
+            error("$.radius", "expected integer, found number")
+        );
+        assert_eq!(
+            validate(r#"{ "radius": 3.0 }"#),
+            error("$.radius", "expected integer, found number")
+        );
+        assert_eq!(
+            validate(r#"{ "radius": 1, "crop": 5 }"#),
+            error("$.crop", "expected object or null, found integer")
+        );
+        assert_eq!(validate("[]"), error("$", "expected object, found array"));
+    }
+
+    #[test]
+    fn test_missing_and_unknown_properties() {
+        assert_eq!(
+            validate("{}"),
+            error("$.radius", "missing required property")
+        );
+        assert_eq!(
+            validate(r#"{ "radius": 1, "crop": {} }"#),
+            error("$.crop.width", "missing required property")
+        );
+        assert_eq!(
+            validate(r#"{ "radius": 1, "radious": 2 }"#),
+            error(
+                "$.radious",
+                "unknown property, expected one of: crop, mode, radius, weights"
+            )
+        );
+    }
+
+    #[test]
+    fn test_enum_and_bounds() {
+        assert_eq!(
+            validate(r#"{ "radius": 1, "mode": "motion" }"#),
+            error(
+                "$.mode",
+                r#"expected one of "box", "gauss", found "motion""#
+            )
+        );
+        assert_eq!(
+            validate(r#"{ "radius": -1 }"#),
+            error("$.radius", "expected value >= 0, found -1")
+        );
+        assert_eq!(
+            validate(r#"{ "radius": 1, "crop": { "width": 0 } }"#),
+            error("$.crop.width", "expected value > 0, found 0")
+        );
+    }
+
+    #[test]
+    fn test_array_items() {
+        assert_eq!(
+            validate(r#"{ "radius": 1, "weights": [1, "a"] }"#),
+            error("$.weights[1]", "expected number, found string")
+        );
+        assert_eq!(
+            validate(r#"{ "radius": 1, "weights": [1, 2, 3] }"#),
+            error("$.weights", "expected at most 2 items, found 3")
+        );
+    }
+
+    #[test]
+    fn test_invalid_json() {
+        let result = validate("{ radius: 1 }");
+        assert_eq!(result.as_ref().map_err(|e| e.path.as_str()), Err("$"));
+        assert!(result.unwrap_err().message.contains("line 1 column 3"));
+    }
+}
@@ -1,4 +1,4 @@
-//! Image processor plugin for mirroring image
+//! Image processor plugin for mirroring, rotating and transposing image
 
 #![deny(unreachable_pub)]
 #![warn(missing_docs)]
@@ -7,68 +7,158 @@ use std::ffi::CStr;
 use std::os::raw::{c_char, c_uchar};
 use std::panic::catch_unwind;
 
+use image_orientation::{Orientation, Transform};
 use log::error;
-use plugin_errors::PluginError;
+use plugin_abi::{
+    ABI_VERSION, PixelFormat, PluginInfo, ProcessContext, capabilities, is_cancelled,
+};
+use plugin_errors::{PluginError, clear_last_error, last_error_ptr, panic_message};
 use serde::Deserialize;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, PartialEq, Deserialize)]
+#[serde(default)]
 struct MirrorParams {
+    orientation: Orientation,
     horizontal: bool,
     vertical: bool,
 }
 
+impl MirrorParams {
+    /// Orientation followed by mirroring requested by `horizontal` and `vertical`
+    fn transform(&self) -> Transform {
+        Transform::from(self.orientation).then_flip(self.horizontal, self.vertical)
+    }
+}
+
+static PARAMS_SCHEMA: &CStr = cr#"{
+    "type": "object",
+    "properties": {
+        "orientation": {
+            "type": "string",
+            "enum": ["normal", "flip_horizontal", "flip_vertical", "rotate_90", "rotate_180", "rotate_270", "transpose", "transverse", "identity", "mirror_horizontal", "mirror_vertical"],
+            "default": "normal",
+            "description": "Rotation clockwise and/or mirroring applied before horizontal and vertical flips"
+        },
+        "horizontal": { "type": "boolean", "default": false, "description": "Flip image left to right" },
+        "vertical": { "type": "boolean", "default": false, "description": "Flip image upside down" }
+    },
+    "additionalProperties": false
+}"#;
+
+static PLUGIN_INFO: PluginInfo = PluginInfo {
+    abi_version: ABI_VERSION,
+    name: c"mirror".as_ptr(),
+    version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr().cast(),
+    description: c"Mirrors, rotates by multiples of 90 degrees and transposes image".as_ptr(),
+    capabilities: capabilities::IN_PLACE
+        | capabilities::TRANSFORM
+        | capabilities::ERROR_MESSAGE
+        | capabilities::PARAMS_SCHEMA,
+    // Pixels are moved as a whole, so any format is supported
+    pixel_formats: PixelFormat::Rgba8.flag()
+        | PixelFormat::Rgba16.flag()
+        | PixelFormat::Rgba32F.flag()
+        | PixelFormat::Gray8.flag()
+        | PixelFormat::Gray16.flag()
+        | PixelFormat::Gray32F.flag(),
+};
+
+/// Plugin description used by host to check ABI compatibility and supported features
+#[unsafe(no_mangle)]
+pub extern "C" fn plugin_info() -> *const PluginInfo {
+    &PLUGIN_INFO
+}
+
+/// JSON Schema of params accepted by `process_image`, `output_size` and `transform_image`
+#[unsafe(no_mangle)]
+pub extern "C" fn params_schema() -> *const c_char {
+    PARAMS_SCHEMA.as_ptr()
+}
+
+/// Description of last error happened in plugin function called on current thread
+///
+/// Returns null if last call finished successfully. Pointer stays valid until next plugin function call on the same thread
+#[unsafe(no_mangle)]
+pub extern "C" fn last_error_message() -> *const c_char {
+    last_error_ptr()
+}
+
 /// Image conversion function. Runs in-place
 ///
+/// Supports only orientations keeping image dimensions, rotations by 90 and 270 degrees
+/// and transposes are available through `transform_image`
+///
 /// # Arguments
 ///
 /// * `width` - image width in pixels
 /// * `height` - image height in pixels
-/// * `rgba_data` - pointer to image data. Image conversion runs in place so it will contain result data in case of successful conversion
+/// * `pixel_format` - `PixelFormat` of image data, all formats are supported
+/// * `data` - pointer to image data. Image conversion runs in place so it will contain result data in case of successful conversion
 /// * `params` - pointer to params string
+/// * `ctx` - pointer to processing context with cancellation flag, may be null
 ///
 /// # Safety
 ///
 /// Pointers are checked for being non-null before usage
 /// `params` should point to a valid UTF-8 string ending with nul-terminator
-/// `rgba_data` must have at least data_size bytes
+/// `data` must have at least data_size bytes
+/// `ctx` should be null or point to `ProcessContext` valid for the whole call
 ///
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn process_image(
     width: u32,
     height: u32,
-    rgba_data: *mut c_uchar,
+    pixel_format: u32,
+    data: *mut c_uchar,
     params: *const c_char,
+    ctx: *const ProcessContext,
 ) -> i32 {
+    clear_last_error();
+
     let result = catch_unwind(move || {
         // Prevent usage of null pointers
-        if rgba_data.is_null() || params.is_null() {
-            return PluginError::NullPointer as i32;
+        if data.is_null() || params.is_null() {
+            return PluginError::NullPointer.with_message("image data or params pointer is null");
         }
 
         // SAFETY: `params` should point to a valid UTF-8 string ending with nul-terminator
-        let c_str = unsafe { CStr::from_ptr(params) };
-        let params_str = c_str.to_string_lossy();
-
-        let config: MirrorParams = match serde_json::from_str(&params_str) {
+        let config = match unsafe { parse_params(params) } {
             Ok(p) => p,
-            Err(_) => return PluginError::InvalidParams as i32,
+            Err(e) => return PluginError::InvalidParams.with_message(e.to_string()),
+        };
+
+        let transform = config.transform();
+        if transform.transpose {
+            return PluginError::InvalidParams.with_message(format!(
+                "orientation {:?} changes image dimensions, supported by transform_image only",
+                config.orientation
+            ));
+        }
+
+        let Some(format) = PixelFormat::from_u32(pixel_format) else {
+            return PluginError::UnsupportedPixelFormat
+                .with_message(format!("unknown pixel format {pixel_format}"));
         };
 
-        let Some(data_size) = (width as usize)
-            .checked_mul(height as usize)
-            .and_then(|res| res.checked_mul(4))
-        else {
-            return PluginError::SizeIsTooBig as i32;
+        let Some(data_size) = format.data_size(width, height) else {
+            return PluginError::SizeIsTooBig
+                .with_message(format!("image {width}x{height} is too big"));
         };
 
-        // SAFETY: rgba_data must have at least data_size bytes
-        let pixels = unsafe { std::slice::from_raw_parts_mut(rgba_data, data_size) };
+        // SAFETY: data must have at least data_size bytes
+        let pixels = unsafe { std::slice::from_raw_parts_mut(data, data_size) };
+
+        // Mirroring is fast, so cancellation is checked only once before image is changed
+        // SAFETY: ctx should be null or point to valid ProcessContext
+        if is_cancelled(unsafe { ProcessContext::from_ptr(ctx) }) {
+            return PluginError::Cancelled.with_message("processing is cancelled by host");
+        }
 
-        if config.horizontal {
-            mirror_horizontal(width, height, pixels);
+        if transform.flip_x {
+            mirror_horizontal(width, height, format.pixel_size(), pixels);
         }
-        if config.vertical {
-            mirror_vertical(width, height, pixels);
+        if transform.flip_y {
+            mirror_vertical(width, height, format.pixel_size(), pixels);
         }
 
         PluginError::Ok as i32
@@ -77,34 +167,204 @@ pub unsafe extern "C" fn process_image(
     match result {
         Ok(status) => status,
         Err(e) => {
-            error!("panic in process_image {e:?}");
-            PluginError::Panic as i32
+            let message = panic_message(e.as_ref());
+            error!("panic in process_image {message}");
+            PluginError::Panic.with_message(format!("panic in process_image: {message}"))
         }
     }
 }
 
-fn mirror_horizontal(width: u32, height: u32, pixels: &mut [u8]) {
+/// Calculates dimensions of output image
+///
+/// # Arguments
+///
+/// * `width` - input image width in pixels
+/// * `height` - input image height in pixels
+/// * `params` - pointer to params string
+/// * `out_width` - pointer to write output image width to
+/// * `out_height` - pointer to write output image height to
+///
+/// # Safety
+///
+/// Pointers are checked for being non-null before usage
+/// `params` should point to a valid UTF-8 string ending with nul-terminator
+/// `out_width` and `out_height` should point to writable `u32` values
+///
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn output_size(
+    width: u32,
+    height: u32,
+    params: *const c_char,
+    out_width: *mut u32,
+    out_height: *mut u32,
+) -> i32 {
+    clear_last_error();
+
+    let result = catch_unwind(move || {
+        // Prevent usage of null pointers
+        if params.is_null() || out_width.is_null() || out_height.is_null() {
+            return PluginError::NullPointer.with_message("params or output size pointer is null");
+        }
+
+        // SAFETY: `params` should point to a valid UTF-8 string ending with nul-terminator
+        let config = match unsafe { parse_params(params) } {
+            Ok(p) => p,
+            Err(e) => return PluginError::InvalidParams.with_message(e.to_string()),
+        };
+
+        let (new_width, new_height) = config.transform().output_size(width, height);
+
+        // SAFETY: output pointers are checked for being non-null
+        unsafe {
+            *out_width = new_width;
+            *out_height = new_height;
+        }
+
+        PluginError::Ok as i32
+    });
+
+    match result {
+        Ok(status) => status,
+        Err(e) => {
+            let message = panic_message(e.as_ref());
+            error!("panic in output_size {message}");
+            PluginError::Panic.with_message(format!("panic in output_size: {message}"))
+        }
+    }
+}
+
+/// Image conversion function writing result into separate output buffer
+///
+/// # Arguments
+///
+/// * `width` - input image width in pixels
+/// * `height` - input image height in pixels
+/// * `pixel_format` - `PixelFormat` of input and output image data, all formats are supported
+/// * `data` - pointer to input image data
+/// * `out_width` - output image width returned by `output_size`
+/// * `out_height` - output image height returned by `output_size`
+/// * `out_data` - pointer to output image data
+/// * `params` - pointer to params string
+/// * `ctx` - pointer to processing context with cancellation flag, may be null
+///
+/// # Safety
+///
+/// Pointers are checked for being non-null before usage
+/// `params` should point to a valid UTF-8 string ending with nul-terminator
+/// `data` must have at least `width * height` pixels of `pixel_format`
+/// `out_data` must have at least `out_width * out_height` pixels of `pixel_format`
+/// `ctx` should be null or point to `ProcessContext` valid for the whole call
+///
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn transform_image(
+    width: u32,
+    height: u32,
+    pixel_format: u32,
+    data: *const c_uchar,
+    out_width: u32,
+    out_height: u32,
+    out_data: *mut c_uchar,
+    params: *const c_char,
+    ctx: *const ProcessContext,
+) -> i32 {
+    clear_last_error();
+
+    let result = catch_unwind(move || {
+        // Prevent usage of null pointers
+        if data.is_null() || out_data.is_null() || params.is_null() {
+            return PluginError::NullPointer.with_message("image data or params pointer is null");
+        }
+
+        // SAFETY: `params` should point to a valid UTF-8 string ending with nul-terminator
+        let config = match unsafe { parse_params(params) } {
+            Ok(p) => p,
+            Err(e) => return PluginError::InvalidParams.with_message(e.to_string()),
+        };
+
+        let transform = config.transform();
+        let (expected_width, expected_height) = transform.output_size(width, height);
+        if (expected_width, expected_height) != (out_width, out_height) {
+            return PluginError::InvalidOutputSize.with_message(format!(
+                "output buffer is {out_width}x{out_height}, expected {expected_width}x{expected_height}"
+            ));
+        }
+
+        let Some(format) = PixelFormat::from_u32(pixel_format) else {
+            return PluginError::UnsupportedPixelFormat
+                .with_message(format!("unknown pixel format {pixel_format}"));
+        };
+
+        let Some(data_size) = format.data_size(width, height) else {
+            return PluginError::SizeIsTooBig
+                .with_message(format!("image {width}x{height} is too big"));
+        };
+
+        // Reordering pixels is fast, so cancellation is checked only once before processing
+        // SAFETY: ctx should be null or point to valid ProcessContext
+        if is_cancelled(unsafe { ProcessContext::from_ptr(ctx) }) {
+            return PluginError::Cancelled.with_message("processing is cancelled by host");
+        }
+
+        // SAFETY: data must have at least data_size bytes
+        let src = unsafe { std::slice::from_raw_parts(data, data_size) };
+        // SAFETY: out_data must have at least data_size bytes, output has the same number of pixels
+        let dst = unsafe { std::slice::from_raw_parts_mut(out_data, data_size) };
+
+        transform.apply(
+            src,
+            width as usize,
+            height as usize,
+            format.pixel_size(),
+            dst,
+        );
+
+        PluginError::Ok as i32
+    });
+
+    match result {
+        Ok(status) => status,
+        Err(e) => {
+            let message = panic_message(e.as_ref());
+            error!("panic in transform_image {message}");
+            PluginError::Panic.with_message(format!("panic in transform_image: {message}"))
+        }
+    }
+}
+
+/// Read params from nul-terminated JSON string
+///
+/// # Safety
+///
+/// `params` should be non-null pointer to a nul-terminated string
+unsafe fn parse_params(params: *const c_char) -> Result<MirrorParams, serde_json::Error> {
+    let c_str = unsafe { CStr::from_ptr(params) };
+    serde_json::from_str(&c_str.to_string_lossy())
+}
+
+/// Flip image left to right, every pixel is `pixel_size` bytes
+fn mirror_horizontal(width: u32, height: u32, pixel_size: usize, pixels: &mut [u8]) {
     let width = width as usize;
     for y in 0..height as usize {
-        let row_start = y * width * 4;
-        let row_end = row_start + width * 4;
+        let row_start = y * width * pixel_size;
+        let row_end = row_start + width * pixel_size;
         let row = &mut pixels[row_start..row_end];
 
         for x in 0..(width / 2) {
-            let left_idx = x * 4;
-            let right_idx = (width - 1 - x) * 4;
+            let left_idx = x * pixel_size;
+            let right_idx = (width - 1 - x) * pixel_size;
 
-            for i in 0..4 {
+            for i in 0..pixel_size {
                 row.swap(left_idx + i, right_idx + i);
             }
         }
     }
 }
 
-fn mirror_vertical(width: u32, height: u32, pixels: &mut [u8]) {
+/// Flip image upside down, every pixel is `pixel_size` bytes
+fn mirror_vertical(width: u32, height: u32, pixel_size: usize, pixels: &mut [u8]) {
     let width = width as usize;
     let height = height as usize;
-    let row_size = width * 4;
+    let row_size = width * pixel_size;
 
     for y in 0..(height / 2) {
         let top_row_idx = y * row_size;
@@ -135,9 +395,282 @@ mod tests {
     }
 
     #[test]
-    fn test_process_image_null_rgba_data() {
+    fn test_plugin_info() {
+        let info = unsafe { &*plugin_info() };
+        assert_eq!(info.abi_version, ABI_VERSION);
+        assert_eq!(unsafe { CStr::from_ptr(info.name) }, c"mirror");
+        assert!(!unsafe { CStr::from_ptr(info.description) }.is_empty());
+        assert_ne!(info.capabilities & capabilities::IN_PLACE, 0);
+    }
+
+    #[test]
+    fn test_params_defaults() {
+        let params: MirrorParams = serde_json::from_str(r#"{ "vertical": true }"#).unwrap();
+        assert_eq!(
+            params,
+            MirrorParams {
+                orientation: Orientation::Normal,
+                horizontal: false,
+                vertical: true,
+            }
+        );
+    }
+
+    #[test]
+    fn test_params_schema() {
+        let schema = unsafe { CStr::from_ptr(params_schema()) }.to_str().unwrap();
+        let schema: serde_json::Value = serde_json::from_str(schema).unwrap();
+        let properties: Vec<&String> = schema["properties"].as_object().unwrap().keys().collect();
+        assert_eq!(properties, ["horizontal", "orientation", "vertical"]);
+
+        // Schema must accept every name of orientation accepted by params parser, including aliases
+        let names = schema["properties"]["orientation"]["enum"]
+            .as_array()
+            .unwrap();
+        assert_eq!(names.len(), 11);
+        for name in names {
+            assert!(Orientation::deserialize(name).is_ok(), "{name}");
+        }
+    }
+
+    fn transform(width: u32, height: u32, params: &str) -> Result<(u32, u32, Vec<u8>), i32> {
+        let params = CString::new(params).unwrap();
+        let (mut out_width, mut out_height) = (0, 0);
+        let result = unsafe {
+            output_size(
+                width,
+                height,
+                params.as_ptr(),
+                &mut out_width,
+                &mut out_height,
+            )
+        };
+        if result != PluginError::Ok as i32 {
+            return Err(result);
+        }
+
+        let src = create_test_image(width, height);
+        let mut dst = vec![0u8; src.len()];
+        let result = unsafe {
+            transform_image(
+                width,
+                height,
+                PixelFormat::Rgba8 as u32,
+                src.as_ptr(),
+                out_width,
+                out_height,
+                dst.as_mut_ptr(),
+                params.as_ptr(),
+                std::ptr::null(),
+            )
+        };
+        match result {
+            0 => Ok((out_width, out_height, dst)),
+            error => Err(error),
+        }
+    }
+
+    #[test]
+    fn test_plugin_info_transform() {
+        let info = unsafe { &*plugin_info() };
+        assert_ne!(info.capabilities & capabilities::TRANSFORM, 0);
+    }
+
+    #[test]
+    fn test_transform_image_rotate_90() {
+        let (width, height, pixels) = transform(3, 2, r#"{ "orientation": "rotate_90" }"#).unwrap();
+        assert_eq!((width, height), (2, 3));
+        // Left column of result is bottom row of source
+        let expected = [[0, 1], [0, 0], [1, 1], [1, 0], [2, 1], [2, 0]];
+        let expected: Vec<u8> = expected
+            .iter()
+            .flat_map(|[x, y]| [*x, *y, 0, 255])
+            .collect();
+        assert_eq!(pixels, expected);
+    }
+
+    #[test]
+    fn test_transform_image_matches_process_image() {
+        for params in [
+            r#"{ "horizontal": true }"#,
+            r#"{ "orientation": "rotate_180" }"#,
+            r#"{ "orientation": "flip_vertical", "vertical": true }"#,
+        ] {
+            let mut expected = create_test_image(3, 2);
+            let c_params = CString::new(params).unwrap();
+            let result = unsafe {
+                process_image(
+                    3,
+                    2,
+                    PixelFormat::Rgba8 as u32,
+                    expected.as_mut_ptr(),
+                    c_params.as_ptr(),
+                    std::ptr::null(),
+                )
+            };
+            assert_eq!(result, PluginError::Ok as i32, "{params}");
+            assert_eq!(transform(3, 2, params), Ok((3, 2, expected)), "{params}");
+        }
+    }
+
+    #[test]
+    fn test_orientation_with_flips() {
+        // Rotation by 90 degrees followed by both flips is rotation by 270 degrees
+        assert_eq!(
+            transform(
+                3,
+                2,
+                r#"{ "orientation": "rotate_90", "horizontal": true, "vertical": true }"#
+            ),
+            transform(3, 2, r#"{ "orientation": "rotate_270" }"#)
+        );
+    }
+
+    #[test]
+    fn test_plugin_info_pixel_formats() {
+        let info = unsafe { &*plugin_info() };
+        for format in PixelFormat::ALL {
+            assert_ne!(info.pixel_formats & format.flag(), 0, "{}", format.name());
+        }
+    }
+
+    #[test]
+    fn test_process_image_rgba16() {
+        let mut data: Vec<u16> = vec![1, 2, 3, 65535, 40000, 50000, 60000, 65535];
+        let params = CString::new(r#"{ "horizontal": true }"#).unwrap();
+        let result = unsafe {
+            process_image(
+                2,
+                1,
+                PixelFormat::Rgba16 as u32,
+                data.as_mut_ptr().cast(),
+                params.as_ptr(),
+                std::ptr::null(),
+            )
+        };
+        assert_eq!(result, PluginError::Ok as i32);
+        assert_eq!(data, [40000, 50000, 60000, 65535, 1, 2, 3, 65535]);
+    }
+
+    #[test]
+    fn test_process_image_gray8() {
+        let mut data: Vec<u8> = vec![1, 2, 3, 4, 5, 6];
+        let params = CString::new(r#"{ "vertical": true }"#).unwrap();
+        let result = unsafe {
+            process_image(
+                3,
+                2,
+                PixelFormat::Gray8 as u32,
+                data.as_mut_ptr(),
+                params.as_ptr(),
+                std::ptr::null(),
+            )
+        };
+        assert_eq!(result, PluginError::Ok as i32);
+        assert_eq!(data, [4, 5, 6, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_transform_image_rgba32f() {
+        // HDR values above 1 are moved unchanged
+        let src: Vec<f32> = vec![0.5, 1.5, 2.5, 1.0, 0.1, 0.2, 0.3, 0.4];
+        let mut dst = vec![0f32; src.len()];
+        let params = CString::new(r#"{ "orientation": "rotate_90" }"#).unwrap();
+        let result = unsafe {
+            transform_image(
+                2,
+                1,
+                PixelFormat::Rgba32F as u32,
+                src.as_ptr().cast(),
+                1,
+                2,
+                dst.as_mut_ptr().cast(),
+                params.as_ptr(),
+                std::ptr::null(),
+            )
+        };
+        assert_eq!(result, PluginError::Ok as i32);
+        assert_eq!(dst, src);
+    }
+
+    #[test]
+    fn test_unknown_pixel_format() {
+        let mut data = create_test_image(2, 2);
+        let params = CString::new(r#"{ "horizontal": true }"#).unwrap();
+        let result = unsafe {
+            process_image(
+                2,
+                2,
+                42,
+                data.as_mut_ptr(),
+                params.as_ptr(),
+                std::ptr::null(),
+            )
+        };
+        assert_eq!(result, PluginError::UnsupportedPixelFormat as i32);
+        assert_eq!(data, create_test_image(2, 2));
+    }
+
+    #[test]
+    fn test_process_image_rejects_transpose() {
+        let mut data = create_test_image(3, 2);
+        let params = CString::new(r#"{ "orientation": "transpose" }"#).unwrap();
+        let result = unsafe {
+            process_image(
+                3,
+                2,
+                PixelFormat::Rgba8 as u32,
+                data.as_mut_ptr(),
+                params.as_ptr(),
+                std::ptr::null(),
+            )
+        };
+        assert_eq!(result, PluginError::InvalidParams as i32);
+        assert_eq!(data, create_test_image(3, 2));
+    }
+
+    #[test]
+    fn test_transform_image_wrong_output_size() {
+        let src = create_test_image(3, 2);
+        let mut dst = vec![0u8; src.len()];
+        let params = CString::new(r#"{ "orientation": "rotate_270" }"#).unwrap();
+        let result = unsafe {
+            transform_image(
+                3,
+                2,
+                PixelFormat::Rgba8 as u32,
+                src.as_ptr(),
+                3,
+                2,
+                dst.as_mut_ptr(),
+                params.as_ptr(),
+                std::ptr::null(),
+            )
+        };
+        assert_eq!(result, PluginError::InvalidOutputSize as i32);
+    }
+
+    #[test]
+    fn test_unknown_orientation() {
+        assert_eq!(
+            transform(3, 2, r#"{ "orientation": "rotate_45" }"#),
+            Err(PluginError::InvalidParams as i32)
+        );
+    }
+
+    #[test]
+    fn test_process_image_null_data() {
         let params = CString::new(r#"{ "horizontal": true, "vertical": false }"#).unwrap();
-        let result = unsafe { process_image(1, 1, std::ptr::null_mut(), params.as_ptr()) };
+        let result = unsafe {
+            process_image(
+                1,
+                1,
+                PixelFormat::Rgba8 as u32,
+                std::ptr::null_mut(),
+                params.as_ptr(),
+                std::ptr::null(),
+            )
+        };
         assert_eq!(result, PluginError::NullPointer as i32);
     }
 
@@ -145,9 +678,17 @@ mod tests {
     fn test_process_image_null_params() {
         let width = 1;
         let height = 1;
-        let mut rgba_data = create_test_image(width, height);
-        let result =
-            unsafe { process_image(width, height, rgba_data.as_mut_ptr(), std::ptr::null()) };
+        let mut data = create_test_image(width, height);
+        let result = unsafe {
+            process_image(
+                width,
+                height,
+                PixelFormat::Rgba8 as u32,
+                data.as_mut_ptr(),
+                std::ptr::null(),
+                std::ptr::null(),
+            )
+        };
         assert_eq!(result, PluginError::NullPointer as i32);
     }
 
@@ -155,30 +696,77 @@ mod tests {
     fn test_process_image_invalid_json_params() {
         let width = 1;
         let height = 1;
-        let mut rgba_data = create_test_image(width, height);
+        let mut data = create_test_image(width, height);
         let params = CString::new(r#"{ "horizontal": true, "vertical": false, }"#).unwrap(); // Trailing comma
-        let result =
-            unsafe { process_image(width, height, rgba_data.as_mut_ptr(), params.as_ptr()) };
+        let result = unsafe {
+            process_image(
+                width,
+                height,
+                PixelFormat::Rgba8 as u32,
+                data.as_mut_ptr(),
+                params.as_ptr(),
+                std::ptr::null(),
+            )
+        };
         assert_eq!(result, PluginError::InvalidParams as i32);
     }
 
     #[test]
-    fn test_process_image_missing_fields_in_params() {
+    fn test_process_image_missing_fields_use_defaults() {
         let width = 10;
         let height = 10;
-        let mut rgba_data = create_test_image(width, height);
-        let params = CString::new(r#"{ "horizontal": true }"#).unwrap(); // Missing vertical
-        let result =
-            unsafe { process_image(width, height, rgba_data.as_mut_ptr(), params.as_ptr()) };
+        let mut data = create_test_image(width, height);
+        let params = CString::new(r#"{ "horizontal": true }"#).unwrap(); // Missing fields use defaults
+        let result = unsafe {
+            process_image(
+                width,
+                height,
+                PixelFormat::Rgba8 as u32,
+                data.as_mut_ptr(),
+                params.as_ptr(),
+                std::ptr::null(),
+            )
+        };
+        assert_eq!(result, PluginError::Ok as i32);
+    }
+
+    #[test]
+    fn test_process_image_error_message() {
+        let width = 1;
+        let height = 1;
+        let mut data = create_test_image(width, height);
+        let params = CString::new(r#"{ "horizontal": 1 }"#).unwrap();
+        let result = unsafe {
+            process_image(
+                width,
+                height,
+                PixelFormat::Rgba8 as u32,
+                data.as_mut_ptr(),
+                params.as_ptr(),
+                std::ptr::null(),
+            )
+        };
         assert_eq!(result, PluginError::InvalidParams as i32);
+
+        let message = unsafe { CStr::from_ptr(last_error_message()) }.to_string_lossy();
+        assert!(message.starts_with("invalid type"), "{message}");
+        assert!(message.contains("line 1 column"), "{message}");
     }
 
     #[test]
     fn test_size_too_big() {
-        let mut rgba_data = vec![0u8; 4];
+        let mut data = vec![0u8; 4];
         let params = CString::new(r#"{ "horizontal": true, "vertical": true }"#).unwrap();
-        let result =
-            unsafe { process_image(u32::MAX, u32::MAX, rgba_data.as_mut_ptr(), params.as_ptr()) };
+        let result = unsafe {
+            process_image(
+                u32::MAX,
+                u32::MAX,
+                PixelFormat::Rgba8 as u32,
+                data.as_mut_ptr(),
+                params.as_ptr(),
+                std::ptr::null(),
+            )
+        };
 
         assert_eq!(result, PluginError::SizeIsTooBig as i32);
     }
@@ -187,14 +775,23 @@ mod tests {
     fn test_process_image_does_something_if_no_errors() {
         let width = 2;
         let height = 2;
-        let mut rgba_data = create_test_image(width, height);
-        let original_data = rgba_data.clone();
+        let mut data = create_test_image(width, height);
+        let original_data = data.clone();
         let params = CString::new(r#"{ "horizontal": true, "vertical": true }"#).unwrap();
-        let result =
-            unsafe { process_image(width, height, rgba_data.as_mut_ptr(), params.as_ptr()) };
+        let result = unsafe {
+            process_image(
+                width,
+                height,
+                PixelFormat::Rgba8 as u32,
+                data.as_mut_ptr(),
+                params.as_ptr(),
+                std::ptr::null(),
+            )
+        };
 
         assert_eq!(result, PluginError::Ok as i32);
-        assert_ne!(rgba_data, original_data)
+        assert!(last_error_message().is_null());
+        assert_ne!(data, original_data)
     }
 
     #[test]
@@ -205,7 +802,7 @@ mod tests {
         let mut pixels = create_test_image(width, height);
 
         let expected = vec![1, 0, 0, 255, 0, 0, 0, 255, 1, 1, 0, 255, 0, 1, 0, 255];
-        mirror_horizontal(width, height, &mut pixels);
+        mirror_horizontal(width, height, 4, &mut pixels);
         assert_eq!(pixels, expected);
     }
 
@@ -217,7 +814,7 @@ mod tests {
         let mut pixels = create_test_image(width, height);
 
         let expected = vec![2, 0, 0, 255, 1, 0, 0, 255, 0, 0, 0, 255];
-        mirror_horizontal(width, height, &mut pixels);
+        mirror_horizontal(width, height, 4, &mut pixels);
         assert_eq!(pixels, expected);
     }
 
@@ -227,7 +824,7 @@ mod tests {
         let height = 1;
         let mut pixels = create_test_image(width, height);
         let original_pixels = pixels.clone();
-        mirror_horizontal(width, height, &mut pixels);
+        mirror_horizontal(width, height, 4, &mut pixels);
         assert_eq!(pixels, original_pixels);
     }
 
@@ -239,7 +836,7 @@ mod tests {
         let mut pixels = create_test_image(width, height);
 
         let expected = vec![0, 1, 0, 255, 1, 1, 0, 255, 0, 0, 0, 255, 1, 0, 0, 255];
-        mirror_vertical(width, height, &mut pixels);
+        mirror_vertical(width, height, 4, &mut pixels);
         assert_eq!(pixels, expected);
     }
 
@@ -251,7 +848,7 @@ mod tests {
         let mut pixels = create_test_image(width, height);
 
         let expected = vec![0, 2, 0, 255, 0, 1, 0, 255, 0, 0, 0, 255];
-        mirror_vertical(width, height, &mut pixels);
+        mirror_vertical(width, height, 4, &mut pixels);
         assert_eq!(pixels, expected);
     }
 
@@ -261,7 +858,7 @@ mod tests {
         let height = 1;
         let mut pixels = create_test_image(width, height);
         let original_pixels = pixels.clone();
-        mirror_vertical(width, height, &mut pixels);
+        mirror_vertical(width, height, 4, &mut pixels);
         assert_eq!(pixels, original_pixels);
     }
 }
@@ -0,0 +1,271 @@
+//! Shared plugin ABI description types
+#![deny(unreachable_pub)]
+#![warn(missing_docs)]
+
+use std::os::raw::{c_char, c_void};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Version of plugin ABI. Host refuses to use plugins built against another version
+pub const ABI_VERSION: u32 = 6;
+
+/// Host callback receiving number of done and total work units of current plugin call
+///
+/// May be called from any thread, but only during the plugin call
+pub type ProgressCallback = unsafe extern "C" fn(user_data: *mut c_void, done: u64, total: u64);
+
+/// Plugin capability flags used in `PluginInfo::capabilities`
+pub mod capabilities {
+    /// Plugin exports `process_image` function converting image in-place
+    pub const IN_PLACE: u64 = 1 << 0;
+
+    /// Plugin exports `output_size` and `transform_image` functions
+    /// and is able to produce image with dimensions different from input
+    pub const TRANSFORM: u64 = 1 << 1;
+
+    /// Plugin exports `last_error_message` function returning description of last error
+    pub const ERROR_MESSAGE: u64 = 1 << 2;
+
+    /// Plugin exports `params_schema` function returning JSON Schema of its params
+    pub const PARAMS_SCHEMA: u64 = 1 << 3;
+}
+
+/// Layout of image data passed to plugin functions
+///
+/// Pixels are stored row by row without padding, channels of pixel are stored one after another.
+/// 16-bit and floating point channels use native byte order and data pointer is aligned to channel size.
+/// Floating point channels are in `0.0..=1.0` for ordinary images, HDR images may exceed 1.0
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// Red, green, blue and straight alpha, 8 bits per channel
+    Rgba8 = 0,
+    /// Red, green, blue and straight alpha, 16 bits per channel
+    Rgba16 = 1,
+    /// Red, green, blue and straight alpha, 32-bit float per channel
+    Rgba32F = 2,
+    /// Single luminance channel of 8 bits
+    Gray8 = 3,
+    /// Single luminance channel of 16 bits
+    Gray16 = 4,
+    /// Single luminance channel of 32-bit float
+    Gray32F = 5,
+}
+
+impl PixelFormat {
+    /// All formats known in current ABI version
+    pub const ALL: [PixelFormat; 6] = [
+        PixelFormat::Rgba8,
+        PixelFormat::Rgba16,
+        PixelFormat::Rgba32F,
+        PixelFormat::Gray8,
+        PixelFormat::Gray16,
+        PixelFormat::Gray32F,
+    ];
+
+    /// Map value passed through ABI to format, None for unknown values
+    pub fn from_u32(value: u32) -> Option<Self> {
+        PixelFormat::ALL
+            .into_iter()
+            .find(|format| *format as u32 == value)
+    }
+
+    /// Flag of format in `PluginInfo::pixel_formats`
+    pub const fn flag(self) -> u64 {
+        1 << self as u32
+    }
+
+    /// Short lowercase name like `rgba16`
+    pub fn name(self) -> &'static str {
+        match self {
+            PixelFormat::Rgba8 => "rgba8",
+            PixelFormat::Rgba16 => "rgba16",
+            PixelFormat::Rgba32F => "rgba32f",
+            PixelFormat::Gray8 => "gray8",
+            PixelFormat::Gray16 => "gray16",
+            PixelFormat::Gray32F => "gray32f",
+        }
+    }
+
+    /// Number of channels of pixel
+    pub fn channels(self) -> usize {
+        match self {
+            PixelFormat::Rgba8 | PixelFormat::Rgba16 | PixelFormat::Rgba32F => 4,
+            PixelFormat::Gray8 | PixelFormat::Gray16 | PixelFormat::Gray32F => 1,
+        }
+    }
+
+    /// Size of channel value in bytes
+    pub fn channel_size(self) -> usize {
+        match self {
+            PixelFormat::Rgba8 | PixelFormat::Gray8 => 1,
+            PixelFormat::Rgba16 | PixelFormat::Gray16 => 2,
+            PixelFormat::Rgba32F | PixelFormat::Gray32F => 4,
+        }
+    }
+
+    /// Size of pixel in bytes
+    pub fn pixel_size(self) -> usize {
+        self.channels() * self.channel_size()
+    }
+
+    /// Size of image data in bytes for image with given dimensions or None on overflow
+    pub fn data_size(self, width: u32, height: u32) -> Option<usize> {
+        (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|res| res.checked_mul(self.pixel_size()))
+    }
+}
+
+/// Plugin description returned by `plugin_info` function exported from plugin
+///
+/// `abi_version` must stay the first field in all ABI versions,
+/// so host is able to read it before checking the rest of the struct
+#[repr(C)]
+pub struct PluginInfo {
+    /// ABI version plugin was built against
+    pub abi_version: u32,
+
+    /// Pointer to nul-terminated plugin name
+    pub name: *const c_char,
+
+    /// Pointer to nul-terminated plugin version
+    pub version: *const c_char,
+
+    /// Pointer to nul-terminated short human readable description of plugin, may be null
+    pub description: *const c_char,
+
+    /// Bit set of `capabilities` flags
+    pub capabilities: u64,
+
+    /// Bit set of `PixelFormat::flag` of formats accepted by `process_image` and `transform_image`.
+    /// Host converts image to one of them before calling plugin
+    pub pixel_formats: u64,
+}
+
+// SAFETY: plugins expose PluginInfo as immutable static pointing to static strings only
+unsafe impl Sync for PluginInfo {}
+
+/// Context passed by host to `process_image` and `transform_image` functions
+#[repr(C)]
+pub struct ProcessContext {
+    /// Pointer to flag set by host when processing should be stopped, may be null.
+    /// Plugins should poll it during long operations and return `PluginError::Cancelled` once it is set
+    pub cancel_flag: *const AtomicBool,
+
+    /// Host callback for progress reporting, may be null
+    pub progress: Option<ProgressCallback>,
+
+    /// Opaque pointer passed back to `progress` callback
+    pub progress_user_data: *mut c_void,
+
+    /// Number of threads plugin is recommended to use, 0 means plugin decides itself.
+    /// Host lowers it when several images are processed in parallel
+    pub thread_count: u32,
+}
+
+// SAFETY: host keeps cancellation flag and progress callback valid and thread-safe for the whole plugin call,
+// so plugin may share context between its worker threads
+unsafe impl Sync for ProcessContext {}
+
+impl ProcessContext {
+    /// Convert context pointer received from host into reference, null pointer means no context
+    ///
+    /// # Safety
+    ///
+    /// `ctx` should be null or point to `ProcessContext` valid for the whole plugin call
+    pub unsafe fn from_ptr<'a>(ctx: *const ProcessContext) -> Option<&'a ProcessContext> {
+        unsafe { ctx.as_ref() }
+    }
+
+    /// Check if host asked plugin to stop processing
+    pub fn is_cancelled(&self) -> bool {
+        // SAFETY: host keeps flag alive for the whole plugin call
+        !self.cancel_flag.is_null() && unsafe { (*self.cancel_flag).load(Ordering::Relaxed) }
+    }
+
+    /// Number of threads recommended by host, None if host left the choice to plugin
+    pub fn thread_count(&self) -> Option<usize> {
+        (self.thread_count > 0).then_some(self.thread_count as usize)
+    }
+
+    /// Report that `done` of `total` work units are finished
+    pub fn report_progress(&self, done: u64, total: u64) {
+        if let Some(progress) = self.progress {
+            // SAFETY: host keeps callback and its user data alive for the whole plugin call
+            unsafe { progress(self.progress_user_data, done, total) };
+        }
+    }
+}
+
+/// Report progress to host if context is given
+pub fn report_progress(ctx: Option<&ProcessContext>, done: u64, total: u64) {
+    if let Some(ctx) = ctx {
+        ctx.report_progress(done, total);
+    }
+}
+
+/// Check if host asked plugin to stop processing, missing context is never cancelled
+pub fn is_cancelled(ctx: Option<&ProcessContext>) -> bool {
+    ctx.is_some_and(ProcessContext::is_cancelled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pixel_format() {
+        for format in PixelFormat::ALL {
+            assert_eq!(PixelFormat::from_u32(format as u32), Some(format));
+        }
+        assert_eq!(PixelFormat::from_u32(6), None);
+
+        assert_eq!(PixelFormat::Rgba16.flag(), 1 << 1);
+        assert_eq!(PixelFormat::Rgba32F.pixel_size(), 16);
+        assert_eq!(PixelFormat::Gray16.pixel_size(), 2);
+        assert_eq!(PixelFormat::Rgba16.data_size(3, 2), Some(48));
+        assert_eq!(PixelFormat::Rgba8.data_size(u32::MAX, u32::MAX), None);
+    }
+
+    #[test]
+    fn test_is_cancelled() {
+        let flag = AtomicBool::new(false);
+        let ctx = ProcessContext {
+            cancel_flag: &flag,
+            progress: None,
+            progress_user_data: std::ptr::null_mut(),
+            thread_count: 0,
+        };
+        assert!(!is_cancelled(Some(&ctx)));
+
+        flag.store(true, Ordering::Relaxed);
+        assert!(is_cancelled(Some(&ctx)));
+
+        let no_flag = ProcessContext {
+            cancel_flag: std::ptr::null(),
+            progress: None,
+            progress_user_data: std::ptr::null_mut(),
+            thread_count: 0,
+        };
+        assert!(!is_cancelled(Some(&no_flag)));
+        assert!(!is_cancelled(None));
+    }
+
+    #[test]
+    fn test_report_progress() {
+        unsafe extern "C" fn store(user_data: *mut c_void, done: u64, total: u64) {
+            unsafe { *(user_data as *mut (u64, u64)) = (done, total) };
+        }
+
+        let mut reported = (0u64, 0u64);
+        let ctx = ProcessContext {
+            cancel_flag: std::ptr::null(),
+            progress: Some(store),
+            progress_user_data: (&mut reported as *mut (u64, u64)).cast(),
+            thread_count: 0,
+        };
+        report_progress(Some(&ctx), 3, 10);
+        report_progress(None, 5, 10);
+        assert_eq!(reported, (3, 10));
+    }
+}
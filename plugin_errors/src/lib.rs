@@ -2,9 +2,15 @@
 #![deny(unreachable_pub)]
 #![warn(missing_docs)]
 
+use std::any::Any;
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::os::raw::c_char;
+
 /// Known plugin errors with mappings into i32 for ABI interaction
 /// Used as return code form process_image function
 #[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PluginError {
     /// No error
     Ok = 0,
@@ -20,6 +26,18 @@ pub enum PluginError {
 
     /// Unable to convert image with given dimensions
     SizeIsTooBig = 4,
+
+    /// Output buffer dimensions differ from ones reported by `output_size`
+    InvalidOutputSize = 5,
+
+    /// Processing was stopped because host set cancellation flag
+    Cancelled = 6,
+
+    /// Plugin does not accept pixel format of image data
+    UnsupportedPixelFormat = 7,
+
+    /// Image data can not be read in given pixel format, like pointer not aligned to channel size
+    InvalidData = 8,
 }
 
 impl PluginError {
@@ -31,7 +49,95 @@ impl PluginError {
             2 => Some(PluginError::NullPointer),
             3 => Some(PluginError::Panic),
             4 => Some(PluginError::SizeIsTooBig),
+            5 => Some(PluginError::InvalidOutputSize),
+            6 => Some(PluginError::Cancelled),
+            7 => Some(PluginError::UnsupportedPixelFormat),
+            8 => Some(PluginError::InvalidData),
             _ => None,
         }
     }
+
+    /// Save human-readable description of error as last error message and return error code
+    pub fn with_message(self, message: impl Into<String>) -> i32 {
+        set_last_error(message);
+        self as i32
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Save message describing last error happened on current thread
+pub fn set_last_error(message: impl Into<String>) {
+    // Nul bytes can not be passed through C string, so they are replaced
+    let message = message.into().replace('\0', "\\0");
+    LAST_ERROR.with(|last| *last.borrow_mut() = CString::new(message).ok());
+}
+
+/// Forget last error message of current thread. Should be called when plugin function starts
+pub fn clear_last_error() {
+    LAST_ERROR.with(|last| *last.borrow_mut() = None);
+}
+
+/// Pointer to nul-terminated last error message of current thread or null if there is no message
+///
+/// Pointer stays valid until next `set_last_error` or `clear_last_error` call on the same thread
+pub fn last_error_ptr() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// Extract message from panic payload returned by `catch_unwind`
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic payload".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+    use std::panic::catch_unwind;
+
+    fn last_error() -> Option<String> {
+        let ptr = last_error_ptr();
+        (!ptr.is_null()).then(|| unsafe { CStr::from_ptr(ptr) }.to_string_lossy().to_string())
+    }
+
+    #[test]
+    fn test_last_error() {
+        clear_last_error();
+        assert_eq!(last_error(), None);
+
+        let code = PluginError::InvalidParams.with_message("missing field");
+        assert_eq!(code, PluginError::InvalidParams as i32);
+        assert_eq!(last_error().as_deref(), Some("missing field"));
+
+        clear_last_error();
+        assert_eq!(last_error(), None);
+    }
+
+    #[test]
+    fn test_last_error_with_nul() {
+        set_last_error("a\0b");
+        assert_eq!(last_error().as_deref(), Some("a\\0b"));
+    }
+
+    #[test]
+    fn test_panic_message() {
+        let payload = catch_unwind(|| panic!("static message")).unwrap_err();
+        assert_eq!(panic_message(payload.as_ref()), "static message");
+
+        let payload = catch_unwind(|| panic!("formatted {}", 42)).unwrap_err();
+        assert_eq!(panic_message(payload.as_ref()), "formatted 42");
+    }
 }
@@ -0,0 +1,610 @@
+//! Image processor plugin for cropping and resizing image
+
+#![deny(unreachable_pub)]
+#![warn(missing_docs)]
+
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_uchar};
+use std::panic::catch_unwind;
+
+use log::error;
+use plugin_abi::{
+    ABI_VERSION, PixelFormat, PluginInfo, ProcessContext, capabilities, is_cancelled,
+};
+use plugin_errors::{PluginError, clear_last_error, last_error_ptr, panic_message};
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+struct ResizeParams {
+    crop: Option<CropRect>,
+    width: Option<u32>,
+    height: Option<u32>,
+    #[serde(default)]
+    filter: Filter,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+struct CropRect {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Filter {
+    Nearest,
+    #[default]
+    Bilinear,
+}
+
+static PARAMS_SCHEMA: &CStr = cr#"{
+    "type": "object",
+    "properties": {
+        "crop": {
+            "type": ["object", "null"],
+            "description": "Region of input image to keep, whole image by default",
+            "properties": {
+                "x": { "type": "integer", "minimum": 0 },
+                "y": { "type": "integer", "minimum": 0 },
+                "width": { "type": "integer", "minimum": 0 },
+                "height": { "type": "integer", "minimum": 0 }
+            },
+            "required": ["x", "y", "width", "height"],
+            "additionalProperties": false
+        },
+        "width": { "type": ["integer", "null"], "minimum": 0, "description": "Output width, keeps aspect ratio if omitted" },
+        "height": { "type": ["integer", "null"], "minimum": 0, "description": "Output height, keeps aspect ratio if omitted" },
+        "filter": { "type": "string", "enum": ["nearest", "bilinear"], "default": "bilinear" }
+    },
+    "additionalProperties": false
+}"#;
+
+static PLUGIN_INFO: PluginInfo = PluginInfo {
+    abi_version: ABI_VERSION,
+    name: c"resize".as_ptr(),
+    version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr().cast(),
+    description: c"Crops and resizes image".as_ptr(),
+    capabilities: capabilities::TRANSFORM
+        | capabilities::ERROR_MESSAGE
+        | capabilities::PARAMS_SCHEMA,
+    // Bilinear interpolation works with 8-bit channels only
+    pixel_formats: PixelFormat::Rgba8.flag(),
+};
+
+/// Plugin description used by host to check ABI compatibility and supported features
+#[unsafe(no_mangle)]
+pub extern "C" fn plugin_info() -> *const PluginInfo {
+    &PLUGIN_INFO
+}
+
+/// JSON Schema of params accepted by `output_size` and `transform_image`
+#[unsafe(no_mangle)]
+pub extern "C" fn params_schema() -> *const c_char {
+    PARAMS_SCHEMA.as_ptr()
+}
+
+/// Description of last error happened in plugin function called on current thread
+///
+/// Returns null if last call finished successfully. Pointer stays valid until next plugin function call on the same thread
+#[unsafe(no_mangle)]
+pub extern "C" fn last_error_message() -> *const c_char {
+    last_error_ptr()
+}
+
+/// Calculates dimensions of output image
+///
+/// # Arguments
+///
+/// * `width` - input image width in pixels
+/// * `height` - input image height in pixels
+/// * `params` - pointer to params string
+/// * `out_width` - pointer to write output image width to
+/// * `out_height` - pointer to write output image height to
+///
+/// # Safety
+///
+/// Pointers are checked for being non-null before usage
+/// `params` should point to a valid UTF-8 string ending with nul-terminator
+/// `out_width` and `out_height` should point to writable `u32` values
+///
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn output_size(
+    width: u32,
+    height: u32,
+    params: *const c_char,
+    out_width: *mut u32,
+    out_height: *mut u32,
+) -> i32 {
+    clear_last_error();
+
+    let result = catch_unwind(move || {
+        // Prevent usage of null pointers
+        if params.is_null() || out_width.is_null() || out_height.is_null() {
+            return PluginError::NullPointer.with_message("params or output size pointer is null");
+        }
+
+        // SAFETY: `params` should point to a valid UTF-8 string ending with nul-terminator
+        let config = match unsafe { parse_params(params) } {
+            Ok(p) => p,
+            Err(e) => return PluginError::InvalidParams.with_message(e.to_string()),
+        };
+
+        let (_, (new_width, new_height)) = match layout(width, height, &config) {
+            Ok(layout) => layout,
+            Err(message) => return PluginError::InvalidParams.with_message(message),
+        };
+
+        // SAFETY: output pointers are checked for being non-null
+        unsafe {
+            *out_width = new_width;
+            *out_height = new_height;
+        }
+
+        PluginError::Ok as i32
+    });
+
+    match result {
+        Ok(status) => status,
+        Err(e) => {
+            let message = panic_message(e.as_ref());
+            error!("panic in output_size {message}");
+            PluginError::Panic.with_message(format!("panic in output_size: {message}"))
+        }
+    }
+}
+
+/// Image conversion function writing result into separate output buffer
+///
+/// # Arguments
+///
+/// * `width` - input image width in pixels
+/// * `height` - input image height in pixels
+/// * `pixel_format` - `PixelFormat` of input and output image data, only RGBA8 is supported
+/// * `data` - pointer to input image data
+/// * `out_width` - output image width returned by `output_size`
+/// * `out_height` - output image height returned by `output_size`
+/// * `out_data` - pointer to output image data
+/// * `params` - pointer to params string
+/// * `ctx` - pointer to processing context with cancellation flag, may be null
+///
+/// # Safety
+///
+/// Pointers are checked for being non-null before usage
+/// `params` should point to a valid UTF-8 string ending with nul-terminator
+/// `data` must have at least `width * height * 4` bytes
+/// `out_data` must have at least `out_width * out_height * 4` bytes
+/// `ctx` should be null or point to `ProcessContext` valid for the whole call
+///
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn transform_image(
+    width: u32,
+    height: u32,
+    pixel_format: u32,
+    data: *const c_uchar,
+    out_width: u32,
+    out_height: u32,
+    out_data: *mut c_uchar,
+    params: *const c_char,
+    ctx: *const ProcessContext,
+) -> i32 {
+    clear_last_error();
+
+    let result = catch_unwind(move || {
+        // Prevent usage of null pointers
+        if data.is_null() || out_data.is_null() || params.is_null() {
+            return PluginError::NullPointer.with_message("image data or params pointer is null");
+        }
+
+        // SAFETY: `params` should point to a valid UTF-8 string ending with nul-terminator
+        let config = match unsafe { parse_params(params) } {
+            Ok(p) => p,
+            Err(e) => return PluginError::InvalidParams.with_message(e.to_string()),
+        };
+
+        let (crop, (expected_width, expected_height)) = match layout(width, height, &config) {
+            Ok(layout) => layout,
+            Err(message) => return PluginError::InvalidParams.with_message(message),
+        };
+        if (expected_width, expected_height) != (out_width, out_height) {
+            return PluginError::InvalidOutputSize.with_message(format!(
+                "output buffer is {out_width}x{out_height}, expected {expected_width}x{expected_height}"
+            ));
+        }
+
+        match PixelFormat::from_u32(pixel_format) {
+            Some(PixelFormat::Rgba8) => {}
+            Some(format) => {
+                return PluginError::UnsupportedPixelFormat
+                    .with_message(format!("pixel format {} is not supported", format.name()));
+            }
+            None => {
+                return PluginError::UnsupportedPixelFormat
+                    .with_message(format!("unknown pixel format {pixel_format}"));
+            }
+        }
+
+        let (Some(data_size), Some(out_data_size)) = (
+            PixelFormat::Rgba8.data_size(width, height),
+            PixelFormat::Rgba8.data_size(out_width, out_height),
+        ) else {
+            return PluginError::SizeIsTooBig
+                .with_message(format!("image {width}x{height} is too big"));
+        };
+
+        // Resizing is fast, so cancellation is checked only once before processing
+        // SAFETY: ctx should be null or point to valid ProcessContext
+        if is_cancelled(unsafe { ProcessContext::from_ptr(ctx) }) {
+            return PluginError::Cancelled.with_message("processing is cancelled by host");
+        }
+
+        // SAFETY: data must have at least data_size bytes
+        let src = unsafe { std::slice::from_raw_parts(data, data_size) };
+        // SAFETY: out_data must have at least out_data_size bytes
+        let dst = unsafe { std::slice::from_raw_parts_mut(out_data, out_data_size) };
+
+        match config.filter {
+            Filter::Nearest => resize_nearest(src, width as usize, crop, dst, out_width as usize),
+            Filter::Bilinear => resize_bilinear(src, width as usize, crop, dst, out_width as usize),
+        }
+
+        PluginError::Ok as i32
+    });
+
+    match result {
+        Ok(status) => status,
+        Err(e) => {
+            let message = panic_message(e.as_ref());
+            error!("panic in transform_image {message}");
+            PluginError::Panic.with_message(format!("panic in transform_image: {message}"))
+        }
+    }
+}
+
+/// Read params from nul-terminated JSON string
+///
+/// # Safety
+///
+/// `params` should be non-null pointer to a nul-terminated string
+unsafe fn parse_params(params: *const c_char) -> Result<ResizeParams, serde_json::Error> {
+    let c_str = unsafe { CStr::from_ptr(params) };
+    serde_json::from_str(&c_str.to_string_lossy())
+}
+
+/// Validate params against input dimensions and return source region with output dimensions
+/// or description of invalid param
+fn layout(
+    width: u32,
+    height: u32,
+    params: &ResizeParams,
+) -> Result<(CropRect, (u32, u32)), String> {
+    let crop = params.crop.unwrap_or(CropRect {
+        x: 0,
+        y: 0,
+        width,
+        height,
+    });
+
+    let fits =
+        |start: u32, size: u32, limit: u32| start.checked_add(size).is_some_and(|end| end <= limit);
+    if crop.width == 0
+        || crop.height == 0
+        || !fits(crop.x, crop.width, width)
+        || !fits(crop.y, crop.height, height)
+    {
+        return Err(format!(
+            "crop rectangle {}x{} at ({}, {}) does not fit into image {width}x{height}",
+            crop.width, crop.height, crop.x, crop.y
+        ));
+    }
+
+    let scaled = |value: u32, to: u32, from: u32| {
+        ((value as u64 * to as u64 + from as u64 / 2) / from as u64).clamp(1, u32::MAX as u64)
+            as u32
+    };
+
+    let size = match (params.width, params.height) {
+        (Some(0), _) | (_, Some(0)) => return Err("output size should not be zero".to_string()),
+        (Some(w), Some(h)) => (w, h),
+        (Some(w), None) => (w, scaled(crop.height, w, crop.width)),
+        (None, Some(h)) => (scaled(crop.width, h, crop.height), h),
+        (None, None) => (crop.width, crop.height),
+    };
+
+    Ok((crop, size))
+}
+
+fn resize_nearest(src: &[u8], src_width: usize, crop: CropRect, dst: &mut [u8], dst_width: usize) {
+    let dst_height = dst.len() / 4 / dst_width;
+    let (crop_x, crop_y) = (crop.x as usize, crop.y as usize);
+    let (crop_width, crop_height) = (crop.width as usize, crop.height as usize);
+
+    for y in 0..dst_height {
+        let sy = crop_y + (y * crop_height) / dst_height;
+        for x in 0..dst_width {
+            let sx = crop_x + (x * crop_width) / dst_width;
+            let src_idx = (sy * src_width + sx) * 4;
+            let dst_idx = (y * dst_width + x) * 4;
+            dst[dst_idx..dst_idx + 4].copy_from_slice(&src[src_idx..src_idx + 4]);
+        }
+    }
+}
+
+fn resize_bilinear(src: &[u8], src_width: usize, crop: CropRect, dst: &mut [u8], dst_width: usize) {
+    let dst_height = dst.len() / 4 / dst_width;
+    let scale_x = crop.width as f32 / dst_width as f32;
+    let scale_y = crop.height as f32 / dst_height as f32;
+    let max_x = (crop.width - 1) as f32;
+    let max_y = (crop.height - 1) as f32;
+
+    for y in 0..dst_height {
+        let fy = ((y as f32 + 0.5) * scale_y - 0.5).clamp(0.0, max_y);
+        let y0 = fy.floor() as usize;
+        let y1 = (y0 + 1).min(crop.height as usize - 1);
+        let ty = fy - y0 as f32;
+
+        for x in 0..dst_width {
+            let fx = ((x as f32 + 0.5) * scale_x - 0.5).clamp(0.0, max_x);
+            let x0 = fx.floor() as usize;
+            let x1 = (x0 + 1).min(crop.width as usize - 1);
+            let tx = fx - x0 as f32;
+
+            let idx = |cx: usize, cy: usize| {
+                ((crop.y as usize + cy) * src_width + crop.x as usize + cx) * 4
+            };
+            let (i00, i10, i01, i11) = (idx(x0, y0), idx(x1, y0), idx(x0, y1), idx(x1, y1));
+            let dst_idx = (y * dst_width + x) * 4;
+
+            for c in 0..4 {
+                let top = src[i00 + c] as f32 * (1.0 - tx) + src[i10 + c] as f32 * tx;
+                let bottom = src[i01 + c] as f32 * (1.0 - tx) + src[i11 + c] as f32 * tx;
+                dst[dst_idx + c] = (top * (1.0 - ty) + bottom * ty).round() as u8;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+
+    fn create_test_image(width: u32, height: u32) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                pixels.push(x as u8);
+                pixels.push(y as u8);
+                pixels.push(0);
+                pixels.push(255);
+            }
+        }
+        pixels
+    }
+
+    fn query_size(width: u32, height: u32, params: &str) -> Result<(u32, u32), i32> {
+        let params = CString::new(params).unwrap();
+        let (mut out_width, mut out_height) = (0, 0);
+        let result = unsafe {
+            output_size(
+                width,
+                height,
+                params.as_ptr(),
+                &mut out_width,
+                &mut out_height,
+            )
+        };
+        if result == PluginError::Ok as i32 {
+            Ok((out_width, out_height))
+        } else {
+            Err(result)
+        }
+    }
+
+    #[test]
+    fn test_plugin_info() {
+        let info = unsafe { &*plugin_info() };
+        assert_eq!(info.abi_version, ABI_VERSION);
+        assert_eq!(unsafe { CStr::from_ptr(info.name) }, c"resize");
+        assert!(!unsafe { CStr::from_ptr(info.description) }.is_empty());
+        assert_ne!(info.capabilities & capabilities::TRANSFORM, 0);
+    }
+
+    #[test]
+    fn test_params_schema() {
+        let schema = unsafe { CStr::from_ptr(params_schema()) }.to_str().unwrap();
+        let schema: serde_json::Value = serde_json::from_str(schema).unwrap();
+        let properties: Vec<&String> = schema["properties"].as_object().unwrap().keys().collect();
+        assert_eq!(properties, ["crop", "filter", "height", "width"]);
+    }
+
+    #[test]
+    fn test_output_size() {
+        assert_eq!(query_size(10, 20, r#"{}"#), Ok((10, 20)));
+        assert_eq!(query_size(10, 20, r#"{ "width": 5 }"#), Ok((5, 10)));
+        assert_eq!(query_size(10, 20, r#"{ "height": 5 }"#), Ok((3, 5)));
+        assert_eq!(
+            query_size(10, 20, r#"{ "width": 7, "height": 3 }"#),
+            Ok((7, 3))
+        );
+        assert_eq!(
+            query_size(
+                10,
+                20,
+                r#"{ "crop": { "x": 2, "y": 4, "width": 6, "height": 8 } }"#
+            ),
+            Ok((6, 8))
+        );
+    }
+
+    #[test]
+    fn test_output_size_invalid_params() {
+        let invalid = Err(PluginError::InvalidParams as i32);
+        assert_eq!(query_size(10, 20, r#"{ "width": 0 }"#), invalid);
+        assert_eq!(query_size(10, 20, r#"{ "filter": "cubic" }"#), invalid);
+        assert_eq!(
+            query_size(
+                10,
+                20,
+                r#"{ "crop": { "x": 5, "y": 0, "width": 6, "height": 1 } }"#
+            ),
+            invalid
+        );
+    }
+
+    #[test]
+    fn test_invalid_crop_error_message() {
+        let result = query_size(
+            10,
+            20,
+            r#"{ "crop": { "x": 5, "y": 0, "width": 6, "height": 1 } }"#,
+        );
+        assert_eq!(result, Err(PluginError::InvalidParams as i32));
+
+        let message = unsafe { CStr::from_ptr(last_error_message()) };
+        assert_eq!(
+            message.to_str().unwrap(),
+            "crop rectangle 6x1 at (5, 0) does not fit into image 10x20"
+        );
+    }
+
+    #[test]
+    fn test_output_size_null_pointers() {
+        let params = CString::new("{}").unwrap();
+        let mut out = 0;
+        let result = unsafe { output_size(1, 1, params.as_ptr(), std::ptr::null_mut(), &mut out) };
+        assert_eq!(result, PluginError::NullPointer as i32);
+    }
+
+    #[test]
+    fn test_transform_image_null_pointers() {
+        let params = CString::new("{}").unwrap();
+        let mut out = vec![0u8; 4];
+        let result = unsafe {
+            transform_image(
+                1,
+                1,
+                PixelFormat::Rgba8 as u32,
+                std::ptr::null(),
+                1,
+                1,
+                out.as_mut_ptr(),
+                params.as_ptr(),
+                std::ptr::null(),
+            )
+        };
+        assert_eq!(result, PluginError::NullPointer as i32);
+    }
+
+    #[test]
+    fn test_transform_image_wrong_output_size() {
+        let src = create_test_image(4, 4);
+        let mut dst = vec![0u8; 3 * 3 * 4];
+        let params = CString::new(r#"{ "width": 2, "height": 2 }"#).unwrap();
+        let result = unsafe {
+            transform_image(
+                4,
+                4,
+                PixelFormat::Rgba8 as u32,
+                src.as_ptr(),
+                3,
+                3,
+                dst.as_mut_ptr(),
+                params.as_ptr(),
+                std::ptr::null(),
+            )
+        };
+        assert_eq!(result, PluginError::InvalidOutputSize as i32);
+    }
+
+    #[test]
+    fn test_transform_image_unsupported_pixel_format() {
+        let src = [0u16; 2 * 2 * 4];
+        let mut dst = [0u16; 4];
+        let params = CString::new(r#"{ "width": 1, "height": 1 }"#).unwrap();
+        let result = unsafe {
+            transform_image(
+                2,
+                2,
+                PixelFormat::Rgba16 as u32,
+                src.as_ptr().cast(),
+                1,
+                1,
+                dst.as_mut_ptr().cast(),
+                params.as_ptr(),
+                std::ptr::null(),
+            )
+        };
+        assert_eq!(result, PluginError::UnsupportedPixelFormat as i32);
+        assert_eq!(dst, [0; 4]);
+    }
+
+    #[test]
+    fn test_crop() {
+        let src = create_test_image(4, 4);
+        let mut dst = vec![0u8; 2 * 2 * 4];
+        let params =
+            CString::new(r#"{ "crop": { "x": 1, "y": 2, "width": 2, "height": 2 } }"#).unwrap();
+        let result = unsafe {
+            transform_image(
+                4,
+                4,
+                PixelFormat::Rgba8 as u32,
+                src.as_ptr(),
+                2,
+                2,
+                dst.as_mut_ptr(),
+                params.as_ptr(),
+                std::ptr::null(),
+            )
+        };
+
+        assert_eq!(result, PluginError::Ok as i32);
+        let expected = vec![1, 2, 0, 255, 2, 2, 0, 255, 1, 3, 0, 255, 2, 3, 0, 255];
+        assert_eq!(dst, expected);
+    }
+
+    #[test]
+    fn test_resize_nearest_upscale() {
+        let src = create_test_image(2, 1);
+        let mut dst = vec![0u8; 4 * 4];
+        let crop = CropRect {
+            x: 0,
+            y: 0,
+            width: 2,
+            height: 1,
+        };
+        resize_nearest(&src, 2, crop, &mut dst, 4);
+        let expected = vec![0, 0, 0, 255, 0, 0, 0, 255, 1, 0, 0, 255, 1, 0, 0, 255];
+        assert_eq!(dst, expected);
+    }
+
+    #[test]
+    fn test_resize_bilinear_same_size_is_identity() {
+        let src = create_test_image(5, 3);
+        let mut dst = vec![0u8; src.len()];
+        let crop = CropRect {
+            x: 0,
+            y: 0,
+            width: 5,
+            height: 3,
+        };
+        resize_bilinear(&src, 5, crop, &mut dst, 5);
+        assert_eq!(dst, src);
+    }
+
+    #[test]
+    fn test_resize_bilinear_downscale_averages() {
+        let src = vec![0, 0, 0, 255, 100, 100, 100, 255];
+        let mut dst = vec![0u8; 4];
+        let crop = CropRect {
+            x: 0,
+            y: 0,
+            width: 2,
+            height: 1,
+        };
+        resize_bilinear(&src, 2, crop, &mut dst, 1);
+        assert_eq!(dst, vec![50, 50, 50, 255]);
+    }
+}